@@ -0,0 +1,30 @@
+//! Measures the throughput of the instruction decoder.
+#![feature(test)]
+
+extern crate test;
+
+use rivenu::{instruction::decode, RV64I};
+use test::Bencher;
+
+/// A mix of instructions of every encoding variant.
+const INSTRUCTIONS: &[u32] = &[
+    0x0B04_0413, // addi x8, x8, 0xb0
+    0x0109_3403, // ld x8, 16(x18)
+    0x0001_1537, // lui x10, 0x11
+    0x00B7_0723, // sb x11, 14(x14)
+    0x00E6_86B3, // add x13, x13, x14
+    0x0020_873B, // addw x14, x1, x2
+    0x040B_8463, // beq x23, x0, 72
+    0x0100_00EF, // jal ra, 16
+    0x3401_10F3, // csrrw x1, mscratch, x2
+    0x0035_22AF, // amoadd.w x5, x3, (x10)
+];
+
+#[bench]
+fn decode_mix(b: &mut Bencher) {
+    b.iter(|| {
+        for inst in INSTRUCTIONS {
+            test::black_box(decode::<RV64I>(test::black_box(*inst)).unwrap());
+        }
+    });
+}
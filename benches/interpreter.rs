@@ -0,0 +1,54 @@
+//! Compares the throughput of a CPU with hooks and a bare CPU.
+//!
+//! This uses the unstable libtest bencher instead of criterion, because the crate
+//! is built with a nightly toolchain anyway, and it avoids a large dev-dependency.
+#![feature(test)]
+
+extern crate test;
+
+use rivenu::{
+    cpu::{BareCpu, Cpu, Hooks},
+    memory::Memory,
+    RV64I,
+};
+use test::Bencher;
+
+/// Sums up all numbers from 1 to 10000 into `x1`.
+const WORKLOAD: &[u32] = &[
+    0x0000_0093, // addi x1, x0, 0
+    0x0000_2137, // lui x2, 0x2
+    0x7101_0113, // addi x2, x2, 1808
+    0x0020_80B3, // add x1, x1, x2
+    0xFFF1_0113, // addi x2, x2, -1
+    0xFE01_1CE3, // bne x2, x0, -8
+    0x0010_0073, // ebreak
+];
+
+fn memory() -> Memory<RV64I> {
+    let mut memory = Memory::with_size(0x1000);
+    for (idx, inst) in WORKLOAD.iter().enumerate() {
+        memory.write(idx as u64 * 4, *inst);
+    }
+    memory
+}
+
+fn run<H: Hooks<RV64I>>(cpu: &mut Cpu<RV64I, H>) {
+    *cpu.registers_mut().pc_mut() = 0;
+    cpu.run().unwrap();
+}
+
+#[bench]
+fn hooks(b: &mut Bencher) {
+    let mut cpu = Cpu::<RV64I>::new(memory());
+    cpu.set_step_hook(|pc, inst| {
+        test::black_box((pc, inst));
+    });
+
+    b.iter(|| run(&mut cpu));
+}
+
+#[bench]
+fn bare(b: &mut Bencher) {
+    let mut cpu = BareCpu::<RV64I>::new(memory());
+    b.iter(|| run(&mut cpu));
+}
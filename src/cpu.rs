@@ -1,14 +1,1853 @@
 //! Implementation of the actual CPU emulation.
 
+mod config;
+mod ecall;
+mod execute;
+mod hooks;
+mod icache;
 mod register;
+mod snapshot;
+mod trap;
+pub use config::*;
+pub use ecall::*;
+pub use hooks::*;
+pub use icache::CacheStats;
 pub use register::*;
+pub use snapshot::*;
+
+use crate::{
+    dtb,
+    instruction::{self, Instruction, InstructionCategory, Kind},
+    memory::{AccessMode, Memory, Mmu, VirtAddr},
+    trap::{Exception, Interrupt, Trap},
+    Address, Base, EmuError, Extensions, Result,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
 
 /// Specifies the availabe privilege modes that a RISC-V hart
 /// can run in.
-#[derive(Debug, Clone, Copy)]
+///
+/// The discriminant of each variant is the encoding of the mode,
+/// that is used in the `xPP` fields of `mstatus`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PrivilegeMode {
-    User,
-    Supervisor,
-    Reserved,
-    Machine,
+    User = 0,
+    Supervisor = 1,
+    Reserved = 2,
+    Machine = 3,
+}
+
+impl PrivilegeMode {
+    /// Converts the lower two bits of `bits` into a privilege mode.
+    pub(crate) fn from_bits(bits: u64) -> Self {
+        match bits & 0b11 {
+            0 => PrivilegeMode::User,
+            1 => PrivilegeMode::Supervisor,
+            2 => PrivilegeMode::Reserved,
+            _ => PrivilegeMode::Machine,
+        }
+    }
+}
+
+/// The result of a single successful [`Cpu::step`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// An instruction was executed.
+    Retired,
+    /// The given trap was taken, instead of executing an instruction.
+    ///
+    /// Only interrupts are taken automatically. Exceptions are returned
+    /// as an error from [`Cpu::step`], and the embedder decides whether
+    /// to [take](Cpu::take_trap) them.
+    TrapTaken(Trap),
+    /// The CPU stopped for the given reason.
+    Halted(HaltReason),
+    /// The CPU executed a `WFI` instruction, and is idle until an interrupt
+    /// becomes pending in `mip` and `mie`.
+    ///
+    /// It's up to the embedder to decide whether to advance time, or to block
+    /// until an interrupt is asserted.
+    WaitingForInterrupt,
+}
+
+/// The reason why the CPU [halted](StepOutcome::Halted).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HaltReason {
+    /// An `EBREAK` instruction was executed.
+    ///
+    /// The `pc` still points to the `EBREAK` instruction, and no trap is taken,
+    /// which allows a debugger to inspect the CPU.
+    Breakpoint,
+    /// The `pc` reached an address, that was registered using [`Cpu::set_breakpoint`].
+    ///
+    /// The instruction at the `pc` was not executed yet. It's executed by the
+    /// next step, without halting again.
+    BreakpointHit,
+    /// The guest requested to exit with the given code, by writing
+    /// to a device like the [HTIF](crate::device::htif::Htif).
+    ///
+    /// The instruction that wrote to the device was retired.
+    Exit(u64),
+}
+
+/// The reason why [`Cpu::run`] stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunOutcome {
+    /// The CPU halted for the given reason.
+    Halted(HaltReason),
+    /// The CPU waits for an interrupt.
+    WaitingForInterrupt,
+    /// An `ECALL` instruction raised an environment call exception.
+    ///
+    /// The trap was not taken, so the `pc` still points to the `ECALL` instruction.
+    EnvironmentCall,
+}
+
+/// A [`Cpu`] that doesn't invoke any hooks, which
+/// results in the fastest possible interpreter.
+#[allow(clippy::module_name_repetitions)]
+pub type BareCpu<B> = Cpu<B, NoHooks>;
+
+/// A single RISC-V hart, which executes instructions from it's memory.
+///
+/// The `H` parameter specifies the [`Hooks`] that are invoked while executing
+/// instructions. See [`BareCpu`] for a CPU without any hooks.
+pub struct Cpu<B: Base, H: Hooks<B> = DynHooks<B>> {
+    registers: Registers<B>,
+    memory: Memory<B>,
+    privilege: PrivilegeMode,
+    hooks: H,
+    ecall: Option<Box<dyn EnvironmentCall<B, H>>>,
+    /// The reservation set of the last `LR` instruction.
+    ///
+    /// This is the address of the naturally aligned doubleword, that
+    /// contains the loaded address.
+    reservation: Option<u64>,
+    /// The first and last doubleword, that were written by the last store.
+    ///
+    /// A [`System`](crate::system::System) uses them, to invalidate the
+    /// reservations of the other harts.
+    stored: Option<(u64, u64)>,
+    icache: Option<icache::InstructionCache>,
+    mmu: Mmu<B>,
+    /// Set by the `WFI` instruction, until an interrupt becomes pending.
+    waiting: bool,
+    /// The bits of `mip`, that were set by the interrupt lines of the devices.
+    device_interrupts: u64,
+    /// Set if misaligned loads and stores are split into byte accesses,
+    /// instead of raising an exception.
+    misaligned_access: bool,
+    /// The value for `xtval` of the exception, that was raised by the last step.
+    trap_value: u64,
+    /// The addresses, where the CPU halts before executing the instruction.
+    breakpoints: HashSet<u64>,
+    /// The address of the breakpoint, that halted the last step.
+    halted_at: Option<u64>,
+    /// The number of retired instructions of every kind, if profiling is enabled.
+    profile: Option<HashMap<Kind, u64>>,
+    /// The enabled extensions, which are reported in `misa`. Instructions of
+    /// other extensions are illegal.
+    extensions: Extensions,
+    /// The sum of the modeled costs of all retired instructions,
+    /// if the cycle model is enabled.
+    modeled_cycles: Option<u64>,
+    /// The cost model, that overrides [`Kind::default_cost`].
+    cost_model: Option<Box<dyn Fn(Kind) -> u32>>,
+}
+
+impl<B: Base, H: Hooks<B> + Default> Cpu<B, H> {
+    /// Creates a new `Cpu` that will execute the program inside `memory`,
+    /// starting at address `0`.
+    pub fn new(memory: Memory<B>) -> Self {
+        Self::with_hooks(memory, H::default())
+    }
+
+    /// Creates a new `Cpu` from the given config.
+    ///
+    /// The RAM starts at the base address of the config, where the binary is loaded.
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`EmuError::InvalidConfig`] if the binary or a [blob](CpuConfig::map_blob)
+    /// of the config doesn't fit into the memory, if the memory doesn't end
+    /// inside the address space, if an extension is not an uppercase letter,
+    /// or if the initial privilege mode is reserved.
+    pub fn from_config(config: CpuConfig<B>) -> Result<Self> {
+        let CpuConfig {
+            binary,
+            pc,
+            base,
+            memory_size,
+            init_sp,
+            blobs,
+            extensions,
+            privilege,
+            ..
+        } = config;
+
+        let extensions = Extensions::from_letters(&extensions)
+            .ok_or(EmuError::InvalidConfig("invalid ISA extension"))?;
+        if privilege == PrivilegeMode::Reserved {
+            return Err(EmuError::InvalidConfig("reserved privilege mode"));
+        }
+
+        if !Memory::<B>::is_valid_region(base.to_u64(), memory_size) {
+            return Err(EmuError::InvalidConfig(
+                "memory size exceeds the address space",
+            ));
+        }
+
+        let mut memory = Memory::with_base(base.to_u64(), memory_size);
+        memory
+            .write_bytes(base, &binary)
+            .map_err(|_| EmuError::InvalidConfig("binary doesn't fit into memory"))?;
+        for (addr, data) in blobs {
+            memory
+                .write_bytes(addr, &data)
+                .map_err(|_| EmuError::InvalidConfig("blob doesn't fit into memory"))?;
+        }
+
+        let mut cpu = Self::new(memory);
+        *cpu.registers.pc_mut() = pc;
+        cpu.registers.set_extensions(extensions);
+        cpu.extensions = extensions;
+        cpu.privilege = privilege;
+
+        if init_sp {
+            // A memory that fills the whole address space ends at `1 << XLEN`,
+            // which wraps around to `0`, so the first push goes to the top of the memory.
+            let sp = (base.to_u64() + memory_size as u64) & !0xF & (u64::MAX >> (64 - B::XLEN));
+            cpu.registers
+                .write_x(XRegister::from(2), B::Addr::from_u64(sp));
+        }
+        Ok(cpu)
+    }
+
+    /// Loads the ELF file at `path`, and runs it until it stops.
+    ///
+    /// The CPU is returned afterwards together with the reason why it stopped,
+    /// so it's state can be inspected.
+    ///
+    /// ## Errors
+    ///
+    /// Returns an error if the ELF file couldn't be loaded, or if
+    /// the program raised an exception. See [`Cpu::run`].
+    pub fn run_elf(path: impl AsRef<Path>) -> Result<(Self, RunOutcome)> {
+        let mut cpu = Self::from_config(CpuConfig::from_elf(path)?)?;
+        let outcome = cpu.run()?;
+        Ok((cpu, outcome))
+    }
+}
+
+impl<B: Base, H: Hooks<B>> Cpu<B, H> {
+    /// Creates a new `Cpu` that will execute the program inside `memory`,
+    /// and invokes the given `hooks`.
+    pub fn with_hooks(memory: Memory<B>, hooks: H) -> Self {
+        Self {
+            registers: Registers::new(),
+            memory,
+            privilege: PrivilegeMode::Machine,
+            hooks,
+            ecall: None,
+            reservation: None,
+            stored: None,
+            icache: None,
+            mmu: Mmu::new(),
+            waiting: false,
+            device_interrupts: 0,
+            misaligned_access: false,
+            trap_value: 0,
+            breakpoints: HashSet::new(),
+            halted_at: None,
+            profile: None,
+            extensions: Extensions::default(),
+            modeled_cycles: None,
+            cost_model: None,
+        }
+    }
+
+    /// Returns a reference to the registers of this CPU.
+    pub fn registers(&self) -> &Registers<B> {
+        &self.registers
+    }
+
+    /// Returns a mutable reference to the registers of this CPU.
+    pub fn registers_mut(&mut self) -> &mut Registers<B> {
+        &mut self.registers
+    }
+
+    /// Returns a reference to the memory of this CPU.
+    pub fn memory(&self) -> &Memory<B> {
+        &self.memory
+    }
+
+    /// Returns a mutable reference to the memory of this CPU.
+    pub fn memory_mut(&mut self) -> &mut Memory<B> {
+        &mut self.memory
+    }
+
+    /// Writes a [device tree](crate::dtb) of this CPU, it's memory, and all mapped
+    /// devices to `addr`, and passes it to the program according to the Linux boot
+    /// protocol: `a0` contains the `mhartid`, and `a1` the address of the device tree.
+    ///
+    /// ## Errors
+    ///
+    /// Returns an exception if the device tree doesn't fit into the memory at `addr`.
+    pub fn load_device_tree(&mut self, addr: B::Addr) -> Result<(), Exception> {
+        let fdt = dtb::generate(self.extensions, &self.memory, 1);
+        self.memory.write_bytes(addr, &fdt)?;
+        self.registers.set_boot_arguments(addr);
+        Ok(())
+    }
+
+    /// Returns a reference to the MMU of this CPU.
+    pub fn mmu(&self) -> &Mmu<B> {
+        &self.mmu
+    }
+
+    /// Returns a mutable reference to the MMU of this CPU.
+    pub fn mmu_mut(&mut self) -> &mut Mmu<B> {
+        &mut self.mmu
+    }
+
+    /// Returns the value for `xtval` of the exception, that was raised by the last
+    /// [step](Cpu::step), which should be passed to [`Cpu::take_trap`].
+    ///
+    /// This is the encoding of the instruction for illegal-instruction exceptions,
+    /// the faulting virtual address for address-misaligned, access, and page faults,
+    /// and `0` for every other exception.
+    pub fn trap_value(&self) -> u64 {
+        self.trap_value
+    }
+
+    /// Returns the ISA extensions, that are enabled for this CPU.
+    pub fn extensions(&self) -> Extensions {
+        self.extensions
+    }
+
+    /// Returns the privilege mode the CPU is currently running in.
+    pub fn privilege(&self) -> PrivilegeMode {
+        self.privilege
+    }
+
+    /// Checks if the CPU is waiting for an interrupt, after executing a `WFI` instruction.
+    pub fn is_waiting(&self) -> bool {
+        self.waiting
+    }
+
+    /// Specifies if misaligned loads and stores are supported.
+    ///
+    /// If enabled, a misaligned access is split into byte accesses, which are
+    /// only performed if every byte can be accessed. Otherwise, which is the default,
+    /// an address-misaligned exception is raised. Atomic memory operations
+    /// must always be aligned.
+    pub fn set_misaligned_access(&mut self, enabled: bool) {
+        self.misaligned_access = enabled;
+    }
+
+    /// Sets a breakpoint at the virtual address `addr`.
+    ///
+    /// Before the instruction at `addr` is executed, the CPU [halts](HaltReason::BreakpointHit),
+    /// which also stops [`Cpu::run`]. In contrast to an `EBREAK` instruction, the
+    /// memory of the guest is not modified.
+    pub fn set_breakpoint(&mut self, addr: B::Addr) {
+        self.breakpoints.insert(addr.to_u64());
+    }
+
+    /// Removes the breakpoint at `addr`, and returns whether it was set.
+    pub fn clear_breakpoint(&mut self, addr: B::Addr) -> bool {
+        self.breakpoints.remove(&addr.to_u64())
+    }
+
+    /// Enables counting how often every kind of instruction is retired.
+    ///
+    /// The counts start at zero, and can be read using [`Cpu::profile`].
+    pub fn enable_profiling(&mut self) {
+        self.profile.get_or_insert_with(HashMap::new);
+    }
+
+    /// Returns the number of retired instructions of every kind, since
+    /// [profiling](Cpu::enable_profiling) was enabled.
+    ///
+    /// Kinds that were never retired are missing, so the profile is
+    /// empty if profiling is disabled.
+    pub fn profile(&self) -> HashMap<Kind, u64> {
+        self.profile.clone().unwrap_or_default()
+    }
+
+    /// Enables summing up the modeled cycles of every retired instruction.
+    ///
+    /// The sum starts at zero, and can be read using [`Cpu::modeled_cycles`].
+    pub fn enable_cycle_model(&mut self) {
+        self.modeled_cycles.get_or_insert(0);
+    }
+
+    /// Returns the sum of the modeled cycles of all retired instructions, since
+    /// the [cycle model](Cpu::enable_cycle_model) was enabled, or `0` if it's disabled.
+    ///
+    /// This is not cycle-accurate, but can be used to compare the relative
+    /// performance of programs. The cost of every instruction is given by
+    /// [`Kind::default_cost`], unless a [cost model](Cpu::set_cost_model) is set.
+    pub fn modeled_cycles(&self) -> u64 {
+        self.modeled_cycles.unwrap_or(0)
+    }
+
+    /// Sets the function, that returns the number of modeled cycles of an instruction,
+    /// and enables the [cycle model](Cpu::enable_cycle_model).
+    pub fn set_cost_model(&mut self, model: impl Fn(Kind) -> u32 + 'static) {
+        self.cost_model = Some(Box::new(model));
+        self.enable_cycle_model();
+    }
+
+    /// Returns a mutable reference to the hooks of this CPU.
+    pub fn hooks_mut(&mut self) -> &mut H {
+        &mut self.hooks
+    }
+
+    /// Fetches, decodes and executes a single instruction.
+    ///
+    /// The interrupt lines of all devices are sampled first, and if an interrupt
+    /// is pending, it's taken instead of executing an instruction.
+    /// See [`Cpu::check_interrupts`].
+    ///
+    /// While the CPU [is waiting](Cpu::is_waiting) for an interrupt, nothing is executed
+    /// and [`StepOutcome::WaitingForInterrupt`] is returned, until an interrupt is
+    /// pending in `mip` and enabled in `mie`.
+    ///
+    /// ## Errors
+    ///
+    /// Returns the exception that was raised while executing the instruction,
+    /// except for breakpoints, which [halt](StepOutcome::Halted) the CPU.
+    /// The CPU also halts, if a store requested an exit through a device.
+    pub fn step(&mut self) -> Result<StepOutcome, Exception> {
+        self.trap_value = 0;
+        let resumed = self.halted_at.take();
+        self.update_device_interrupts();
+
+        if self.waiting {
+            if !self.interrupt_pending() {
+                return Ok(StepOutcome::WaitingForInterrupt);
+            }
+            self.waiting = false;
+        }
+
+        if let Some(int) = self.check_interrupts() {
+            self.take_trap(int.into(), 0);
+            return Ok(StepOutcome::TrapTaken(int.into()));
+        }
+
+        // The set is only hashed if there are breakpoints, so a CPU
+        // without breakpoints doesn't pay for the lookup.
+        let pc = self.registers.pc();
+        if !self.breakpoints.is_empty()
+            && resumed != Some(pc.to_u64())
+            && self.breakpoints.contains(&pc.to_u64())
+        {
+            self.halted_at = Some(pc.to_u64());
+            return Ok(StepOutcome::Halted(HaltReason::BreakpointHit));
+        }
+
+        let raw = self.fetch(pc)?;
+        let inst = match self.decode(pc.to_u64(), raw) {
+            Ok(inst) => inst,
+            Err(err) => {
+                self.trap_value = u64::from(raw);
+                return Err(err);
+            }
+        };
+
+        if H::ENABLED {
+            self.hooks.before_execute(pc, &inst);
+        }
+
+        let res = self.execute(&inst);
+        match res {
+            Err(Exception::IllegalInstruction) => {
+                self.reservation = None;
+                self.trap_value = u64::from(raw);
+            }
+            Err(_) => self.reservation = None,
+            Ok(()) => {}
+        }
+        self.registers.increment_counters(res.is_ok());
+        if let (Ok(()), Some(cycles)) = (&res, &mut self.modeled_cycles) {
+            let cost = match &self.cost_model {
+                Some(model) => model(inst.kind),
+                None => inst.kind.default_cost(),
+            };
+            *cycles = cycles.wrapping_add(u64::from(cost));
+        }
+        if let (Ok(()), Some(profile)) = (&res, &mut self.profile) {
+            *profile.entry(inst.kind).or_insert(0) += 1;
+        }
+        match res {
+            Ok(()) if self.waiting => Ok(StepOutcome::WaitingForInterrupt),
+            Ok(())
+                if matches!(
+                    inst.kind.category(),
+                    InstructionCategory::Store | InstructionCategory::Atomic
+                ) =>
+            {
+                Ok(match self.memory.take_exit_request() {
+                    Some(code) => StepOutcome::Halted(HaltReason::Exit(code)),
+                    None => StepOutcome::Retired,
+                })
+            }
+            Ok(()) => Ok(StepOutcome::Retired),
+            Err(Exception::Breakpoint) => Ok(StepOutcome::Halted(HaltReason::Breakpoint)),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Executes instructions until an `ECALL` or `EBREAK` instruction
+    /// is executed, a [breakpoint](Cpu::set_breakpoint) is hit, or the guest
+    /// requested to exit.
+    ///
+    /// The execution also stops if the CPU waits for an interrupt, because
+    /// there's nothing that could make an interrupt pending in the meantime.
+    /// The reason why the execution stopped is returned, which includes the
+    /// exit code if the guest requested to exit.
+    ///
+    /// ## Errors
+    ///
+    /// Any other exception that is raised stops the execution, and is returned
+    /// as an [`EmuError::Trap`].
+    pub fn run(&mut self) -> Result<RunOutcome> {
+        loop {
+            match self.step() {
+                Ok(StepOutcome::Halted(reason)) => return Ok(RunOutcome::Halted(reason)),
+                Ok(StepOutcome::WaitingForInterrupt) => return Ok(RunOutcome::WaitingForInterrupt),
+                Err(Exception::UserModeEnvironmentCall)
+                | Err(Exception::SupervisorModeEnvironmentCall)
+                | Err(Exception::MachineModeEnvironmentCall) => {
+                    return Ok(RunOutcome::EnvironmentCall)
+                }
+                Ok(_) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Executes at most `limit` steps, until the CPU halts or waits for an interrupt.
+    ///
+    /// Unlike [`Cpu::run`], every exception is [taken](Cpu::take_trap), so the guest
+    /// handles it's own environment calls and faults, like a test environment does.
+    /// Returns `None` if the CPU didn't stop after `limit` steps.
+    pub fn run_until_halt(&mut self, limit: u64) -> Option<RunOutcome> {
+        for _ in 0..limit {
+            match self.step() {
+                Ok(StepOutcome::Halted(reason)) => return Some(RunOutcome::Halted(reason)),
+                Ok(StepOutcome::WaitingForInterrupt) => {
+                    return Some(RunOutcome::WaitingForInterrupt)
+                }
+                Ok(_) => {}
+                Err(err) => self.take_trap(err.into(), self.trap_value),
+            }
+        }
+        None
+    }
+
+    /// Steps through instructions while the `pc` stays inside `start..end`.
+    ///
+    /// At least one instruction is always executed. This is used to implement
+    /// range stepping for debuggers. Stepping also stops if the CPU halted,
+    /// or is waiting for an interrupt. The outcome of the last step is returned.
+    ///
+    /// ## Errors
+    ///
+    /// Stops and returns the exception if one was raised while stepping.
+    pub fn range_step(&mut self, start: B::Addr, end: B::Addr) -> Result<StepOutcome, Exception> {
+        let range = start.to_u64()..end.to_u64();
+
+        loop {
+            let outcome = self.step()?;
+
+            if let StepOutcome::Halted(_) | StepOutcome::WaitingForInterrupt = outcome {
+                return Ok(outcome);
+            }
+            if !range.contains(&self.registers.pc().to_u64()) {
+                return Ok(outcome);
+            }
+        }
+    }
+
+    /// Decodes the instruction at `pc`, or looks it up in the instruction cache.
+    fn decode(&mut self, pc: u64, raw: u32) -> Result<Instruction, Exception> {
+        if let Some(inst) = self.icache.as_mut().and_then(|cache| cache.get(pc, raw)) {
+            return Ok(inst);
+        }
+
+        let inst = instruction::decode_with_extensions::<B>(raw, self.extensions)
+            .map_err(|_| Exception::IllegalInstruction)?;
+        if let Some(cache) = &mut self.icache {
+            cache.insert(pc, inst);
+        }
+        Ok(inst)
+    }
+
+    /// Returns the alignment of instruction addresses, which is
+    /// 2 bytes if the C extension is enabled, and 4 bytes otherwise.
+    fn instruction_alignment(&self) -> u64 {
+        if self.registers.has_extension('C') {
+            2
+        } else {
+            4
+        }
+    }
+
+    /// Fetches the instruction at `pc`.
+    ///
+    /// The instruction is read in halfwords, so an instruction that crosses
+    /// a page boundary is read using two separate reads. A compressed
+    /// instruction is returned as the lower 16 bits.
+    ///
+    /// Instructions are always stored as little endian parcels, independent
+    /// of the endianness of the host, and of the data accesses.
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`Exception::InstructionAddressMisaligned`] if `pc` is not aligned,
+    /// and [`Exception::InstructionAccessFault`] or [`Exception::InstructionPageFault`]
+    /// if the instruction can't be read. The faulting address is [recorded](Cpu::trap_value).
+    pub fn fetch(&mut self, pc: B::Addr) -> Result<u32, Exception> {
+        let addr = pc.to_u64();
+
+        if addr % self.instruction_alignment() != 0 {
+            return Err(self.address_fault(addr, Exception::InstructionAddressMisaligned));
+        }
+
+        let low = self.fetch_half(addr)?;
+        match instruction::instruction_width(low) {
+            Some(2) => Ok(u32::from(low)),
+            Some(4) => {
+                let high = self.fetch_half(pc.wrapping_add(B::Addr::from_u64(2)).to_u64())?;
+                Ok(u32::from(high) << 16 | u32::from(low))
+            }
+            _ => {
+                self.trap_value = u64::from(low);
+                Err(Exception::IllegalInstruction)
+            }
+        }
+    }
+
+    /// Reads the little endian halfword of an instruction at the virtual address `addr`.
+    fn fetch_half(&mut self, addr: u64) -> Result<u16, Exception> {
+        let phys = self.translate(addr, AccessMode::Execute)?;
+        self.memory
+            .read_int(phys, 2, false)
+            .map(|half| half.to_u64() as u16)
+            .map_err(|_| self.address_fault(addr, Exception::InstructionAccessFault))
+    }
+
+    /// Records the virtual address `addr` as the faulting address of `err`,
+    /// which is [passed](Cpu::trap_value) to the trap handler in `xtval`.
+    pub(super) fn address_fault(&mut self, addr: u64, err: Exception) -> Exception {
+        self.trap_value = addr;
+        err
+    }
+
+    /// Translates the virtual address `addr` into a physical address, using the MMU.
+    ///
+    /// Addresses are not translated in machine mode. If `mstatus.MPRV` is set,
+    /// loads and stores use the privilege mode in `mstatus.MPP` instead of the
+    /// current one.
+    pub(super) fn translate(
+        &mut self,
+        addr: u64,
+        access: AccessMode,
+    ) -> Result<B::Addr, Exception> {
+        let status = self.registers.mstatus();
+        let privilege = match access {
+            AccessMode::Read | AccessMode::Write if status.mprv() => status.mpp(),
+            _ => self.privilege,
+        };
+
+        let phys = if privilege == PrivilegeMode::Machine {
+            addr
+        } else {
+            self.mmu
+                .translate_addr(
+                    &self.registers,
+                    privilege,
+                    &mut self.memory,
+                    VirtAddr::from(addr),
+                    access,
+                )
+                .map_err(|err| self.address_fault(addr, err))?
+                .as_u64()
+        };
+
+        // Physical addresses may be wider than `XLEN`, but the memory never is.
+        if phys > u64::MAX >> (64 - B::XLEN) {
+            return Err(self.address_fault(addr, access.access_fault()));
+        }
+        Ok(B::Addr::from_u64(phys))
+    }
+}
+
+impl<B: Base> Cpu<B> {
+    /// Sets a hook that is called before every instruction gets executed.
+    ///
+    /// The hook receives the `pc` and the decoded instruction, which makes it
+    /// usable for printing a disassembly trace. It is not called if fetching
+    /// or decoding the instruction faults.
+    pub fn set_step_hook(&mut self, hook: impl FnMut(B::Addr, &Instruction) + 'static) {
+        self.hooks.step = Some(Box::new(hook));
+    }
+
+    /// Sets a hook that is called if a device asserts an interrupt line.
+    ///
+    /// The hook can either remap the interrupt to a different one,
+    /// or drop it by returning `None`.
+    pub fn set_interrupt_hook(
+        &mut self,
+        hook: impl FnMut(&str, u32, Interrupt) -> Option<Interrupt> + 'static,
+    ) {
+        self.hooks.interrupt = Some(Box::new(hook));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        csr, BareCpu, Cpu, CpuConfig, HaltReason, Hooks, PrivilegeMode, RunOutcome, StepOutcome,
+    };
+    use crate::{
+        cpu::XRegister,
+        instruction::Kind,
+        memory::{AccessMode, Memory, VirtAddr},
+        trap::{Exception, Interrupt, Trap},
+        Address, Base, EmuError, RV32I, RV64I,
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    /// Sums up all numbers from 1 to 1000 into `x1`.
+    const SUM_LOOP: &[u32] = &[
+        0x0000_0093, // addi x1, x0, 0
+        0x3E80_0113, // addi x2, x0, 1000
+        0x0020_80B3, // add x1, x1, x2
+        0xFFF1_0113, // addi x2, x2, -1
+        0xFE01_1CE3, // bne x2, x0, -8
+        0x0010_0073, // ebreak
+    ];
+
+    fn memory<B: Base>(program: &[u32]) -> Memory<B> {
+        let mut memory = Memory::with_size(0x1000);
+        for (idx, inst) in program.iter().enumerate() {
+            memory.write(B::Addr::from_u64(idx as u64 * 4), *inst);
+        }
+        memory
+    }
+
+    /// Executes the single instruction `raw`, with the operands `a` and `b`
+    /// inside `x11` and `x12`, and returns the result inside `x10`.
+    fn execute<B: Base>(raw: u32, a: u64, b: u64) -> u64 {
+        let mut cpu = Cpu::<B>::new(memory(&[raw]));
+        cpu.registers()
+            .write_x(XRegister::from(11), B::Addr::from_u64(a));
+        cpu.registers()
+            .write_x(XRegister::from(12), B::Addr::from_u64(b));
+        cpu.step().unwrap();
+        cpu.registers().read_x(XRegister::from(10)).to_u64()
+    }
+
+    fn run_sum_loop<B: Base, H: Hooks<B> + Default>() {
+        let mut cpu = Cpu::<B, H>::new(memory(SUM_LOOP));
+
+        cpu.run().unwrap();
+        assert_eq!(cpu.registers().read_x(XRegister::from(1)).to_u64(), 500_500);
+        assert_eq!(cpu.registers().pc().to_u64(), 20);
+    }
+
+    #[test]
+    fn run_program() {
+        run_sum_loop::<RV32I, super::DynHooks<RV32I>>();
+        run_sum_loop::<RV64I, super::DynHooks<RV64I>>();
+        run_sum_loop::<RV32I, super::NoHooks>();
+        run_sum_loop::<RV64I, super::NoHooks>();
+    }
+
+    #[test]
+    fn step_hook() {
+        let pcs = Rc::new(RefCell::new(Vec::new()));
+        let mut cpu = Cpu::<RV64I>::new(memory(&SUM_LOOP[..2]));
+
+        let hook_pcs = Rc::clone(&pcs);
+        cpu.set_step_hook(move |pc, _| hook_pcs.borrow_mut().push(pc));
+
+        assert!(matches!(
+            cpu.run(),
+            Err(EmuError::Trap(Exception::IllegalInstruction))
+        ));
+        assert_eq!(*pcs.borrow(), vec![0, 4]);
+    }
+
+    #[test]
+    fn range_step() {
+        let mut cpu = Cpu::<RV64I>::new(memory(SUM_LOOP));
+
+        cpu.range_step(0, 8).unwrap();
+        assert_eq!(cpu.registers().pc(), 8);
+        assert_eq!(cpu.registers().read_x(XRegister::from(2)), 1000);
+
+        *cpu.registers_mut().pc_mut() = 0;
+        assert_eq!(
+            cpu.range_step(0, 0x100),
+            Ok(StepOutcome::Halted(HaltReason::Breakpoint))
+        );
+        assert_eq!(cpu.registers().pc(), 20);
+    }
+
+    #[test]
+    fn csr_instructions() {
+        let mut cpu = Cpu::<RV64I>::new(memory(&[
+            0x3401_10F3, // csrrw x1, mscratch, x2
+            0x3400_21F3, // csrrs x3, mscratch, x0
+            0x3402_3073, // csrrc x0, mscratch, x4
+            0x3402_D373, // csrrwi x6, mscratch, 5
+            0x3401_63F3, // csrrsi x7, mscratch, 2
+            0x3400_7473, // csrrci x8, mscratch, 0
+            0xF140_22F3, // csrrs x5, mhartid, x0
+            0xF140_A073, // csrrs x0, mhartid, x1
+        ]));
+
+        let regs = cpu.registers();
+        regs.write_csr(csr::MSCRATCH, 0xF0);
+        regs.write_x(XRegister::from(2), 0xAB);
+        regs.write_x(XRegister::from(4), 0x0F);
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().read_x(XRegister::from(1)), 0xF0);
+        assert_eq!(cpu.registers().read_csr(csr::MSCRATCH), 0xAB);
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().read_x(XRegister::from(3)), 0xAB);
+        assert_eq!(cpu.registers().read_csr(csr::MSCRATCH), 0xAB);
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().read_csr(csr::MSCRATCH), 0xA0);
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().read_x(XRegister::from(6)), 0xA0);
+        assert_eq!(cpu.registers().read_csr(csr::MSCRATCH), 5);
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().read_x(XRegister::from(7)), 5);
+        assert_eq!(cpu.registers().read_csr(csr::MSCRATCH), 7);
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().read_x(XRegister::from(8)), 7);
+        assert_eq!(cpu.registers().read_csr(csr::MSCRATCH), 7);
+
+        // Reading a read-only CSR is fine, as long as it's not written.
+        cpu.step().unwrap();
+        assert_eq!(cpu.step(), Err(Exception::IllegalInstruction));
+
+        // Machine mode CSRs can't be accessed from lower privilege modes.
+        cpu.privilege = PrivilegeMode::User;
+        *cpu.registers_mut().pc_mut() = 4;
+        assert_eq!(cpu.step(), Err(Exception::IllegalInstruction));
+        assert_eq!(cpu.registers().read_x(XRegister::from(3)), 0xAB);
+    }
+
+    #[test]
+    fn counters() {
+        let mut cpu = Cpu::<RV32I>::new(memory(&[
+            0x0000_0013, // nop
+            0x0000_0013, // nop
+            0x0000_0013, // nop
+            0xC020_20F3, // rdinstret x1
+            0xC000_2173, // rdcycle x2
+            0xC800_21F3, // rdcycleh x3
+        ]));
+
+        for _ in 0..6 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.registers().read_x(XRegister::from(1)), 3);
+        assert_eq!(cpu.registers().read_x(XRegister::from(2)), 4);
+        assert_eq!(cpu.registers().read_x(XRegister::from(3)), 0);
+        assert_eq!(cpu.registers().read_csr(csr::MINSTRET), 6);
+
+        // Only an upper half of the counter is written.
+        cpu.registers().write_csr(csr::MCYCLEH, 1);
+        assert_eq!(cpu.registers().read_csr(csr::CYCLE), 6);
+        assert_eq!(cpu.registers().read_csr(csr::CYCLEH), 1);
+
+        // The counters are not accessible from user mode, until enabled.
+        cpu.privilege = PrivilegeMode::User;
+        *cpu.registers_mut().pc_mut() = 12;
+        assert_eq!(cpu.step(), Err(Exception::IllegalInstruction));
+
+        cpu.registers().write_csr(csr::MCOUNTEREN, 0b100);
+        cpu.registers().write_csr(csr::SCOUNTEREN, 0b100);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().read_x(XRegister::from(1)), 6);
+
+        // User mode needs both enable bits, while supervisor mode only needs `mcounteren`.
+        cpu.registers().write_csr(csr::MCOUNTEREN, 0b101);
+        assert_eq!(cpu.step(), Err(Exception::IllegalInstruction));
+        cpu.privilege = PrivilegeMode::Supervisor;
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().read_x(XRegister::from(2)), 9);
+
+        cpu.privilege = PrivilegeMode::User;
+        cpu.registers().write_csr(csr::SCOUNTEREN, 0b001);
+        *cpu.registers_mut().pc_mut() = 16;
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().read_x(XRegister::from(2)), 10);
+    }
+
+    #[test]
+    fn hints() {
+        let mut cpu = Cpu::<RV64I>::new(memory(&[
+            0x0020_0033, // ntl.p1
+            0x0100_000F, // pause
+        ]));
+
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().pc(), 8);
+    }
+
+    #[test]
+    fn upper_immediates() {
+        let program = &[
+            0xFFFF_E0B7, // lui x1, 0xffffe
+            0x8000_0117, // auipc x2, 0x80000
+        ];
+
+        let mut cpu = Cpu::<RV64I>::new(memory(program));
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(
+            cpu.registers().read_x(XRegister::from(1)),
+            0xFFFF_FFFF_FFFF_E000
+        );
+        assert_eq!(
+            cpu.registers().read_x(XRegister::from(2)),
+            0xFFFF_FFFF_8000_0004
+        );
+
+        let mut cpu = Cpu::<RV32I>::new(memory(program));
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().read_x(XRegister::from(1)), 0xFFFF_E000);
+        assert_eq!(cpu.registers().read_x(XRegister::from(2)), 0x8000_0004);
+    }
+
+    #[test]
+    fn multiplication() {
+        const MUL: u32 = 0x02C5_8533; // mul a0, a1, a2
+        const MULW: u32 = 0x02C5_853B; // mulw a0, a1, a2
+
+        assert_eq!(execute::<RV64I>(MUL, 0x1_0000_0001, 3), 0x3_0000_0003);
+        assert_eq!(execute::<RV64I>(MUL, -2_i64 as u64, 3), -6_i64 as u64);
+        assert_eq!(execute::<RV32I>(MUL, 0x8000_0001, 2), 2);
+        assert_eq!(execute::<RV64I>(MULW, 0xFFFF, 0x1_0001), u64::MAX);
+    }
+
+    #[test]
+    fn high_multiplication() {
+        const MULH: u32 = 0x02C5_9533; // mulh a0, a1, a2
+        const MULHSU: u32 = 0x02C5_A533; // mulhsu a0, a1, a2
+        const MULHU: u32 = 0x02C5_B533; // mulhu a0, a1, a2
+
+        // -1 * -1 = 1
+        assert_eq!(execute::<RV64I>(MULH, u64::MAX, u64::MAX), 0);
+        assert_eq!(execute::<RV32I>(MULH, 0xFFFF_FFFF, 0xFFFF_FFFF), 0);
+        // -2^63 * -2^63 = 2^126
+        let min = i64::MIN as u64;
+        assert_eq!(execute::<RV64I>(MULH, min, min), 1 << 62);
+        assert_eq!(execute::<RV32I>(MULH, 0x8000_0000, 0x8000_0000), 1 << 30);
+        // -2 * 3 = -6
+        assert_eq!(execute::<RV64I>(MULH, -2_i64 as u64, 3), u64::MAX);
+
+        // (2^64 - 1)^2 = 2^128 - 2^65 + 1
+        assert_eq!(execute::<RV64I>(MULHU, u64::MAX, u64::MAX), u64::MAX - 1);
+        assert_eq!(
+            execute::<RV32I>(MULHU, 0xFFFF_FFFF, 0xFFFF_FFFF),
+            0xFFFF_FFFE
+        );
+        assert_eq!(execute::<RV64I>(MULHU, 1 << 32, 1 << 40), 1 << 8);
+
+        // -1 * (2^64 - 1) = -2^64 + 1
+        assert_eq!(execute::<RV64I>(MULHSU, u64::MAX, u64::MAX), u64::MAX);
+        assert_eq!(
+            execute::<RV32I>(MULHSU, 0xFFFF_FFFF, 0xFFFF_FFFF),
+            0xFFFF_FFFF
+        );
+        // -2 * (2^63 + 1) = -2^64 - 2
+        assert_eq!(
+            execute::<RV64I>(MULHSU, -2_i64 as u64, (1 << 63) + 1),
+            -2_i64 as u64
+        );
+        assert_eq!(
+            execute::<RV32I>(MULHSU, 0xFFFF_FFFE, 0x8000_0001),
+            0xFFFF_FFFE
+        );
+        // The product is positive, if `rs1` is positive.
+        assert_eq!(execute::<RV64I>(MULHSU, 2, u64::MAX), 1);
+    }
+
+    #[test]
+    fn division() {
+        const DIV: u32 = 0x02C5_C533; // div a0, a1, a2
+        const DIVU: u32 = 0x02C5_D533; // divu a0, a1, a2
+        const REM: u32 = 0x02C5_E533; // rem a0, a1, a2
+        const REMU: u32 = 0x02C5_F533; // remu a0, a1, a2
+
+        assert_eq!(execute::<RV64I>(DIV, -7_i64 as u64, 2), -3_i64 as u64);
+        assert_eq!(execute::<RV64I>(REM, -7_i64 as u64, 2), -1_i64 as u64);
+        assert_eq!(execute::<RV64I>(DIVU, -7_i64 as u64, 2), u64::MAX / 2 - 3);
+        assert_eq!(execute::<RV32I>(DIV, 0xFFFF_FFF9, 2), 0xFFFF_FFFD);
+        assert_eq!(execute::<RV32I>(REMU, 0xFFFF_FFF9, 2), 1);
+
+        // A division by zero returns all ones, and the remainder is the dividend.
+        assert_eq!(execute::<RV64I>(DIV, 7, 0), u64::MAX);
+        assert_eq!(execute::<RV64I>(DIVU, 7, 0), u64::MAX);
+        assert_eq!(execute::<RV64I>(REM, -7_i64 as u64, 0), -7_i64 as u64);
+        assert_eq!(execute::<RV64I>(REMU, 7, 0), 7);
+        assert_eq!(execute::<RV32I>(DIV, 7, 0), 0xFFFF_FFFF);
+        assert_eq!(execute::<RV32I>(DIVU, 7, 0), 0xFFFF_FFFF);
+        assert_eq!(execute::<RV32I>(REM, 0xFFFF_FFF9, 0), 0xFFFF_FFF9);
+        assert_eq!(execute::<RV32I>(REMU, 7, 0), 7);
+
+        // The signed overflow returns the dividend, and a remainder of zero.
+        let min = i64::MIN as u64;
+        assert_eq!(execute::<RV64I>(DIV, min, u64::MAX), min);
+        assert_eq!(execute::<RV64I>(REM, min, u64::MAX), 0);
+        assert_eq!(execute::<RV32I>(DIV, 0x8000_0000, 0xFFFF_FFFF), 0x8000_0000);
+        assert_eq!(execute::<RV32I>(REM, 0x8000_0000, 0xFFFF_FFFF), 0);
+    }
+
+    #[test]
+    fn word_division() {
+        const DIVW: u32 = 0x02C5_C53B; // divw a0, a1, a2
+        const DIVUW: u32 = 0x02C5_D53B; // divuw a0, a1, a2
+        const REMW: u32 = 0x02C5_E53B; // remw a0, a1, a2
+        const REMUW: u32 = 0x02C5_F53B; // remuw a0, a1, a2
+
+        // Only the lower words are used, and the results are sign extended.
+        assert_eq!(execute::<RV64I>(DIVW, 0x1_FFFF_FFF9, 2), -3_i64 as u64);
+        assert_eq!(execute::<RV64I>(DIVUW, 0x1_FFFF_FFFE, 1), u64::MAX - 1);
+        assert_eq!(execute::<RV64I>(REMW, 0x1_FFFF_FFF9, 2), u64::MAX);
+        assert_eq!(execute::<RV64I>(REMUW, 0x1_0000_0007, 4), 3);
+
+        assert_eq!(execute::<RV64I>(DIVW, 7, 0x1_0000_0000), u64::MAX);
+        assert_eq!(execute::<RV64I>(DIVUW, 7, 0), u64::MAX);
+        assert_eq!(
+            execute::<RV64I>(REMW, 0x1_8000_0000, 0),
+            0xFFFF_FFFF_8000_0000
+        );
+        assert_eq!(
+            execute::<RV64I>(REMUW, 0x1_8000_0000, 0),
+            0xFFFF_FFFF_8000_0000
+        );
+
+        assert_eq!(
+            execute::<RV64I>(DIVW, 0x8000_0000, 0xFFFF_FFFF),
+            0xFFFF_FFFF_8000_0000
+        );
+        assert_eq!(execute::<RV64I>(REMW, 0x8000_0000, 0xFFFF_FFFF), 0);
+    }
+
+    #[test]
+    fn init_sp() {
+        let mut config = CpuConfig::raw(vec![]);
+        *config.memory_size_mut() = 0x1234;
+        let cpu = Cpu::<RV64I>::from_config(config.clone()).unwrap();
+        assert_eq!(cpu.registers().read_x(XRegister::from(2)), 0x1230);
+
+        *config.init_sp_mut() = false;
+        let cpu = Cpu::<RV64I>::from_config(config).unwrap();
+        assert_eq!(cpu.registers().read_x(XRegister::from(2)), 0);
+    }
+
+    #[test]
+    fn init_sp_full_address_space() {
+        let config = CpuConfig::builder().memory_size(1 << 32).build();
+        let cpu = Cpu::<RV32I>::from_config(config).unwrap();
+        assert_eq!(cpu.registers().read_x(XRegister::from(2)), 0);
+    }
+
+    #[test]
+    fn config_base() {
+        let config = CpuConfig::builder()
+            .binary(vec![0x93, 0x00, 0xA0, 0x02, 0x73, 0x00, 0x10, 0x00])
+            .base(0x100)
+            .pc(0x100)
+            .memory_size(0x200)
+            .build();
+        let mut cpu = Cpu::<RV32I>::from_config(config).unwrap();
+        cpu.run().unwrap();
+        assert_eq!(cpu.registers().read_x(XRegister::from(1)), 42);
+
+        let config = CpuConfig::builder()
+            .binary(vec![0; 0x201])
+            .base(0x100)
+            .memory_size(0x200)
+            .build();
+        assert!(matches!(
+            Cpu::<RV32I>::from_config(config),
+            Err(EmuError::InvalidConfig(_))
+        ));
+
+        let config = CpuConfig::builder().memory_size(1 << 33).build();
+        assert!(matches!(
+            Cpu::<RV32I>::from_config(config),
+            Err(EmuError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn config_blobs() {
+        let mut config = CpuConfig::builder()
+            .binary(vec![0x13, 0, 0, 0])
+            .memory_size(0x200)
+            .build();
+        config.map_blob(0x100, b"dtb".to_vec());
+        config.map_blob(0x1FE, vec![0xAA, 0xBB]);
+
+        let cpu = Cpu::<RV64I>::from_config(config.clone()).unwrap();
+        assert_eq!(cpu.memory().read_bytes(0x100, 3), Ok(b"dtb".to_vec()));
+        assert_eq!(cpu.memory().read_bytes(0x1FE, 2), Ok(vec![0xAA, 0xBB]));
+        assert_eq!(cpu.memory().read::<u32>(0), 0x13);
+
+        config.map_blob(0x1FF, vec![0; 2]);
+        assert!(matches!(
+            Cpu::<RV64I>::from_config(config),
+            Err(EmuError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn run_errors() {
+        let mut config = CpuConfig::raw(vec![0; 0x20]);
+        *config.memory_size_mut() = 0x10;
+        assert!(matches!(
+            Cpu::<RV64I>::from_config(config),
+            Err(EmuError::InvalidConfig(_))
+        ));
+
+        let mut cpu = Cpu::<RV64I>::from_config(CpuConfig::raw(vec![0; 4])).unwrap();
+        assert!(matches!(
+            cpu.run(),
+            Err(EmuError::Trap(Exception::IllegalInstruction))
+        ));
+
+        assert!(matches!(
+            Cpu::<RV64I>::run_elf("does/not/exist.elf"),
+            Err(EmuError::Elf(_))
+        ));
+    }
+
+    #[test]
+    fn run_elf() {
+        let elf = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/tiny.elf");
+        let (cpu, outcome) = Cpu::<RV64I>::run_elf(elf).unwrap();
+        assert_eq!(outcome, RunOutcome::Halted(HaltReason::Breakpoint));
+        assert_eq!(cpu.registers().read_x(XRegister::from(1)), 42);
+        assert_eq!(cpu.registers().pc(), 0x104);
+    }
+
+    #[test]
+    fn bare_cpu() {
+        let mut cpu = BareCpu::<RV32I>::new(memory(&[0xFFF0_0093])); // addi x1, x0, -1
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().read_x(XRegister::from(1)), 0xFFFF_FFFF);
+    }
+
+    #[test]
+    fn config_machine() {
+        let config = CpuConfig::builder()
+            .binary(0x02A0_0093_u32.to_le_bytes().to_vec()) // addi x1, x0, 42
+            .memory_size(0x1000)
+            .base(0x100)
+            .pc(0x100)
+            .extensions(vec!['I', 'U'])
+            .privilege(PrivilegeMode::User)
+            .build();
+
+        let mut cpu = Cpu::<RV64I>::from_config(config).unwrap();
+        assert_eq!(cpu.privilege(), PrivilegeMode::User);
+        assert_eq!(cpu.memory().size(), 0x1000);
+        assert!(cpu.registers().has_extension('U'));
+        assert!(!cpu.registers().has_extension('S'));
+        assert!(cpu.extensions().contains('U'));
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().read_x(XRegister::from(1)), 42);
+        assert_eq!(cpu.registers().pc(), 0x104);
+
+        let invalid = |config: CpuConfig<RV32I>| {
+            matches!(
+                Cpu::<RV32I>::from_config(config),
+                Err(EmuError::InvalidConfig(_))
+            )
+        };
+        assert!(invalid(CpuConfig::builder().extensions(vec!['i']).build()));
+        assert!(invalid(
+            CpuConfig::builder()
+                .privilege(PrivilegeMode::Reserved)
+                .build()
+        ));
+    }
+
+    #[test]
+    fn new_cpu() {
+        fn check<B: Base>() {
+            let cpu = Cpu::<B>::new(Memory::with_size(0x100));
+            assert_eq!(cpu.privilege(), PrivilegeMode::Machine);
+            assert_eq!(cpu.registers().pc().to_u64(), 0);
+            assert_eq!(
+                cpu.registers().read_csr(csr::MISA).to_u64() >> (B::XLEN - 2),
+                if B::XLEN == 32 { 1 } else { 2 }
+            );
+        }
+
+        check::<RV32I>();
+        check::<RV64I>();
+    }
+
+    #[test]
+    fn trace() {
+        let trace = Rc::new(RefCell::new(Vec::new()));
+        let mut cpu = Cpu::<RV32I>::new(memory(&SUM_LOOP[..2]));
+
+        let hook_trace = Rc::clone(&trace);
+        cpu.set_step_hook(move |pc, inst| {
+            hook_trace.borrow_mut().push(format!("{:x}: {}", pc, inst));
+        });
+
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.step(), Err(Exception::IllegalInstruction));
+        assert_eq!(
+            *trace.borrow(),
+            vec!["0: addi r1 r0 0x0", "4: addi r2 r0 0x3e8"]
+        );
+    }
+
+    #[test]
+    fn load_reserved() {
+        let mut cpu = Cpu::<RV64I>::new(memory(&[
+            0x1005_20AF, // lr.w x1, (x10)
+            0x1835_212F, // sc.w x2, x3, (x10)
+            0x1835_212F, // sc.w x2, x3, (x10)
+            0x1005_30AF, // lr.d x1, (x10)
+            0x0045_2023, // sw x4, 0(x10)
+            0x1835_312F, // sc.d x2, x3, (x10)
+        ]));
+        let regs = cpu.registers();
+        regs.write_x(XRegister::from(10), 0x100);
+        regs.write_x(XRegister::from(3), 7);
+        regs.write_x(XRegister::from(4), 9);
+
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().read_x(XRegister::from(2)), 0);
+        assert_eq!(cpu.memory().read::<u32>(0x100), 7);
+
+        // The reservation is consumed by the first `sc.w`.
+        cpu.registers().write_x(XRegister::from(3), 8);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().read_x(XRegister::from(2)), 1);
+        assert_eq!(cpu.memory().read::<u32>(0x100), 7);
+
+        // The reservation is invalidated by the store.
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().read_x(XRegister::from(1)), 7);
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().read_x(XRegister::from(2)), 1);
+        assert_eq!(cpu.memory().read::<u64>(0x100), 9);
+    }
+
+    #[test]
+    fn misaligned_load_reserved() {
+        let mut cpu = Cpu::<RV64I>::new(memory(&[
+            0x1005_20AF, // lr.w x1, (x10)
+            0x1005_30AF, // lr.d x1, (x10)
+        ]));
+        cpu.set_misaligned_access(true);
+        cpu.registers().write_x(XRegister::from(10), 0x102);
+
+        // LR is never split into multiple accesses, even if misaligned accesses are enabled.
+        assert_eq!(cpu.step(), Err(Exception::LoadAddressMisaligned));
+        assert_eq!(cpu.registers().pc(), 0);
+
+        *cpu.registers_mut().pc_mut() = 4;
+        cpu.registers().write_x(XRegister::from(10), 0x104);
+        assert_eq!(cpu.step(), Err(Exception::LoadAddressMisaligned));
+        assert_eq!(cpu.registers().read_x(XRegister::from(1)), 0);
+    }
+
+    #[test]
+    fn atomic_memory_operations() {
+        let mut cpu = Cpu::<RV64I>::new(memory(&[
+            0x0035_22AF, // amoadd.w x5, x3, (x10)
+            0xC035_232F, // amominu.w x6, x3, (x10)
+            0x0035_A2AF, // amoadd.w x5, x3, (x11)
+        ]));
+        let regs = cpu.registers();
+        regs.write_x(XRegister::from(10), 0x100);
+        regs.write_x(XRegister::from(11), 0x102);
+        regs.write_x(XRegister::from(3), 0xFFFF_FFFF);
+        cpu.memory_mut().write(0x100_u64, 0x8000_0001_u32);
+
+        cpu.step().unwrap();
+        assert_eq!(
+            cpu.registers().read_x(XRegister::from(5)),
+            0xFFFF_FFFF_8000_0001
+        );
+        assert_eq!(cpu.memory().read::<u32>(0x100), 0x8000_0000);
+
+        // `0xFFFF_FFFF` is the largest unsigned word, so the memory stays unchanged.
+        cpu.step().unwrap();
+        assert_eq!(
+            cpu.registers().read_x(XRegister::from(6)),
+            0xFFFF_FFFF_8000_0000
+        );
+        assert_eq!(cpu.memory().read::<u32>(0x100), 0x8000_0000);
+
+        assert_eq!(cpu.step(), Err(Exception::StoreAddressMisaligned));
+    }
+
+    #[test]
+    fn fetch_little_endian() {
+        let mut memory = Memory::<RV64I>::with_size(0x100);
+        memory
+            .write_bytes(0x10, &[0x93, 0x00, 0xA0, 0x02]) // addi x1, x0, 42
+            .unwrap();
+
+        let mut cpu = Cpu::<RV64I>::new(memory);
+        assert_eq!(cpu.fetch(0x10), Ok(0x02A0_0093));
+        assert_eq!(cpu.fetch_half(0x12), Ok(0x02A0));
+    }
+
+    #[test]
+    fn fetch_faults() {
+        let config = CpuConfig::builder()
+            .binary(0x0000_0013_u32.to_le_bytes().to_vec()) // nop
+            .memory_size(0x100)
+            .extensions(vec!['C', 'I'])
+            .build();
+        let mut cpu = Cpu::<RV64I>::from_config(config).unwrap();
+        let size = 0x100;
+
+        assert_eq!(cpu.fetch(size), Err(Exception::InstructionAccessFault));
+        assert_eq!(cpu.trap_value(), size);
+        assert_eq!(cpu.fetch(3), Err(Exception::InstructionAddressMisaligned));
+        assert_eq!(cpu.trap_value(), 3);
+
+        // The second half of the last instruction is outside of the RAM.
+        cpu.memory_mut().write(size - 2, 0x0013_u16);
+        assert_eq!(cpu.fetch(size - 2), Err(Exception::InstructionAccessFault));
+        assert_eq!(cpu.trap_value(), size);
+
+        *cpu.registers_mut().pc_mut() = size;
+        assert_eq!(cpu.step(), Err(Exception::InstructionAccessFault));
+    }
+
+    #[test]
+    fn fetch_halfwords() {
+        let mut binary = vec![0x01, 0x00]; // c.nop
+        binary.extend_from_slice(&0x02A0_0093_u32.to_le_bytes()); // addi x1, x0, 42
+
+        // Without the C extension, the instruction at 2 is misaligned.
+        let config = CpuConfig::builder()
+            .binary(binary.clone())
+            .memory_size(0x100)
+            .pc(2)
+            .build();
+        let mut cpu = Cpu::<RV32I>::from_config(config).unwrap();
+        assert_eq!(cpu.step(), Err(Exception::InstructionAddressMisaligned));
+
+        let config = CpuConfig::builder()
+            .binary(binary)
+            .memory_size(0x100)
+            .pc(2)
+            .extensions(vec!['C', 'I'])
+            .build();
+        let mut cpu = Cpu::<RV32I>::from_config(config).unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().pc(), 6);
+        assert_eq!(cpu.registers().read_x(XRegister::from(1)), 42);
+
+        // Compressed instructions are not supported yet.
+        *cpu.registers_mut().pc_mut() = 0;
+        assert_eq!(cpu.step(), Err(Exception::IllegalInstruction));
+        assert_eq!(cpu.registers().pc(), 0);
+    }
+
+    #[test]
+    fn sfence_vma() {
+        let pte = |ppn: u64, flags: u64| (ppn << 10) | flags;
+
+        let mut memory = Memory::<RV64I>::with_size(0x8000);
+        memory.write(0_u64, 0x1200_0073_u32); // sfence.vma x0, x0
+        memory.write(4_u64, 0x1200_0073_u32); // sfence.vma x0, x0
+
+        // Identity maps the first gigabyte, and maps the page at `0x4000_1000`
+        // to the physical page `0x4`.
+        memory.write(0x1000_u64, pte(0x0, 0b1_1111));
+        memory.write(0x1008_u64, pte(0x2, 0b01));
+        memory.write(0x2000_u64, pte(0x3, 0b01));
+        memory.write(0x3008_u64, pte(0x4, 0b11));
+
+        let mut cpu = Cpu::<RV64I>::new(memory);
+        cpu.registers().write_csr(csr::SATP, (8 << 60) | 0x1);
+
+        let va = VirtAddr::from(0x4000_1234_u64);
+        let translate = |cpu: &mut Cpu<RV64I>| {
+            let Cpu {
+                mmu,
+                registers,
+                memory,
+                ..
+            } = cpu;
+            mmu.translate_addr(
+                registers,
+                PrivilegeMode::Supervisor,
+                memory,
+                va,
+                AccessMode::Read,
+            )
+            .unwrap()
+            .as_u64()
+        };
+        assert_eq!(translate(&mut cpu), 0x4234);
+
+        // The old translation stays in the TLB until it's flushed.
+        cpu.memory_mut().write(0x3008_u64, pte(0x5, 0b11));
+        assert_eq!(translate(&mut cpu), 0x4234);
+        cpu.step().unwrap();
+        assert_eq!(translate(&mut cpu), 0x5234);
+
+        cpu.privilege = PrivilegeMode::User;
+        assert_eq!(cpu.step(), Err(Exception::IllegalInstruction));
+    }
+
+    #[test]
+    fn wait_for_interrupt() {
+        let mut cpu = Cpu::<RV64I>::new(memory(&[
+            0x1050_0073, // wfi
+            0x0010_0093, // addi x1, x0, 1
+            0x1050_0073, // wfi
+            0x0020_0113, // addi x2, x0, 2
+            0x1050_0073, // wfi
+        ]));
+        cpu.registers().write_csr(csr::MTVEC, 0x100);
+        cpu.registers().write_csr(csr::MIE, 1 << 7);
+
+        assert_eq!(cpu.step(), Ok(StepOutcome::WaitingForInterrupt));
+        assert_eq!(cpu.step(), Ok(StepOutcome::WaitingForInterrupt));
+        assert_eq!(cpu.registers().pc(), 4);
+        assert!(cpu.is_waiting());
+
+        // A pending timer interrupt resumes the execution, even if it's not taken.
+        cpu.set_pending_interrupt(Interrupt::MachineTimer);
+        assert_eq!(cpu.step(), Ok(StepOutcome::Retired));
+        assert_eq!(cpu.registers().read_x(XRegister::from(1)), 1);
+
+        // `WFI` completes immediately if an interrupt is already pending.
+        assert_eq!(cpu.step(), Ok(StepOutcome::Retired));
+        assert_eq!(cpu.step(), Ok(StepOutcome::Retired));
+        assert_eq!(cpu.registers().read_x(XRegister::from(2)), 2);
+
+        cpu.registers().write_csr(csr::MIP, 0);
+        assert_eq!(cpu.step(), Ok(StepOutcome::WaitingForInterrupt));
+        cpu.registers().mstatus().set_mie(true);
+        cpu.set_pending_interrupt(Interrupt::MachineTimer);
+        assert_eq!(
+            cpu.step(),
+            Ok(StepOutcome::TrapTaken(Interrupt::MachineTimer.into()))
+        );
+        assert_eq!(cpu.registers().read_csr(csr::MEPC), 20);
+        assert_eq!(cpu.registers().pc(), 0x100);
+
+        // With `mstatus.TW` set, `WFI` is illegal outside of machine mode.
+        cpu.registers().write_csr(csr::MIP, 0);
+        cpu.privilege = PrivilegeMode::User;
+        cpu.registers().mstatus().set_tw(true);
+        *cpu.registers_mut().pc_mut() = 0;
+        assert_eq!(cpu.step(), Err(Exception::IllegalInstruction));
+    }
+
+    #[test]
+    fn paging() {
+        let pte = |ppn: u64, flags: u64| (ppn << 10) | flags;
+
+        let mut memory = Memory::<RV64I>::with_size(0x8000);
+        memory.write(0_u64, 0x0085_3083_u32); // ld x1, 8(x10)
+        memory.write(4_u64, 0x0015_3423_u32); // sd x1, 8(x10)
+        memory.write(8_u64, 0x0005_B103_u32); // ld x2, 0(x11)
+        memory.write(0x4008_u64, 0x1234_5678_u64);
+
+        // The first gigabyte is identity mapped, and the page at `0x4000_1000`
+        // is mapped read-only to the physical page `0x4`.
+        memory.write(0x1000_u64, pte(0x0, 0b1111));
+        memory.write(0x1008_u64, pte(0x2, 0b0001));
+        memory.write(0x2000_u64, pte(0x3, 0b0001));
+        memory.write(0x3008_u64, pte(0x4, 0b0011));
+
+        let mut cpu = Cpu::<RV64I>::new(memory);
+        cpu.privilege = PrivilegeMode::Supervisor;
+        cpu.registers().write_csr(csr::SATP, (8 << 60) | 0x1);
+        cpu.registers().write_x(XRegister::from(10), 0x4000_1000);
+        cpu.registers().write_x(XRegister::from(11), 0x8000_0000);
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().read_x(XRegister::from(1)), 0x1234_5678);
+        assert_eq!(cpu.step(), Err(Exception::StorePageFault));
+
+        *cpu.registers_mut().pc_mut() = 8;
+        assert_eq!(cpu.step(), Err(Exception::LoadPageFault));
+
+        // Machine mode uses physical addresses, unless `mstatus.MPRV` is set.
+        cpu.privilege = PrivilegeMode::Machine;
+        cpu.registers().write_x(XRegister::from(10), 0x4000);
+        *cpu.registers_mut().pc_mut() = 0;
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().read_x(XRegister::from(1)), 0x1234_5678);
+
+        cpu.registers().mstatus().set_mprv(true);
+        cpu.registers().mstatus().set_mpp(PrivilegeMode::Supervisor);
+        cpu.registers().write_x(XRegister::from(10), 0x4000_1000);
+        *cpu.registers_mut().pc_mut() = 0;
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().read_x(XRegister::from(1)), 0x1234_5678);
+    }
+
+    #[test]
+    fn modify_privilege() {
+        let pte = |ppn: u64, flags: u64| (ppn << 10) | flags;
+
+        let mut memory = Memory::<RV64I>::with_size(0x8000);
+        memory.write(0_u64, 0x0015_3423_u32); // sd x1, 8(x10)
+
+        // Maps the page at `0x4000_1000` to the physical page `0x4`,
+        // which is only accessible from supervisor mode.
+        memory.write(0x1008_u64, pte(0x2, 0b0001));
+        memory.write(0x2000_u64, pte(0x3, 0b0001));
+        memory.write(0x3008_u64, pte(0x4, 0b0111));
+
+        let mut cpu = Cpu::<RV64I>::new(memory);
+        cpu.registers().write_csr(csr::SATP, (8 << 60) | 0x1);
+        cpu.registers().write_x(XRegister::from(1), 42);
+        cpu.registers().write_x(XRegister::from(10), 0x4000_1000);
+
+        let status = cpu.registers().mstatus();
+        status.set_mprv(true);
+        status.set_mpp(PrivilegeMode::User);
+
+        // The store is executed with user mode permissions.
+        assert_eq!(cpu.step(), Err(Exception::StorePageFault));
+
+        cpu.memory_mut().write(0x3008_u64, pte(0x4, 0b1_0111));
+        cpu.mmu_mut().flush_tlb(None, None);
+        cpu.step().unwrap();
+        assert_eq!(cpu.memory().read::<u64>(0x4008_u64), 42);
+
+        // Supervisor mode can't access user pages, unless `SUM` is set.
+        let status = cpu.registers().mstatus();
+        status.set_mpp(PrivilegeMode::Supervisor);
+        *cpu.registers_mut().pc_mut() = 0;
+        assert_eq!(cpu.step(), Err(Exception::StorePageFault));
+
+        cpu.registers().mstatus().set_sum(true);
+        cpu.step().unwrap();
+    }
+
+    #[test]
+    fn misaligned_access() {
+        let mut cpu = Cpu::<RV64I>::new(memory(&[
+            0x0015_2083, // lw x1, 1(x10)
+            0x0015_2123, // sw x1, 2(x10)
+            0x0035_4103, // lbu x2, 3(x10)
+        ]));
+        cpu.registers().write_x(XRegister::from(10), 0x100);
+        cpu.memory_mut().write(0x100_u64, 0x8877_6655_4433_2211_u64);
+
+        // Misaligned accesses raise an exception by default.
+        assert_eq!(cpu.step(), Err(Exception::LoadAddressMisaligned));
+        assert_eq!(cpu.registers().pc(), 0);
+
+        cpu.set_misaligned_access(true);
+        cpu.step().unwrap();
+        assert_eq!(
+            cpu.registers().read_x(XRegister::from(1)),
+            0x0000_0000_5544_3322
+        );
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.memory().read::<u64>(0x100), 0x8877_5544_3322_2211);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().read_x(XRegister::from(2)), 0x33);
+
+        // Loads are sign extended, and every byte must be inside the memory.
+        let mut cpu = Cpu::<RV32I>::new(memory(&[
+            0x0015_1083, // lh x1, 1(x10)
+            0x0015_1083, // lh x1, 1(x10)
+        ]));
+        cpu.set_misaligned_access(true);
+        cpu.registers().write_x(XRegister::from(10), 0x100);
+        cpu.memory_mut().write(0x100_u32, 0x00FF_8000_u32);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().read_x(XRegister::from(1)), 0xFFFF_FF80);
+
+        let size = cpu.memory().size() as u32;
+        cpu.registers().write_x(XRegister::from(10), size - 2);
+        assert_eq!(cpu.step(), Err(Exception::LoadAccessFault));
+    }
+
+    #[test]
+    fn step_outcomes() {
+        let mut cpu = Cpu::<RV64I>::new(memory(&[
+            0x0020_80B3, // add x1, x1, x2
+            0x1050_0073, // wfi
+            0x0010_0073, // ebreak
+            0x0000_0000, // illegal
+        ]));
+        cpu.registers().write_csr(csr::MTVEC, 0x100);
+        cpu.registers().write_csr(csr::MIE, 1 << 3);
+
+        assert_eq!(cpu.step(), Ok(StepOutcome::Retired));
+        assert_eq!(cpu.step(), Ok(StepOutcome::WaitingForInterrupt));
+
+        cpu.registers().mstatus().set_mie(true);
+        cpu.set_pending_interrupt(Interrupt::MachineSoftware);
+        assert_eq!(
+            cpu.step(),
+            Ok(StepOutcome::TrapTaken(Trap::Interrupt(
+                Interrupt::MachineSoftware
+            )))
+        );
+        assert_eq!(cpu.registers().pc(), 0x100);
+
+        // A breakpoint halts the CPU without taking a trap.
+        cpu.registers().write_csr(csr::MIP, 0);
+        *cpu.registers_mut().pc_mut() = 8;
+        assert_eq!(cpu.step(), Ok(StepOutcome::Halted(HaltReason::Breakpoint)));
+        assert_eq!(cpu.registers().pc(), 8);
+
+        *cpu.registers_mut().pc_mut() = 12;
+        assert_eq!(cpu.step(), Err(Exception::IllegalInstruction));
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut cpu = Cpu::<RV64I>::new(memory(&[
+            0x0030_0093, // addi x1, x0, 3
+            0xFFF0_8093, // addi x1, x1, -1
+            0xFE00_9EE3, // bnez x1, -4
+            0x0010_0073, // ebreak
+        ]));
+        cpu.set_breakpoint(8);
+
+        for count in (0..3).rev() {
+            cpu.run().unwrap();
+            assert_eq!(cpu.registers().pc(), 8);
+            assert_eq!(cpu.registers().read_x(XRegister::from(1)), count);
+        }
+
+        // The breakpoint doesn't halt again, after the CPU resumed from it.
+        assert_eq!(cpu.step(), Ok(StepOutcome::Retired));
+        assert_eq!(cpu.registers().pc(), 12);
+
+        *cpu.registers_mut().pc_mut() = 8;
+        assert_eq!(
+            cpu.step(),
+            Ok(StepOutcome::Halted(HaltReason::BreakpointHit))
+        );
+        assert!(cpu.clear_breakpoint(8));
+        assert!(!cpu.clear_breakpoint(8));
+
+        cpu.run().unwrap();
+        assert_eq!(cpu.registers().pc(), 12);
+        assert_eq!(cpu.memory().read_bytes(12, 4), Ok(vec![0x73, 0, 0x10, 0]));
+    }
+
+    #[test]
+    fn profiling() {
+        let mut cpu = Cpu::<RV64I>::new(memory(&[
+            0x0030_0093, // addi x1, x0, 3
+            0xFFF0_8093, // addi x1, x1, -1
+            0xFE00_9EE3, // bnez x1, -4
+            0x0010_0073, // ebreak
+        ]));
+        cpu.step().unwrap();
+        assert!(cpu.profile().is_empty());
+
+        cpu.enable_profiling();
+        cpu.run().unwrap();
+
+        let profile = cpu.profile();
+        assert_eq!(profile[&Kind::ADDI], 3);
+        assert_eq!(profile[&Kind::BNE], 3);
+        assert_eq!(profile.get(&Kind::EBREAK), None);
+    }
+
+    #[test]
+    fn disabled_extension() {
+        let binary = 0x0021_A0AF_u32.to_le_bytes(); // amoadd.w x1, x2, (x3)
+
+        let config = CpuConfig::builder()
+            .binary(binary.to_vec())
+            .extensions(vec!['A', 'I'])
+            .build();
+        let mut cpu = Cpu::<RV64I>::from_config(config).unwrap();
+        cpu.registers().write_x(XRegister::from(3), 0x100);
+        assert_eq!(cpu.step(), Ok(StepOutcome::Retired));
+
+        let config = CpuConfig::builder()
+            .binary(binary.to_vec())
+            .extensions(vec!['I'])
+            .build();
+        let mut cpu = Cpu::<RV64I>::from_config(config).unwrap();
+        cpu.registers().write_x(XRegister::from(3), 0x100);
+        assert!(!cpu.registers().has_extension('A'));
+        assert_eq!(cpu.step(), Err(Exception::IllegalInstruction));
+        assert_eq!(cpu.trap_value(), 0x0021_A0AF);
+    }
+
+    #[test]
+    fn environment_call_cause() {
+        let mut cpu = Cpu::<RV64I>::new(memory(&[0x0000_0073])); // ecall
+
+        let causes = [
+            (PrivilegeMode::User, Exception::UserModeEnvironmentCall),
+            (
+                PrivilegeMode::Supervisor,
+                Exception::SupervisorModeEnvironmentCall,
+            ),
+            (
+                PrivilegeMode::Machine,
+                Exception::MachineModeEnvironmentCall,
+            ),
+        ];
+        for (privilege, cause) in &causes {
+            cpu.privilege = *privilege;
+            assert_eq!(cpu.step(), Err(*cause));
+            assert_eq!(cpu.registers().pc(), 0);
+        }
+    }
+
+    #[test]
+    fn modeled_cycles() {
+        let mut add = Cpu::<RV64I>::new(memory(&[
+            0x0020_80B3, // add x1, x1, x2
+            0x0010_0073, // ebreak
+        ]));
+        add.enable_cycle_model();
+        add.run().unwrap();
+
+        let mut div = Cpu::<RV64I>::new(memory(&[
+            0x0220_C0B3, // div x1, x1, x2
+            0x0010_0073, // ebreak
+        ]));
+        div.enable_cycle_model();
+        div.run().unwrap();
+
+        assert_eq!(add.modeled_cycles(), 1);
+        assert!(div.modeled_cycles() > add.modeled_cycles());
+
+        // Cycles are only modeled if enabled.
+        let mut cpu = Cpu::<RV64I>::new(memory(&[0x0020_80B3]));
+        cpu.step().unwrap();
+        assert_eq!(cpu.modeled_cycles(), 0);
+
+        let mut cpu = Cpu::<RV64I>::new(memory(&[0x0020_80B3, 0x0020_80B3]));
+        cpu.set_cost_model(|kind| match kind {
+            Kind::ADD => 7,
+            _ => 1,
+        });
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.modeled_cycles(), 14);
+    }
+
+    #[test]
+    fn flat_binary() {
+        let mut binary = vec![];
+        binary.extend_from_slice(&0x0000_0013_u32.to_le_bytes()); // nop
+        binary.extend_from_slice(&0x02A0_0093_u32.to_le_bytes()); // addi x1, x0, 42
+
+        let mut cpu =
+            Cpu::<RV64I>::from_config(CpuConfig::flat(binary.clone(), 0x8000, 0x8004)).unwrap();
+        assert_eq!(cpu.registers().pc(), 0x8004);
+        assert_eq!(cpu.fetch(0x8004), Ok(0x02A0_0093));
+        assert_eq!(cpu.memory().base(), 0x8000);
+        assert_eq!(
+            cpu.memory().read_int(0, 4, false),
+            Err(Exception::LoadAccessFault)
+        );
+
+        assert_eq!(cpu.step(), Ok(StepOutcome::Retired));
+        assert_eq!(cpu.registers().read_x(XRegister::from(1)), 42);
+
+        let mut config = CpuConfig::<RV64I>::flat(binary, 0x8000, 0x8000);
+        *config.memory_size_mut() = 4;
+        assert!(matches!(
+            Cpu::<RV64I>::from_config(config),
+            Err(EmuError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn load_extension() {
+        let mut cpu = Cpu::<RV64I>::new(memory(&[
+            0x0001_2083, // lw x1, 0(x2)
+            0x0001_6183, // lwu x3, 0(x2)
+            0x0001_0203, // lb x4, 0(x2)
+            0x0001_4283, // lbu x5, 0(x2)
+            0x0001_1303, // lh x6, 0(x2)
+            0x0001_5383, // lhu x7, 0(x2)
+        ]));
+        cpu.memory_mut().write(0x100_u64, 0xFFFF_FFFF_u32);
+        cpu.registers().write_x(XRegister::from(2), 0x100);
+        for _ in 0..6 {
+            assert_eq!(cpu.step(), Ok(StepOutcome::Retired));
+        }
+
+        let x = |reg: u8| cpu.registers().read_x(XRegister::from(reg));
+        assert_eq!(x(1), 0xFFFF_FFFF_FFFF_FFFF);
+        assert_eq!(x(3), 0x0000_0000_FFFF_FFFF);
+        assert_eq!(x(4), 0xFFFF_FFFF_FFFF_FFFF);
+        assert_eq!(x(5), 0xFF);
+        assert_eq!(x(6), 0xFFFF_FFFF_FFFF_FFFF);
+        assert_eq!(x(7), 0xFFFF);
+    }
+
+    #[test]
+    fn run_until_halt() {
+        let mut cpu = Cpu::<RV64I>::new(memory(&[
+            0x0000_0073, // ecall
+            0x0000_0000, // illegal instruction
+            0x0010_0073, // ebreak
+            0x0000_006F, // j 0
+        ]));
+        cpu.memory_mut().write(0x40_u64, 0x3410_2373_u32); // csrr t1, mepc
+        cpu.memory_mut().write(0x44_u64, 0x0043_0313_u32); // addi t1, t1, 4
+        cpu.memory_mut().write(0x48_u64, 0x3413_1073_u32); // csrw mepc, t1
+        cpu.memory_mut().write(0x4C_u64, 0x3020_0073_u32); // mret
+        cpu.registers().write_csr(csr::MTVEC, 0x40);
+
+        // Both exceptions are handled by the guest.
+        assert_eq!(
+            cpu.run_until_halt(100),
+            Some(RunOutcome::Halted(HaltReason::Breakpoint))
+        );
+        assert_eq!(cpu.registers().pc(), 8);
+        assert_eq!(cpu.registers().read_csr(csr::MCAUSE), 2);
+
+        *cpu.registers_mut().pc_mut() = 12;
+        assert_eq!(cpu.run_until_halt(100), None);
+    }
 }
@@ -0,0 +1,440 @@
+use super::{PrivilegeMode, MISA_EXTENSIONS};
+use crate::{memory::MEMORY_SIZE, Address, Base};
+use derive_more::Display;
+use num_traits::Zero;
+use std::{collections::HashMap, fs, io, marker::PhantomData, path::Path};
+
+/// The `e_machine` value of RISC-V ELF files.
+const EM_RISCV: elf::types::Machine = elf::types::Machine(243);
+
+/// The error that is returned if loading an ELF file failed.
+#[derive(Debug, Display)]
+pub enum ElfError {
+    /// The ELF file couldn't be parsed.
+    #[display(fmt = "failed to parse ELF file: {:?}", _0)]
+    Parse(elf::ParseError),
+    /// The ELF file is not a RISC-V executable.
+    #[display(fmt = "ELF file is not a RISC-V executable")]
+    InvalidMachine,
+    /// The ELF file is malformed.
+    #[display(fmt = "invalid ELF file format")]
+    InvalidFormat,
+    /// The entry point or a segment of the ELF file doesn't fit
+    /// into the address width of the base ISA.
+    #[display(fmt = "ELF file contains addresses that don't fit into XLEN bits")]
+    AddressOutOfRange,
+    /// The ELF file doesn't contain any loadable segment.
+    #[display(fmt = "ELF file doesn't contain any loadable segment")]
+    NoLoadableSegments,
+    /// The ELF file doesn't contain a `.text` section.
+    #[display(fmt = "ELF file doesn't contain a `.text` section")]
+    NoTextSection,
+}
+
+impl std::error::Error for ElfError {}
+
+impl From<elf::ParseError> for ElfError {
+    fn from(err: elf::ParseError) -> Self {
+        Self::Parse(err)
+    }
+}
+
+/// The configuration that is used to create a [`Cpu`](super::Cpu).
+///
+/// It contains the binary that will be executed, and the machine it's
+/// executed on, which includes the memory size, the enabled extensions,
+/// and the initial state of the CPU. A config is either created
+/// directly, or using a [`CpuConfigBuilder`], see [`CpuConfig::builder`].
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone)]
+pub struct CpuConfig<B: Base> {
+    pub(crate) binary: Vec<u8>,
+    pub(crate) pc: B::Addr,
+    pub(crate) base: B::Addr,
+    pub(crate) memory_size: usize,
+    pub(crate) init_sp: bool,
+    pub(crate) blobs: Vec<(B::Addr, Vec<u8>)>,
+    pub(crate) extensions: Vec<char>,
+    pub(crate) privilege: PrivilegeMode,
+    symbols: HashMap<u64, String>,
+    _base: PhantomData<B>,
+}
+
+impl<B: Base> CpuConfig<B> {
+    /// Creates a new [`CpuConfigBuilder`], which is initialized with
+    /// an empty binary and the default values.
+    pub fn builder() -> CpuConfigBuilder<B> {
+        CpuConfigBuilder {
+            config: Self::raw(vec![]),
+        }
+    }
+
+    /// Creates a new config that will execute the raw `binary`,
+    /// which will be loaded at address `0`.
+    pub fn raw(binary: Vec<u8>) -> Self {
+        Self {
+            binary,
+            pc: B::Addr::zero(),
+            base: B::Addr::zero(),
+            memory_size: MEMORY_SIZE,
+            init_sp: true,
+            blobs: Vec::new(),
+            extensions: MISA_EXTENSIONS.to_vec(),
+            privilege: PrivilegeMode::Machine,
+            symbols: HashMap::new(),
+            _base: PhantomData,
+        }
+    }
+
+    /// Creates a new config that will execute the flat `binary`, which is loaded
+    /// at the address `base`, where it was linked, and starts executing at `entry`.
+    ///
+    /// The RAM starts at `base`, so the memory size has to be large enough
+    /// to contain the whole image.
+    pub fn flat(binary: Vec<u8>, base: B::Addr, entry: B::Addr) -> Self {
+        Self {
+            pc: entry,
+            base,
+            ..Self::raw(binary)
+        }
+    }
+
+    /// Reads the raw binary at the given path.
+    ///
+    /// ## Errors
+    ///
+    /// Returns an error if the file couldn't be read.
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        fs::read(path).map(Self::raw)
+    }
+
+    /// Loads the ELF file at the given path.
+    ///
+    /// Every loadable segment is placed at it's virtual address in memory, and
+    /// the `pc` is set to the entry point of the ELF file. The part of a segment
+    /// that is not backed by the file (e.g. `.bss`) is zero-filled.
+    ///
+    /// The base address is the lowest address of any loadable segment, so the RAM
+    /// starts there, and the binary only contains the image from that address on.
+    ///
+    /// ## Errors
+    ///
+    /// Returns an [`ElfError`] if the file is not a valid RISC-V executable,
+    /// or if the entry point or a segment doesn't fit into `XLEN` bits.
+    pub fn from_elf(path: impl AsRef<Path>) -> Result<Self, ElfError> {
+        let data = fs::read(path).map_err(elf::ParseError::from)?;
+        let file = elf::File::open_stream(&mut io::Cursor::new(&data))?;
+
+        if file.ehdr.machine != EM_RISCV {
+            return Err(ElfError::InvalidMachine);
+        }
+
+        let fits = |addr: u64| B::XLEN == 64 || addr >> 32 == 0;
+        if !fits(file.ehdr.entry) {
+            return Err(ElfError::AddressOutOfRange);
+        }
+
+        file.get_section(".text").ok_or(ElfError::NoTextSection)?;
+
+        let segments = file
+            .phdrs
+            .iter()
+            .filter(|phdr| phdr.progtype == elf::types::PT_LOAD)
+            .collect::<Vec<_>>();
+        if segments.is_empty() {
+            return Err(ElfError::NoLoadableSegments);
+        }
+
+        let base = segments
+            .iter()
+            .map(|phdr| phdr.vaddr)
+            .min()
+            .expect("there is at least one segment");
+
+        let mut binary = Vec::new();
+        for phdr in segments {
+            if phdr.filesz > phdr.memsz {
+                return Err(ElfError::InvalidFormat);
+            }
+
+            let end = phdr.vaddr.checked_add(phdr.memsz).filter(|&end| fits(end));
+            let src = phdr
+                .offset
+                .checked_add(phdr.filesz)
+                .and_then(|src_end| data.get(phdr.offset as usize..src_end as usize));
+
+            let (end, src) = match (end, src) {
+                (Some(end), Some(src)) => ((end - base) as usize, src),
+                (None, _) => return Err(ElfError::AddressOutOfRange),
+                (_, None) => return Err(ElfError::InvalidFormat),
+            };
+
+            if binary.len() < end {
+                binary.resize(end, 0);
+            }
+            let start = (phdr.vaddr - base) as usize;
+            binary[start..start + src.len()].copy_from_slice(src);
+            binary[start + src.len()..end]
+                .iter_mut()
+                .for_each(|b| *b = 0);
+        }
+
+        let symbols = match file.get_section(".symtab") {
+            Some(symtab) if (symtab.shdr.link as usize) < file.sections.len() => file
+                .get_symbols(symtab)?
+                .into_iter()
+                .filter_map(|sym| {
+                    (sym.symtype == elf::types::STT_FUNC).then(|| (sym.value, sym.name))
+                })
+                .collect(),
+            _ => HashMap::new(),
+        };
+
+        Ok(Self {
+            pc: B::Addr::from_u64(file.ehdr.entry),
+            base: B::Addr::from_u64(base),
+            symbols,
+            ..Self::raw(binary)
+        })
+    }
+
+    /// Returns a map from address to name of every function symbol
+    /// in the loaded ELF file.
+    ///
+    /// The map is empty if the config wasn't created from an ELF file,
+    /// or if the symbol table was stripped.
+    pub fn symbols(&self) -> &HashMap<u64, String> {
+        &self.symbols
+    }
+
+    /// Returns a mutable reference to the initial program counter.
+    pub fn pc_mut(&mut self) -> &mut B::Addr {
+        &mut self.pc
+    }
+
+    /// Returns a mutable reference to the memory size in bytes.
+    pub fn memory_size_mut(&mut self) -> &mut usize {
+        &mut self.memory_size
+    }
+
+    /// Returns a mutable reference to the flag, that specifies if the stack pointer
+    /// is initialized to the top of the memory.
+    ///
+    /// This is enabled by default, and the stack pointer will be aligned
+    /// to 16 bytes, as required by the calling convention.
+    pub fn init_sp_mut(&mut self) -> &mut bool {
+        &mut self.init_sp
+    }
+
+    /// Places `data` at the physical address `addr`, in addition to the binary.
+    ///
+    /// The blobs are copied into memory after the binary, in the order they were
+    /// mapped, so they are used to provide e.g. a device tree or an initial ramdisk
+    /// at the address that the program expects. Every blob must be inside the RAM.
+    pub fn map_blob(&mut self, addr: B::Addr, data: Vec<u8>) {
+        self.blobs.push((addr, data));
+    }
+}
+
+/// A builder to create a [`CpuConfig`].
+///
+/// By default, the binary is empty and loaded at address `0`, which is
+/// also the initial `pc`, the memory has the [default size](MEMORY_SIZE),
+/// and the stack pointer is initialized. The CPU starts in machine mode, and
+/// only the extensions that are supported by the emulator are enabled.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone)]
+pub struct CpuConfigBuilder<B: Base> {
+    config: CpuConfig<B>,
+}
+
+impl<B: Base> CpuConfigBuilder<B> {
+    /// Sets the raw binary, that will be executed.
+    pub fn binary(mut self, binary: Vec<u8>) -> Self {
+        self.config.binary = binary;
+        self.config.symbols.clear();
+        self
+    }
+
+    /// Loads the ELF file at the given path, which replaces the binary, and sets
+    /// the `pc` to the entry point and the base address to the lowest address
+    /// of the segments. See [`CpuConfig::from_elf`].
+    ///
+    /// ## Errors
+    ///
+    /// Returns an [`ElfError`] if the ELF file couldn't be loaded.
+    pub fn elf(mut self, path: impl AsRef<Path>) -> Result<Self, ElfError> {
+        let loaded = CpuConfig::<B>::from_elf(path)?;
+        self.config.binary = loaded.binary;
+        self.config.symbols = loaded.symbols;
+        self.config.pc = loaded.pc;
+        self.config.base = loaded.base;
+        Ok(self)
+    }
+
+    /// Sets the initial program counter.
+    pub fn pc(mut self, addr: B::Addr) -> Self {
+        self.config.pc = addr;
+        self
+    }
+
+    /// Sets the size of the memory in bytes.
+    pub fn memory_size(mut self, size: usize) -> Self {
+        self.config.memory_size = size;
+        self
+    }
+
+    /// Sets the address at which the RAM starts, and the binary is loaded into memory.
+    pub fn base(mut self, addr: B::Addr) -> Self {
+        self.config.base = addr;
+        self
+    }
+
+    /// Sets if the stack pointer is initialized to the top of the memory.
+    pub fn init_sp(mut self, init_sp: bool) -> Self {
+        self.config.init_sp = init_sp;
+        self
+    }
+
+    /// Sets the enabled ISA extensions, which are reported in `misa`.
+    ///
+    /// Each extension is identified by it's uppercase letter, e.g. `'I'`.
+    pub fn extensions(mut self, extensions: impl Into<Vec<char>>) -> Self {
+        self.config.extensions = extensions.into();
+        self
+    }
+
+    /// Sets the privilege mode the CPU starts in.
+    pub fn privilege(mut self, privilege: PrivilegeMode) -> Self {
+        self.config.privilege = privilege;
+        self
+    }
+
+    /// Creates the [`CpuConfig`].
+    pub fn build(self) -> CpuConfig<B> {
+        self.config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CpuConfig, ElfError};
+    use crate::{cpu::PrivilegeMode, memory::MEMORY_SIZE, RV32I, RV64I};
+
+    const ENTRY_ABOVE_4G: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/entry_above_4g.elf"
+    );
+    const TINY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/tiny.elf");
+    const DATA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/data.elf");
+    const SYMBOLS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/symbols.elf");
+    const NO_SEGMENTS: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/no_segments.elf"
+    );
+
+    #[test]
+    fn load_segments() {
+        let config = CpuConfig::<RV64I>::from_elf(TINY).unwrap();
+        assert_eq!(config.pc, 0x100);
+        assert_eq!(config.base, 0x100);
+        assert_eq!(config.binary.len(), 8);
+        assert_eq!(
+            &config.binary[..],
+            &[0x93, 0x00, 0xA0, 0x02, 0x73, 0x00, 0x10, 0x00]
+        );
+
+        assert!(matches!(
+            CpuConfig::<RV64I>::from_elf(NO_SEGMENTS),
+            Err(ElfError::NoLoadableSegments)
+        ));
+    }
+
+    #[test]
+    fn entry_width() {
+        assert!(matches!(
+            CpuConfig::<RV32I>::from_elf(ENTRY_ABOVE_4G),
+            Err(ElfError::AddressOutOfRange)
+        ));
+
+        let config = CpuConfig::<RV64I>::from_elf(ENTRY_ABOVE_4G).unwrap();
+        assert_eq!(config.pc, 0x1_0000_0000);
+    }
+
+    #[test]
+    fn load_data_and_bss() {
+        let config = CpuConfig::<RV64I>::from_elf(DATA).unwrap();
+        assert_eq!(config.base, 0x100);
+        assert_eq!(config.binary.len(), 0x110);
+        assert_eq!(&config.binary[..0x4], &[0x73, 0x00, 0x10, 0x00]);
+        assert_eq!(&config.binary[0x100..0x104], &[0xDE, 0xAD, 0xBE, 0xEF]);
+        assert!(config.binary[0x104..0x110].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn symbols() {
+        let config = CpuConfig::<RV64I>::from_elf(SYMBOLS).unwrap();
+        let symbols = config.symbols();
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols[&0x100], "_start");
+        assert_eq!(symbols[&0x108], "main");
+
+        let config = CpuConfig::<RV64I>::from_elf(TINY).unwrap();
+        assert!(config.symbols().is_empty());
+    }
+
+    #[test]
+    fn builder() {
+        let config = CpuConfig::<RV32I>::builder().build();
+        assert!(config.binary.is_empty());
+        assert_eq!(config.pc, 0);
+        assert_eq!(config.base, 0);
+        assert_eq!(config.memory_size, MEMORY_SIZE);
+        assert!(config.init_sp);
+        assert_eq!(config.extensions, &['A', 'I', 'M', 'S', 'U']);
+        assert_eq!(config.privilege, PrivilegeMode::Machine);
+
+        let config = CpuConfig::<RV32I>::builder()
+            .binary(vec![0x73, 0x00, 0x10, 0x00])
+            .base(0x100)
+            .pc(0x100)
+            .memory_size(0x1000)
+            .init_sp(false)
+            .extensions(vec!['I', 'M'])
+            .privilege(PrivilegeMode::User)
+            .build();
+        assert_eq!(config.binary.len(), 4);
+        assert_eq!(config.pc, 0x100);
+        assert_eq!(config.base, 0x100);
+        assert_eq!(config.memory_size, 0x1000);
+        assert!(!config.init_sp);
+        assert_eq!(config.extensions, &['I', 'M']);
+        assert_eq!(config.privilege, PrivilegeMode::User);
+
+        // Loading an ELF file sets the base, but later calls still override the `pc`.
+        let config = CpuConfig::<RV64I>::builder()
+            .base(0x1000)
+            .elf(SYMBOLS)
+            .unwrap()
+            .memory_size(0x2000)
+            .build();
+        assert_eq!(config.pc, 0x100);
+        assert_eq!(config.base, 0x100);
+        assert_eq!(config.memory_size, 0x2000);
+        assert_eq!(config.symbols().len(), 2);
+
+        let config = CpuConfig::<RV64I>::builder()
+            .elf(TINY)
+            .unwrap()
+            .pc(0x104)
+            .binary(vec![])
+            .build();
+        assert_eq!(config.pc, 0x104);
+        assert!(config.binary.is_empty());
+
+        assert!(matches!(
+            CpuConfig::<RV64I>::builder().elf(NO_SEGMENTS),
+            Err(ElfError::NoLoadableSegments)
+        ));
+    }
+}
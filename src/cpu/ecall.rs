@@ -0,0 +1,178 @@
+//! Handling of environment calls, which allows a program to
+//! interact with the host.
+
+use super::{Cpu, Hooks, XRegister};
+use crate::{Address, Base};
+use std::io::Write;
+
+/// Specifies if the program should continue after an environment call was handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlFlow {
+    /// The program continues at the instruction after the `ECALL`.
+    Continue,
+    /// The `ECALL` raises an environment call exception, which will stop [`Cpu::run`].
+    Break,
+}
+
+/// A handler, that is invoked every time an `ECALL` instruction is executed.
+pub trait EnvironmentCall<B: Base, H: Hooks<B>> {
+    /// Handles the environment call, by reading the arguments from,
+    /// and writing the results to the registers of `cpu`.
+    fn handle(&mut self, cpu: &mut Cpu<B, H>) -> ControlFlow;
+}
+
+/// The syscall number of `write`.
+const SYS_WRITE: u64 = 64;
+/// The syscall number of `exit`.
+const SYS_EXIT: u64 = 93;
+
+/// Error number for a bad file descriptor.
+const EBADF: i64 = 9;
+/// Error number for an invalid address.
+const EFAULT: i64 = 14;
+/// Error number for an unsupported syscall.
+const ENOSYS: i64 = 38;
+
+/// An [`EnvironmentCall`] handler, that implements the syscalls that are
+/// required by newlib to print and exit.
+///
+/// The syscall number is read from `a7`, and the arguments from `a0`-`a2`.
+/// The return value is written to `a0`, with errors being returned as negative
+/// error numbers.
+///
+/// - `write` (64) writes to `stdout` if the file descriptor is `1` or `2`.
+/// - `exit` (93) stops the execution. The exit code stays in `a0`.
+///
+/// Any other syscall returns `-ENOSYS`.
+#[derive(Debug)]
+pub struct Newlib<W> {
+    stdout: W,
+}
+
+impl<W: Write> Newlib<W> {
+    /// Creates a new handler, which writes all output into `stdout`.
+    pub fn new(stdout: W) -> Self {
+        Self { stdout }
+    }
+
+    fn write<B: Base, H: Hooks<B>>(&mut self, cpu: &Cpu<B, H>, fd: u64, buf: u64, len: u64) -> i64 {
+        if fd != 1 && fd != 2 {
+            return -EBADF;
+        }
+
+        let bytes = match cpu
+            .memory()
+            .read_bytes(B::Addr::from_u64(buf), len as usize)
+        {
+            Ok(bytes) => bytes,
+            Err(_) => return -EFAULT,
+        };
+        match self.stdout.write_all(&bytes) {
+            Ok(()) => len as i64,
+            Err(_) => -EBADF,
+        }
+    }
+}
+
+impl<B: Base, H: Hooks<B>, W: Write> EnvironmentCall<B, H> for Newlib<W> {
+    fn handle(&mut self, cpu: &mut Cpu<B, H>) -> ControlFlow {
+        let arg = |idx: u8| cpu.registers().read_x(XRegister::from(idx)).to_u64();
+        let (a0, a1, a2, a7) = (arg(10), arg(11), arg(12), arg(17));
+
+        let ret = match a7 {
+            SYS_WRITE => self.write(cpu, a0, a1, a2),
+            SYS_EXIT => return ControlFlow::Break,
+            _ => -ENOSYS,
+        };
+
+        cpu.registers().write_x(
+            XRegister::from(10),
+            B::Addr::from_u64(ret as u64 & mask::<B>()),
+        );
+        ControlFlow::Continue
+    }
+}
+
+/// Returns a mask of the lower `XLEN` bits.
+fn mask<B: Base>() -> u64 {
+    u64::MAX >> (64 - B::XLEN)
+}
+
+impl<B: Base, H: Hooks<B>> Cpu<B, H> {
+    /// Sets the handler that is invoked for every `ECALL` instruction.
+    pub fn set_environment_call(&mut self, handler: impl EnvironmentCall<B, H> + 'static) {
+        self.ecall = Some(Box::new(handler));
+    }
+
+    /// Invokes the environment call handler, if there's one.
+    ///
+    /// Returns `true` if the execution should continue after the `ECALL`.
+    pub(super) fn environment_call(&mut self) -> bool {
+        let mut handler = match self.ecall.take() {
+            Some(handler) => handler,
+            None => return false,
+        };
+
+        let flow = handler.handle(self);
+        self.ecall = Some(handler);
+        flow == ControlFlow::Continue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Newlib;
+    use crate::{
+        cpu::{Cpu, XRegister},
+        memory::Memory,
+        RV32I,
+    };
+    use std::{cell::RefCell, io, rc::Rc};
+
+    /// An output buffer that can be inspected after it was moved into the CPU.
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl io::Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn newlib() {
+        let program: &[u32] = &[
+            0x0010_0513, // addi a0, x0, 1
+            0x1000_0593, // addi a1, x0, 0x100
+            0x0050_0613, // addi a2, x0, 5
+            0x0400_0893, // addi a7, x0, 64
+            0x0000_0073, // ecall
+            0x0005_0293, // addi t0, a0, 0
+            0x0070_0513, // addi a0, x0, 7
+            0x05D0_0893, // addi a7, x0, 93
+            0x0000_0073, // ecall
+        ];
+
+        let mut memory = Memory::with_size(0x200);
+        for (idx, inst) in program.iter().enumerate() {
+            memory.write(idx as u32 * 4, *inst);
+        }
+        for (idx, byte) in b"hello".iter().enumerate() {
+            memory.write(0x100 + idx as u32, *byte);
+        }
+
+        let output = Output::default();
+        let mut cpu = Cpu::<RV32I>::new(memory);
+        cpu.set_environment_call(Newlib::new(output.clone()));
+        cpu.run().unwrap();
+
+        assert_eq!(&*output.0.borrow(), b"hello");
+        assert_eq!(cpu.registers().read_x(XRegister::from(5)), 5);
+        assert_eq!(cpu.registers().read_x(XRegister::from(10)), 7);
+        assert_eq!(cpu.registers().pc(), 32);
+    }
+}
@@ -0,0 +1,545 @@
+//! Execution of decoded instructions.
+
+use super::{Cpu, CsrRegister, Hooks, PrivilegeMode, XRegister};
+use crate::{
+    instruction::{Instruction, Kind, Variant},
+    memory::{AccessMode, VirtAddr},
+    trap::Exception,
+    Address, Base,
+};
+
+/// Truncates the given value to `XLEN` bits.
+fn truncate<B: Base>(val: u64) -> u64 {
+    if B::XLEN == 32 {
+        val & 0xFFFF_FFFF
+    } else {
+        val
+    }
+}
+
+/// Interprets the lower `XLEN` bits of `val` as a signed integer.
+fn signed<B: Base>(val: u64) -> i64 {
+    if B::XLEN == 32 {
+        i64::from(val as i32)
+    } else {
+        val as i64
+    }
+}
+
+/// Returns the shift amount that is encoded in `val`.
+fn shamt<B: Base>(val: u64) -> u64 {
+    val & (B::XLEN as u64 - 1)
+}
+
+/// Sign extends the lower 32 bits of `val` to 64 bits.
+fn sign_extend_word(val: u64) -> u64 {
+    i64::from(val as i32) as u64
+}
+
+/// Executes one of the high multiplication instructions, which return the
+/// upper `XLEN` bits of the `2 * XLEN` bit product.
+///
+/// `MULHSU` multiplies the signed `a` with the unsigned `b`.
+fn multiply_high<B: Base>(kind: Kind, a: u64, b: u64) -> u64 {
+    let (sa, sb) = (i128::from(signed::<B>(a)), i128::from(signed::<B>(b)));
+    let (ua, ub) = (u128::from(truncate::<B>(a)), u128::from(truncate::<B>(b)));
+
+    match kind {
+        Kind::MULH => ((sa * sb) >> B::XLEN) as u64,
+        Kind::MULHSU => ((sa * ub as i128) >> B::XLEN) as u64,
+        Kind::MULHU => ((ua * ub) >> B::XLEN) as u64,
+        _ => unreachable!("{} is not a high multiplication", kind),
+    }
+}
+
+/// Executes one of the division or remainder instructions.
+///
+/// Rust panics on a division by zero, and on the overflow of `MIN / -1`,
+/// so these cases explicitly return the results that are defined by the spec:
+/// A division by zero returns all ones, and it's remainder is the dividend.
+/// The overflow returns the dividend, and a remainder of zero.
+fn divide<B: Base>(kind: Kind, a: u64, b: u64) -> u64 {
+    let (sa, sb) = (signed::<B>(a), signed::<B>(b));
+    let (ua, ub) = (truncate::<B>(a), truncate::<B>(b));
+    let (wa, wb) = (a as i32, b as i32);
+
+    match kind {
+        Kind::DIV if sb == 0 => u64::MAX,
+        Kind::DIV => sa.wrapping_div(sb) as u64,
+        Kind::DIVU => ua.checked_div(ub).unwrap_or(u64::MAX),
+        Kind::REM if sb == 0 => a,
+        Kind::REM => sa.wrapping_rem(sb) as u64,
+        Kind::REMU => ua.checked_rem(ub).unwrap_or(ua),
+
+        Kind::DIVW if wb == 0 => u64::MAX,
+        Kind::DIVW => i64::from(wa.wrapping_div(wb)) as u64,
+        Kind::DIVUW => (a as u32)
+            .checked_div(b as u32)
+            .map_or(u64::MAX, |val| sign_extend_word(u64::from(val))),
+        Kind::REMW if wb == 0 => sign_extend_word(a),
+        Kind::REMW => i64::from(wa.wrapping_rem(wb)) as u64,
+        Kind::REMUW => sign_extend_word(u64::from(
+            (a as u32).checked_rem(b as u32).unwrap_or(a as u32),
+        )),
+
+        _ => unreachable!("{} is not a division", kind),
+    }
+}
+
+impl<B: Base, H: Hooks<B>> Cpu<B, H> {
+    fn x(&self, reg: XRegister) -> u64 {
+        self.registers.read_x(reg).to_u64()
+    }
+
+    fn set_x(&self, reg: XRegister, val: u64) {
+        self.registers
+            .write_x(reg, B::Addr::from_u64(truncate::<B>(val)));
+    }
+
+    /// Loads the integer of `width` bytes at the virtual address `addr`.
+    fn load(&mut self, addr: u64, width: usize, signed: bool) -> Result<u64, Exception> {
+        if addr % width as u64 != 0 {
+            if !self.misaligned_access {
+                return Err(self.address_fault(addr, Exception::LoadAddressMisaligned));
+            }
+
+            let phys = self.translate_bytes(addr, width, AccessMode::Read)?;
+            let mut val = 0;
+            for (idx, byte) in phys[..width].iter().enumerate() {
+                let byte = self
+                    .memory
+                    .read_int(*byte, 1, false)
+                    .map_err(|err| self.address_fault(addr, err))?;
+                val |= byte.to_u64() << (idx * 8);
+            }
+
+            let shift = 64 - width * 8;
+            return Ok(if signed {
+                ((val << shift) as i64 >> shift) as u64
+            } else {
+                val
+            });
+        }
+
+        let phys = self.translate(addr, AccessMode::Read)?;
+        self.memory
+            .read_int(phys, width, signed)
+            .map(|val| val.to_u64())
+            .map_err(|err| self.address_fault(addr, err))
+    }
+
+    /// Stores the lower `width` bytes of `value` at the virtual address `addr`.
+    fn store(&mut self, addr: u64, width: usize, value: u64) -> Result<(), Exception> {
+        if addr % width as u64 == 0 {
+            let phys = self.translate(addr, AccessMode::Write)?;
+            self.memory
+                .write_int(phys, width, B::Addr::from_u64(truncate::<B>(value)))
+                .map_err(|err| self.address_fault(addr, err))?;
+        } else {
+            if !self.misaligned_access {
+                return Err(self.address_fault(addr, Exception::StoreAddressMisaligned));
+            }
+
+            let phys = self.translate_bytes(addr, width, AccessMode::Write)?;
+            for (idx, byte) in phys[..width].iter().enumerate() {
+                let val = B::Addr::from_u64((value >> (idx * 8)) & 0xFF);
+                self.memory
+                    .write_int(*byte, 1, val)
+                    .map_err(|err| self.address_fault(addr, err))?;
+            }
+        }
+
+        // A misaligned store may touch two doublewords.
+        let last = truncate::<B>(addr.wrapping_add(width as u64 - 1));
+        let stored = (addr & !7, last & !7);
+        self.invalidate_reservation(stored);
+        self.stored = Some(stored);
+        Ok(())
+    }
+
+    /// Returns the first and last doubleword, that were written by the last store,
+    /// if there was a store since the last call.
+    pub(crate) fn take_stored(&mut self) -> Option<(u64, u64)> {
+        self.stored.take()
+    }
+
+    /// Clears the reservation of the last `LR` instruction, if it's one
+    /// of the given doublewords, that were written by a store.
+    pub(crate) fn invalidate_reservation(&mut self, (first, last): (u64, u64)) {
+        if self.reservation == Some(first) || self.reservation == Some(last) {
+            self.reservation = None;
+        }
+    }
+
+    /// Translates every byte of a misaligned access of `width` bytes at `addr`,
+    /// which may cross a page boundary.
+    ///
+    /// All bytes are translated up front, so that no byte is accessed
+    /// if the translation of any of them fails.
+    fn translate_bytes(
+        &mut self,
+        addr: u64,
+        width: usize,
+        access: AccessMode,
+    ) -> Result<[B::Addr; 8], Exception> {
+        let mut phys = [B::Addr::from_u64(0); 8];
+        for (idx, byte) in phys[..width].iter_mut().enumerate() {
+            *byte = self.translate(truncate::<B>(addr.wrapping_add(idx as u64)), access)?;
+        }
+        Ok(phys)
+    }
+
+    /// Executes an atomic memory operation on the integer of `width` bytes at `addr`,
+    /// and returns the original value, which is sign extended if it's a word.
+    fn atomic(&mut self, kind: Kind, addr: u64, width: usize, src: u64) -> Result<u64, Exception> {
+        // Faults of AMOs are always reported as store faults.
+        if addr % width as u64 != 0 {
+            return Err(self.address_fault(addr, Exception::StoreAddressMisaligned));
+        }
+
+        let phys = self.translate(addr, AccessMode::Write)?;
+        let old = self
+            .memory
+            .read_int(phys, width, false)
+            .map_err(|_| self.address_fault(addr, Exception::StoreAccessFault))?
+            .to_u64();
+
+        let (old, src, mask) = if width == 4 {
+            (sign_extend_word(old), sign_extend_word(src), 0xFFFF_FFFF)
+        } else {
+            (old, src, u64::MAX)
+        };
+
+        let new = match kind {
+            Kind::AMOSWAP_W | Kind::AMOSWAP_D => src,
+            Kind::AMOADD_W | Kind::AMOADD_D => old.wrapping_add(src),
+            Kind::AMOXOR_W | Kind::AMOXOR_D => old ^ src,
+            Kind::AMOAND_W | Kind::AMOAND_D => old & src,
+            Kind::AMOOR_W | Kind::AMOOR_D => old | src,
+            Kind::AMOMIN_W | Kind::AMOMIN_D => (old as i64).min(src as i64) as u64,
+            Kind::AMOMAX_W | Kind::AMOMAX_D => (old as i64).max(src as i64) as u64,
+            Kind::AMOMINU_W | Kind::AMOMINU_D => (old & mask).min(src & mask),
+            _ => (old & mask).max(src & mask),
+        };
+
+        self.store(addr, width, new)?;
+        Ok(old)
+    }
+
+    /// Executes a load-reserved instruction, and returns the loaded value,
+    /// which is sign extended if it's a word.
+    fn load_reserved(&mut self, addr: u64, width: usize) -> Result<u64, Exception> {
+        // Misaligned LR/SC sequences are never split, even if misaligned accesses are enabled.
+        if addr % width as u64 != 0 {
+            return Err(self.address_fault(addr, Exception::LoadAddressMisaligned));
+        }
+
+        let val = self.load(addr, width, width == 4)?;
+        self.reservation = Some(addr & !7);
+        Ok(val)
+    }
+
+    /// Executes a store-conditional instruction, and returns
+    /// `0` if the store succeeded, and `1` if it failed.
+    fn store_conditional(&mut self, addr: u64, width: usize, val: u64) -> Result<u64, Exception> {
+        if addr % width as u64 != 0 {
+            return Err(self.address_fault(addr, Exception::StoreAddressMisaligned));
+        }
+
+        if self.reservation.take() != Some(addr & !7) {
+            return Ok(1);
+        }
+
+        self.store(addr, width, val)?;
+        Ok(0)
+    }
+
+    /// Executes one of the CSR instructions, and returns the value
+    /// that is written into `rd`.
+    ///
+    /// The CSR is only read if the instruction writes `rd`, or if the CSR
+    /// is modified by setting or clearing bits. It's only written if
+    /// `rs1` is not `x0`, or if it's a `CSRRW[I]` instruction.
+    fn execute_csr(
+        &self,
+        kind: Kind,
+        reg: CsrRegister,
+        rd: XRegister,
+        rs1: XRegister,
+    ) -> Result<u64, Exception> {
+        let src = match kind {
+            Kind::CSRRWI | Kind::CSRRSI | Kind::CSRRCI => u64::from(u8::from(rs1)),
+            _ => self.x(rs1),
+        };
+
+        let (read, write) = match kind {
+            Kind::CSRRW | Kind::CSRRWI => (rd != XRegister::from(0), true),
+            _ => (true, rs1 != XRegister::from(0)),
+        };
+
+        self.registers.access_csr(reg, self.privilege, write)?;
+
+        let old = if read {
+            self.registers.read_csr(reg).to_u64()
+        } else {
+            0
+        };
+
+        if write {
+            let new = match kind {
+                Kind::CSRRW | Kind::CSRRWI => src,
+                Kind::CSRRS | Kind::CSRRSI => old | src,
+                _ => old & !src,
+            };
+            self.registers
+                .write_csr(reg, B::Addr::from_u64(truncate::<B>(new)));
+        }
+
+        Ok(old)
+    }
+
+    /// Executes the given instruction, and advances the program counter
+    /// if the instruction didn't raise an exception.
+    #[allow(clippy::similar_names)]
+    pub(super) fn execute(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let pc = self.registers.pc().to_u64();
+        let mut next_pc = truncate::<B>(pc.wrapping_add(inst.size() as u64));
+
+        match inst.variant {
+            Variant::R { rd, rs1, rs2 } => {
+                let (a, b) = (self.x(rs1), self.x(rs2));
+
+                let val = match inst.kind {
+                    Kind::ADD => a.wrapping_add(b),
+                    Kind::SUB => a.wrapping_sub(b),
+                    Kind::SLL => a << shamt::<B>(b),
+                    Kind::SLT => (signed::<B>(a) < signed::<B>(b)) as u64,
+                    Kind::SLTU => (a < b) as u64,
+                    Kind::XOR => a ^ b,
+                    Kind::SRL => a >> shamt::<B>(b),
+                    Kind::SRA => (signed::<B>(a) >> shamt::<B>(b)) as u64,
+                    Kind::OR => a | b,
+                    Kind::AND => a & b,
+
+                    Kind::ADDW => sign_extend_word(a.wrapping_add(b)),
+                    Kind::SUBW => sign_extend_word(a.wrapping_sub(b)),
+                    Kind::SLLW => sign_extend_word(u64::from((a as u32) << (b & 0x1F))),
+                    Kind::SRLW => sign_extend_word(u64::from((a as u32) >> (b & 0x1F))),
+                    Kind::SRAW => i64::from((a as i32) >> (b & 0x1F)) as u64,
+
+                    Kind::MUL => a.wrapping_mul(b),
+                    Kind::MULH | Kind::MULHSU | Kind::MULHU => multiply_high::<B>(inst.kind, a, b),
+                    Kind::MULW => sign_extend_word(a.wrapping_mul(b)),
+                    Kind::DIV
+                    | Kind::DIVU
+                    | Kind::REM
+                    | Kind::REMU
+                    | Kind::DIVW
+                    | Kind::DIVUW
+                    | Kind::REMW
+                    | Kind::REMUW => divide::<B>(inst.kind, a, b),
+
+                    Kind::LR_W => self.load_reserved(a, 4)?,
+                    Kind::LR_D => self.load_reserved(a, 8)?,
+                    Kind::SC_W => self.store_conditional(a, 4, b)?,
+                    Kind::SC_D => self.store_conditional(a, 8, b)?,
+
+                    Kind::AMOSWAP_W
+                    | Kind::AMOADD_W
+                    | Kind::AMOXOR_W
+                    | Kind::AMOAND_W
+                    | Kind::AMOOR_W
+                    | Kind::AMOMIN_W
+                    | Kind::AMOMAX_W
+                    | Kind::AMOMINU_W
+                    | Kind::AMOMAXU_W => self.atomic(inst.kind, a, 4, b)?,
+                    Kind::AMOSWAP_D
+                    | Kind::AMOADD_D
+                    | Kind::AMOXOR_D
+                    | Kind::AMOAND_D
+                    | Kind::AMOOR_D
+                    | Kind::AMOMIN_D
+                    | Kind::AMOMAX_D
+                    | Kind::AMOMINU_D
+                    | Kind::AMOMAXU_D => self.atomic(inst.kind, a, 8, b)?,
+
+                    Kind::NTL_P1 | Kind::NTL_PALL | Kind::NTL_S1 | Kind::NTL_ALL => {
+                        *self.registers.pc_mut() = B::Addr::from_u64(next_pc);
+                        return Ok(());
+                    }
+
+                    Kind::SFENCE_VMA => {
+                        if self.privilege < PrivilegeMode::Supervisor {
+                            return Err(Exception::IllegalInstruction);
+                        }
+
+                        // `x0` as an operand means all addresses, or all address spaces.
+                        let vaddr = (rs1 != XRegister::from(0)).then(|| VirtAddr::from(a));
+                        let asid = (rs2 != XRegister::from(0)).then(|| b as u16);
+                        self.mmu.flush_tlb(vaddr, asid);
+
+                        *self.registers.pc_mut() = B::Addr::from_u64(next_pc);
+                        return Ok(());
+                    }
+
+                    _ => return Err(Exception::IllegalInstruction),
+                };
+
+                self.set_x(rd, val);
+            }
+
+            Variant::I { val: imm, rd, rs1 } => {
+                let a = self.x(rs1);
+                let addr = truncate::<B>(a.wrapping_add(imm as u64));
+
+                let val = match inst.kind {
+                    Kind::ADDI => a.wrapping_add(imm as u64),
+                    Kind::SLTI => (signed::<B>(a) < i64::from(imm)) as u64,
+                    Kind::SLTIU => (a < truncate::<B>(imm as u64)) as u64,
+                    Kind::XORI => a ^ imm as u64,
+                    Kind::ORI => a | imm as u64,
+                    Kind::ANDI => a & imm as u64,
+                    Kind::SLLI => a << imm,
+                    Kind::SRLI => a >> imm,
+                    Kind::SRAI => (signed::<B>(a) >> imm) as u64,
+
+                    Kind::ADDIW => sign_extend_word(a.wrapping_add(imm as u64)),
+                    Kind::SLLIW => sign_extend_word(u64::from((a as u32) << imm)),
+                    Kind::SRLIW => sign_extend_word(u64::from((a as u32) >> imm)),
+                    Kind::SRAIW => i64::from((a as i32) >> imm) as u64,
+
+                    Kind::LB => self.load(addr, 1, true)?,
+                    Kind::LH => self.load(addr, 2, true)?,
+                    Kind::LW => self.load(addr, 4, true)?,
+                    Kind::LD => self.load(addr, 8, false)?,
+                    Kind::LBU => self.load(addr, 1, false)?,
+                    Kind::LHU => self.load(addr, 2, false)?,
+                    Kind::LWU => self.load(addr, 4, false)?,
+
+                    Kind::JALR => {
+                        let target = addr & !1;
+                        if target % self.instruction_alignment() != 0 {
+                            return Err(
+                                self.address_fault(target, Exception::InstructionAddressMisaligned)
+                            );
+                        }
+
+                        let link = next_pc;
+                        next_pc = target;
+                        link
+                    }
+
+                    Kind::FENCE | Kind::FENCE_I | Kind::PAUSE => {
+                        if let Kind::FENCE_I = inst.kind {
+                            self.flush_icache();
+                        }
+
+                        *self.registers.pc_mut() = B::Addr::from_u64(next_pc);
+                        return Ok(());
+                    }
+                    Kind::ECALL => {
+                        if !self.environment_call() {
+                            return Err(match self.privilege {
+                                PrivilegeMode::User => Exception::UserModeEnvironmentCall,
+                                PrivilegeMode::Supervisor => {
+                                    Exception::SupervisorModeEnvironmentCall
+                                }
+                                _ => Exception::MachineModeEnvironmentCall,
+                            });
+                        }
+
+                        *self.registers.pc_mut() = B::Addr::from_u64(next_pc);
+                        return Ok(());
+                    }
+                    Kind::EBREAK => return Err(Exception::Breakpoint),
+                    Kind::MRET => return self.mret(),
+
+                    Kind::CSRRW
+                    | Kind::CSRRS
+                    | Kind::CSRRC
+                    | Kind::CSRRWI
+                    | Kind::CSRRSI
+                    | Kind::CSRRCI => {
+                        self.execute_csr(inst.kind, CsrRegister::from(imm as u16), rd, rs1)?
+                    }
+                    Kind::SRET => return self.sret(),
+                    Kind::WFI => {
+                        if self.privilege < PrivilegeMode::Machine && self.registers.mstatus().tw()
+                        {
+                            return Err(Exception::IllegalInstruction);
+                        }
+
+                        self.waiting = !self.interrupt_pending();
+                        *self.registers.pc_mut() = B::Addr::from_u64(next_pc);
+                        return Ok(());
+                    }
+
+                    _ => return Err(Exception::IllegalInstruction),
+                };
+
+                self.set_x(rd, val);
+            }
+
+            Variant::S { val: imm, rs1, rs2 } => {
+                let addr = truncate::<B>(self.x(rs1).wrapping_add(imm as u64));
+                let val = self.x(rs2);
+
+                match inst.kind {
+                    Kind::SB => self.store(addr, 1, val)?,
+                    Kind::SH => self.store(addr, 2, val)?,
+                    Kind::SW => self.store(addr, 4, val)?,
+                    Kind::SD => self.store(addr, 8, val)?,
+                    _ => return Err(Exception::IllegalInstruction),
+                }
+            }
+
+            Variant::B { val: imm, rs1, rs2 } => {
+                let (a, b) = (self.x(rs1), self.x(rs2));
+
+                let taken = match inst.kind {
+                    Kind::BEQ => a == b,
+                    Kind::BNE => a != b,
+                    Kind::BLT => signed::<B>(a) < signed::<B>(b),
+                    Kind::BGE => signed::<B>(a) >= signed::<B>(b),
+                    Kind::BLTU => a < b,
+                    Kind::BGEU => a >= b,
+                    _ => return Err(Exception::IllegalInstruction),
+                };
+
+                if taken {
+                    let target = truncate::<B>(pc.wrapping_add(imm as u64));
+                    if target % self.instruction_alignment() != 0 {
+                        return Err(
+                            self.address_fault(target, Exception::InstructionAddressMisaligned)
+                        );
+                    }
+                    next_pc = target;
+                }
+            }
+
+            Variant::U { val: imm, rd } => {
+                // Converting the `i32` sign extends the immediate from bit 31 on RV64.
+                let val = match inst.kind {
+                    Kind::LUI => imm as u64,
+                    Kind::AUIPC => pc.wrapping_add(imm as u64),
+                    _ => return Err(Exception::IllegalInstruction),
+                };
+
+                self.set_x(rd, val);
+            }
+
+            Variant::J { val: imm, rd } => {
+                let target = truncate::<B>(pc.wrapping_add(imm as u64));
+                if target % self.instruction_alignment() != 0 {
+                    return Err(self.address_fault(target, Exception::InstructionAddressMisaligned));
+                }
+
+                self.set_x(rd, next_pc);
+                next_pc = target;
+            }
+        }
+
+        *self.registers.pc_mut() = B::Addr::from_u64(next_pc);
+        Ok(())
+    }
+}
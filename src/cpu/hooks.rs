@@ -0,0 +1,74 @@
+use crate::{instruction::Instruction, trap::Interrupt, Base};
+
+/// Callbacks that are invoked by the [`Cpu`](super::Cpu) while it
+/// executes instructions.
+///
+/// Every hook is checked inside the hot path of the interpreter.
+/// A `Hooks` implementation can set [`ENABLED`](Hooks::ENABLED) to `false`,
+/// which removes all hook invocations at compile time.
+pub trait Hooks<B: Base> {
+    /// Whether the CPU should invoke the hooks at all.
+    const ENABLED: bool = true;
+
+    /// Called before the given instruction at `pc` gets executed.
+    fn before_execute(&mut self, _pc: B::Addr, _inst: &Instruction) {}
+
+    /// Called if the `source` line of `device` asserts the interrupt `int`.
+    ///
+    /// Returns the interrupt that is actually delivered to the CPU,
+    /// or `None` if the interrupt should be dropped.
+    fn on_interrupt(&mut self, _device: &str, _source: u32, int: Interrupt) -> Option<Interrupt> {
+        Some(int)
+    }
+}
+
+/// A [`Hooks`] implementation that doesn't do anything.
+///
+/// Using this for a CPU will produce a minimal interpreter,
+/// without any overhead for hooks.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoHooks;
+
+impl<B: Base> Hooks<B> for NoHooks {
+    const ENABLED: bool = false;
+}
+
+/// A hook that is called with the `pc` and the instruction
+/// that is about to be executed.
+pub type StepHook<B> = Box<dyn FnMut(<B as Base>::Addr, &Instruction)>;
+
+/// A hook that is called with the device, source line and interrupt
+/// that is asserted, and returns the interrupt that will be delivered.
+pub type InterruptHook = Box<dyn FnMut(&str, u32, Interrupt) -> Option<Interrupt>>;
+
+/// The default [`Hooks`] of a CPU, which can be configured at runtime.
+#[allow(clippy::module_name_repetitions)]
+pub struct DynHooks<B: Base> {
+    pub(crate) step: Option<StepHook<B>>,
+    pub(crate) interrupt: Option<InterruptHook>,
+}
+
+impl<B: Base> Default for DynHooks<B> {
+    fn default() -> Self {
+        Self {
+            step: None,
+            interrupt: None,
+        }
+    }
+}
+
+impl<B: Base> Hooks<B> for DynHooks<B> {
+    fn before_execute(&mut self, pc: B::Addr, inst: &Instruction) {
+        if let Some(hook) = &mut self.step {
+            hook(pc, inst);
+        }
+    }
+
+    fn on_interrupt(&mut self, device: &str, source: u32, int: Interrupt) -> Option<Interrupt> {
+        match &mut self.interrupt {
+            Some(hook) => hook(device, source, int),
+            None => Some(int),
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! A cache for decoded instructions.
+
+use super::{Cpu, Hooks};
+use crate::{instruction::Instruction, Base};
+
+/// The number of hits and misses of the instruction cache.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    /// The number of instructions that didn't have to be decoded.
+    pub hits: u64,
+    /// The number of instructions that were decoded and inserted into the cache.
+    pub misses: u64,
+}
+
+/// A direct-mapped cache, that maps the address of an instruction
+/// to the decoded instruction.
+///
+/// Every entry also stores the raw instruction, and a lookup only hits if the raw
+/// instruction still matches the fetched one. This way, an entry is invalidated
+/// as soon as the memory at it's address is written, which is required to
+/// support self-modifying code.
+pub(super) struct InstructionCache {
+    entries: Box<[Option<(u64, Instruction)>]>,
+    stats: CacheStats,
+}
+
+impl InstructionCache {
+    /// Creates a new cache, that holds up to `size` instructions.
+    ///
+    /// `size` is rounded up to the next power of two.
+    pub(super) fn new(size: usize) -> Self {
+        Self {
+            entries: vec![None; size.next_power_of_two()].into_boxed_slice(),
+            stats: CacheStats::default(),
+        }
+    }
+
+    fn index(&self, pc: u64) -> usize {
+        (pc >> 1) as usize & (self.entries.len() - 1)
+    }
+
+    /// Returns the cached instruction at `pc`, if it was decoded from `raw`.
+    pub(super) fn get(&mut self, pc: u64, raw: u32) -> Option<Instruction> {
+        match self.entries[self.index(pc)] {
+            Some((addr, inst)) if addr == pc && inst.raw == raw => {
+                self.stats.hits += 1;
+                Some(inst)
+            }
+            _ => None,
+        }
+    }
+
+    /// Inserts the instruction at `pc`, replacing the entry that is currently
+    /// stored in it's slot.
+    pub(super) fn insert(&mut self, pc: u64, inst: Instruction) {
+        self.stats.misses += 1;
+        let idx = self.index(pc);
+        self.entries[idx] = Some((pc, inst));
+    }
+
+    fn flush(&mut self) {
+        self.entries.iter_mut().for_each(|entry| *entry = None);
+    }
+}
+
+impl<B: Base, H: Hooks<B>> Cpu<B, H> {
+    /// Enables the instruction cache, which holds up to `size` decoded instructions.
+    ///
+    /// Passing `0` disables the cache.
+    pub fn set_icache_size(&mut self, size: usize) {
+        self.icache = (size > 0).then(|| InstructionCache::new(size));
+    }
+
+    /// Removes every instruction from the instruction cache.
+    ///
+    /// This is done automatically when a `FENCE.I` instruction is executed.
+    pub fn flush_icache(&mut self) {
+        if let Some(cache) = &mut self.icache {
+            cache.flush();
+        }
+    }
+
+    /// Returns the hit statistics of the instruction cache,
+    /// or `None` if the cache is disabled.
+    pub fn icache_stats(&self) -> Option<CacheStats> {
+        self.icache.as_ref().map(|cache| cache.stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cpu::{Cpu, XRegister},
+        memory::Memory,
+        Address, Base, RV32I, RV64I,
+    };
+
+    #[test]
+    fn cache_hits() {
+        let program: &[u32] = &[
+            0x0000_0093, // addi x1, x0, 0
+            0x00A0_0113, // addi x2, x0, 10
+            0x0020_80B3, // add x1, x1, x2
+            0xFFF1_0113, // addi x2, x2, -1
+            0xFE01_1CE3, // bne x2, x0, -8
+            0x0010_0073, // ebreak
+        ];
+
+        let mut memory = Memory::with_size(0x100);
+        for (idx, inst) in program.iter().enumerate() {
+            memory.write(idx as u64 * 4, *inst);
+        }
+
+        let mut cpu = Cpu::<RV64I>::new(memory);
+        assert_eq!(cpu.icache_stats(), None);
+        cpu.set_icache_size(16);
+
+        cpu.run().unwrap();
+        let stats = cpu.icache_stats().unwrap();
+        assert_eq!(cpu.registers().read_x(XRegister::from(1)), 55);
+        assert_eq!(stats.misses, 6);
+        assert_eq!(stats.hits, 27);
+
+        // Self-modifying code: `addi x2, x0, 10` becomes `addi x2, x0, 5`.
+        cpu.memory_mut().write(4_u64, 0x0050_0113_u32);
+        *cpu.registers_mut().pc_mut() = 0;
+        cpu.run().unwrap();
+        assert_eq!(cpu.registers().read_x(XRegister::from(1)), 15);
+
+        let stats = cpu.icache_stats().unwrap();
+        assert_eq!(stats.misses, 7);
+
+        cpu.flush_icache();
+        *cpu.registers_mut().pc_mut() = 0;
+        cpu.run().unwrap();
+        assert_eq!(cpu.icache_stats().unwrap().misses, 13);
+    }
+
+    fn fence_i_on<B: Base>() {
+        let program: &[u32] = &[
+            0x0050_2423, // sw x5, 8(x0)
+            0x0000_100F, // fence.i
+            0x0010_0093, // addi x1, x0, 1
+            0x0010_0073, // ebreak
+        ];
+
+        let mut memory = Memory::<B>::with_size(0x100);
+        for (idx, inst) in program.iter().enumerate() {
+            memory.write(B::Addr::from_u64(idx as u64 * 4), *inst);
+        }
+
+        let mut cpu = Cpu::<B>::new(memory);
+        cpu.set_icache_size(16);
+        let x = |val: u64| B::Addr::from_u64(val);
+        cpu.registers().write_x(XRegister::from(5), x(0x0010_0093));
+        cpu.run().unwrap();
+        assert_eq!(cpu.registers().read_x(XRegister::from(1)), x(1));
+
+        // Patch the instruction after the `FENCE.I` to `addi x1, x0, 2`.
+        cpu.registers().write_x(XRegister::from(5), x(0x0020_0093));
+        *cpu.registers_mut().pc_mut() = x(0);
+        cpu.run().unwrap();
+        assert_eq!(cpu.registers().read_x(XRegister::from(1)), x(2));
+
+        // The `FENCE.I` flushes the whole cache, so every instruction missed both times.
+        assert_eq!(cpu.icache_stats().unwrap().misses, 8);
+    }
+
+    #[test]
+    fn fence_i() {
+        fence_i_on::<RV64I>();
+        fence_i_on::<RV32I>();
+    }
+}
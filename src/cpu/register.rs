@@ -1,7 +1,8 @@
-use crate::Base;
+use super::PrivilegeMode;
+use crate::{trap::Exception, Address, Base, Extensions};
 use derive_more::{Display, From, Into};
 use num_traits::Zero;
-use std::cell::Cell;
+use std::{cell::Cell, fmt};
 
 macro_rules! register_consts {
     ($($name:ident = $val:literal;)*$(,)?) => {
@@ -63,8 +64,81 @@ register_consts! {
     MCAUSE = 0x342;
     MTVAL = 0x343;
     MIP = 0x344;
+
+    MCYCLE = 0xB00;
+    MINSTRET = 0xB02;
+    MCYCLEH = 0xB80;
+    MINSTRETH = 0xB82;
+
+
+    CYCLE = 0xC00;
+    TIME = 0xC01;
+    INSTRET = 0xC02;
+    CYCLEH = 0xC80;
+    TIMEH = 0xC81;
+    INSTRETH = 0xC82;
 }
 
+/// The bits of the fields inside the `mstatus` register.
+///
+/// The `SD` bit is not defined here, because it's position depends on `XLEN`.
+pub mod mstatus {
+    pub const SIE: u64 = 1 << 1;
+    pub const MIE: u64 = 1 << 3;
+    pub const SPIE: u64 = 1 << 5;
+    pub const MPIE: u64 = 1 << 7;
+    pub const SPP_SHIFT: u64 = 8;
+    pub const SPP: u64 = 1 << SPP_SHIFT;
+    pub const MPP_SHIFT: u64 = 11;
+    pub const MPP: u64 = 0b11 << MPP_SHIFT;
+    pub const MPRV: u64 = 1 << 17;
+    pub const SUM: u64 = 1 << 18;
+    pub const MXR: u64 = 1 << 19;
+    pub const TW: u64 = 1 << 21;
+}
+
+/// All bits of `mstatus` that can be written.
+const MSTATUS_WRITE_MASK: u64 = mstatus::SIE
+    | mstatus::MIE
+    | mstatus::SPIE
+    | mstatus::MPIE
+    | mstatus::SPP
+    | mstatus::MPP
+    | mstatus::MPRV
+    | mstatus::SUM
+    | mstatus::MXR
+    | mstatus::TW;
+
+/// All bits of `mstatus` that are visible and writable through `sstatus`.
+const SSTATUS_MASK: u64 = mstatus::SIE | mstatus::SPIE | mstatus::SPP | mstatus::SUM | mstatus::MXR;
+
+/// All bits of `sip` that can be written, which is only the supervisor software interrupt.
+const SIP_WRITE_MASK: u64 = 1 << 1;
+
+/// All bits of `mie` and `mip` that belong to an interrupt.
+const INTERRUPT_MASK: u64 = 0xBBB;
+
+/// All bits of `mideleg` that can be written, which are the supervisor interrupts.
+const MIDELEG_WRITE_MASK: u64 = 0x222;
+
+/// All bits of `medeleg` that can be written.
+///
+/// Environment calls from machine mode can never be delegated.
+const MEDELEG_WRITE_MASK: u64 = 0xB7FF;
+
+/// The extensions that are reported in `misa`.
+///
+/// These are the base ISA, the integer multiplication and division, the atomic
+/// instructions, and the supervisor and user mode.
+pub(crate) const MISA_EXTENSIONS: &[char] = &['A', 'I', 'M', 'S', 'U'];
+
+/// The ABI names of the integer registers.
+const ABI_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
 /// Number of CSR registers.
 pub const CSR_CAPACITY: usize = 4096;
 
@@ -74,6 +148,37 @@ pub const CSR_CAPACITY: usize = 4096;
 #[derive(Debug, Display, Clone, Copy, Hash, Eq, PartialEq, PartialOrd, Ord, From, Into)]
 pub struct XRegister(u8);
 
+impl XRegister {
+    /// Creates a new `XRegister` from the given index,
+    /// or returns `None` if the index is not in the range `0..32`.
+    pub fn new(idx: u8) -> Option<Self> {
+        (idx < 32).then(|| Self(idx))
+    }
+
+    /// Returns the name of this register, that is used by the standard calling convention.
+    pub fn abi_name(self) -> &'static str {
+        ABI_NAMES[self.0 as usize]
+    }
+
+    /// Parses a register name, which is either it's ABI name, `fp`
+    /// as an alias for `s0`, or `xN` where `N` is the index.
+    pub fn from_name(name: &str) -> Option<Self> {
+        if name == "fp" {
+            return Some(Self(8));
+        }
+
+        if let Some(idx) = ABI_NAMES.iter().position(|abi| *abi == name) {
+            return Some(Self(idx as u8));
+        }
+
+        let idx = name.strip_prefix('x')?;
+        if idx.len() > 1 && idx.starts_with('0') {
+            return None;
+        }
+        Self::new(idx.parse().ok()?)
+    }
+}
+
 /// Represents the number of a CSR register.
 #[allow(clippy::module_name_repetitions)]
 #[repr(transparent)]
@@ -93,28 +198,157 @@ pub struct CsrRegister(u16);
 ///
 /// The `x0` register is hardwired to zero and will ignore any writes.
 /// `x1`-`x31` are general purpose registers
-#[derive(Default)]
 pub struct Registers<B: Base> {
     /// The x registers, or integer registers.
     ///
-    /// We only hold 31 registers here, because the `x0` register is hardcoded
-    /// in the read / write methods.
-    xregs: Box<[Cell<B::Addr>]>,
+    /// They are stored inline, to avoid the indirection in the interpreter loop.
+    /// `x0` is always zero, because writes to it are ignored.
+    xregs: Cell<[B::Addr; 32]>,
     /// The list of control and status registers.
     csr: Box<[Cell<B::Addr>]>,
+    /// The number of cycles that were executed.
+    ///
+    /// This is also used as the `time` counter.
+    cycle: Cell<u64>,
+    /// The number of instructions that were retired.
+    instret: Cell<u64>,
     /// The current program counter.
     pc: B::Addr,
 }
 
+impl<B: Base> Clone for Registers<B> {
+    fn clone(&self) -> Self {
+        Self {
+            xregs: self.xregs.clone(),
+            csr: self.csr.clone(),
+            cycle: self.cycle.clone(),
+            instret: self.instret.clone(),
+            pc: self.pc,
+        }
+    }
+}
+
+/// The serialized form of the [`Registers`], which only
+/// contains the CSRs that are not zero.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct RegistersState {
+    xregs: Vec<u64>,
+    csrs: Vec<(u16, u64)>,
+    cycle: u64,
+    instret: u64,
+    pc: u64,
+}
+
+#[cfg(feature = "serde")]
+impl<B: Base> serde::Serialize for Registers<B> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let csrs = self
+            .csr
+            .iter()
+            .enumerate()
+            .map(|(idx, val)| (idx as u16, val.get().to_u64()))
+            .filter(|&(_, val)| val != 0)
+            .collect();
+
+        RegistersState {
+            xregs: self.xregs.get()[1..]
+                .iter()
+                .map(|reg| reg.to_u64())
+                .collect(),
+            csrs,
+            cycle: self.cycle.get(),
+            instret: self.instret.get(),
+            pc: self.pc.to_u64(),
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, B: Base> serde::Deserialize<'de> for Registers<B> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let state = RegistersState::deserialize(deserializer)?;
+        let value = |val: u64| {
+            if B::XLEN == 64 || val >> 32 == 0 {
+                Ok(B::Addr::from_u64(val))
+            } else {
+                Err(D::Error::custom("register value doesn't fit into XLEN"))
+            }
+        };
+
+        if state.xregs.len() != 31 {
+            return Err(D::Error::invalid_length(state.xregs.len(), &"31 registers"));
+        }
+
+        let regs = Self::new();
+        for (idx, val) in state.xregs.into_iter().enumerate() {
+            regs.write_x(XRegister::from(idx as u8 + 1), value(val)?);
+        }
+        for reg in regs.csr.iter() {
+            reg.set(B::Addr::zero());
+        }
+        for (idx, val) in state.csrs {
+            let reg = regs
+                .csr
+                .get(idx as usize)
+                .ok_or_else(|| D::Error::custom("invalid CSR number"))?;
+            reg.set(value(val)?);
+        }
+        regs.cycle.set(state.cycle);
+        regs.instret.set(state.instret);
+
+        Ok(Self {
+            pc: value(state.pc)?,
+            ..regs
+        })
+    }
+}
+
+impl<B: Base> Default for Registers<B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<B: Base> Registers<B> {
     /// Creates a new `Registers` struct, with all registers set to 0.
+    ///
+    /// The only exception is `misa`, which is initialized to the base ISA
+    /// and the supported extensions.
     pub fn new() -> Self {
-        Self {
-            xregs: vec![Cell::new(B::Addr::zero()); 31].into_boxed_slice(),
-            // TODO: Initialize special register, like `misa`
+        let regs = Self {
+            xregs: Cell::new([B::Addr::zero(); 32]),
             csr: vec![Cell::new(B::Addr::zero()); CSR_CAPACITY].into_boxed_slice(),
+            cycle: Cell::new(0),
+            instret: Cell::new(0),
             pc: B::Addr::zero(),
-        }
+        };
+
+        regs.set_extensions(Extensions::default());
+        regs
+    }
+
+    /// Sets the extensions that are reported in `misa`.
+    pub(crate) fn set_extensions(&self, extensions: Extensions) {
+        let mxl: u64 = if B::XLEN == 32 { 1 } else { 2 };
+        let misa = mxl << (B::XLEN - 2) | u64::from(extensions.bits());
+        // `misa` is read-only, so it can't be written using `write_csr`.
+        self.csr[csr::MISA.0 as usize].set(B::Addr::from_u64(misa));
+    }
+
+    /// Writes the arguments of the Linux boot protocol into `a0` and `a1`, which
+    /// are the `mhartid`, and the address of the device tree.
+    pub(crate) fn set_boot_arguments(&self, dtb: B::Addr) {
+        self.write_x(XRegister::from(10), self.read_csr(csr::MHARTID));
+        self.write_x(XRegister::from(11), dtb);
+    }
+
+    /// Sets the `mhartid` register, which is read-only for the hart itself.
+    pub(crate) fn set_hart_id(&self, id: u64) {
+        self.csr[csr::MHARTID.0 as usize].set(B::Addr::from_u64(id));
     }
 
     /// Returns a copy of the current program counter.
@@ -132,11 +366,7 @@ impl<B: Base> Registers<B> {
     ///
     /// Panics if the given register index is out of bounds.
     pub fn read_x(&self, reg: XRegister) -> B::Addr {
-        if reg.0 == 0 {
-            B::Addr::zero()
-        } else {
-            self.xregs[reg.0 as usize].get()
-        }
+        self.x_cell(reg).get()
     }
 
     /// Writes the given `val` into the integer register `reg`.
@@ -145,24 +375,574 @@ impl<B: Base> Registers<B> {
     /// that is not valid, will cause a panic.
     pub fn write_x(&self, reg: XRegister, val: B::Addr) {
         if reg.0 != 0 {
-            self.xregs[reg.0 as usize].set(val);
+            self.x_cell(reg).set(val);
+        }
+    }
+
+    /// Returns the cell of the integer register `reg`.
+    fn x_cell(&self, reg: XRegister) -> &Cell<B::Addr> {
+        debug_assert!(reg.0 < 32, "invalid register x{}", reg.0);
+        let xregs: &Cell<[B::Addr]> = &self.xregs;
+        // Masking the index allows the compiler to remove the bounds check.
+        &xregs.as_slice_of_cells()[reg.0 as usize & 0x1F]
+    }
+
+    /// Checks if the CSR can be accessed from the given privilege mode,
+    /// and if it can be written, if `write` is set.
+    ///
+    /// The bits 9:8 of the CSR number are the lowest privilege mode that can access
+    /// the CSR, and the CSR is read-only if the bits 11:10 are set. The unprivileged
+    /// counters must additionally be enabled in `mcounteren` to be accessible from
+    /// supervisor mode, and in `mcounteren` and `scounteren` from user mode.
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`Exception::IllegalInstruction`] if the access is not allowed.
+    pub fn access_csr(
+        &self,
+        reg: CsrRegister,
+        privilege: PrivilegeMode,
+        write: bool,
+    ) -> Result<(), Exception> {
+        let num = reg.0;
+        if u64::from(num >> 8 & 0b11) > privilege as u64 || (write && num >> 10 == 0b11) {
+            return Err(Exception::IllegalInstruction);
+        }
+
+        if let 0xC00..=0xC1F | 0xC80..=0xC9F = num {
+            let bit = 1 << (num & 0x1F);
+            let machine = self.read_csr(csr::MCOUNTEREN).to_u64();
+            let supervisor = self.read_csr(csr::SCOUNTEREN).to_u64();
+
+            let enabled = match privilege {
+                PrivilegeMode::Machine => true,
+                PrivilegeMode::Supervisor => machine & bit != 0,
+                _ => machine & supervisor & bit != 0,
+            };
+
+            if !enabled {
+                return Err(Exception::IllegalInstruction);
+            }
         }
+
+        Ok(())
     }
 
     /// Reads a value from CSR register identified by it's number.
+    ///
+    /// The supervisor CSRs `sstatus`, `sie`, and `sip` are restricted views of
+    /// their machine mode counterparts. See [`Registers::write_csr`].
     pub fn read_csr(&self, reg: CsrRegister) -> B::Addr {
-        self.csr[reg.0 as usize].get()
+        if let Some((machine, mask, _)) = self.shadow(reg) {
+            return B::Addr::from_u64(self.read_csr(machine).to_u64() & mask);
+        }
+
+        let (counter, upper) = match self.counter(reg) {
+            Some(counter) => counter,
+            None => return self.csr[reg.0 as usize].get(),
+        };
+
+        let val = match (B::XLEN, upper) {
+            (_, true) => counter.get() >> 32,
+            (32, false) => counter.get() & 0xFFFF_FFFF,
+            _ => counter.get(),
+        };
+        B::Addr::from_u64(val)
     }
 
     /// Writes a value into a CSR register identified by his number.
+    ///
+    /// The value is legalized before it's written, which means that
+    /// read-only fields are kept and illegal values are ignored.
+    ///
+    /// Writing `sstatus`, `sie`, or `sip` only modifies the bits of `mstatus`, `mie`,
+    /// or `mip` that are visible to supervisor mode. For `sie` and `sip` these are the
+    /// interrupts that are delegated in `mideleg`, of which only the software
+    /// interrupt can be written in `sip`.
     pub fn write_csr(&self, reg: CsrRegister, value: B::Addr) {
-        const READ_ONLY_REGS: &[CsrRegister] =
-            &[csr::MVENDORID, csr::MARCHID, csr::MIMPID, csr::MHARTID];
+        if let Some((machine, _, mask)) = self.shadow(reg) {
+            let old = self.read_csr(machine).to_u64();
+            let value = (old & !mask) | (value.to_u64() & mask);
+            return self.write_csr(machine, B::Addr::from_u64(value));
+        }
+
+        let value = match self.legalize_csr(reg, value.to_u64()) {
+            Some(value) => value,
+            None => return,
+        };
 
-        if READ_ONLY_REGS.contains(&reg) {
-            return;
+        if let Some((counter, upper)) = self.counter(reg) {
+            let old = counter.get();
+            counter.set(match (B::XLEN, upper) {
+                (_, true) => (old & 0xFFFF_FFFF) | value << 32,
+                (32, false) => (old & !0xFFFF_FFFF) | value,
+                _ => value,
+            });
+        } else {
+            self.csr[reg.0 as usize].set(B::Addr::from_u64(value));
         }
+    }
+
+    /// Returns the counter that is accessed by the given CSR, and whether
+    /// the upper 32 bits of it are accessed.
+    fn counter(&self, reg: CsrRegister) -> Option<(&Cell<u64>, bool)> {
+        match reg {
+            csr::CYCLE | csr::TIME | csr::MCYCLE => Some((&self.cycle, false)),
+            csr::CYCLEH | csr::TIMEH | csr::MCYCLEH => Some((&self.cycle, true)),
+            csr::INSTRET | csr::MINSTRET => Some((&self.instret, false)),
+            csr::INSTRETH | csr::MINSTRETH => Some((&self.instret, true)),
+            _ => None,
+        }
+    }
+
+    /// Returns the machine mode CSR, whose bits are shadowed by the given
+    /// supervisor CSR, together with the mask of the visible and the writable bits.
+    fn shadow(&self, reg: CsrRegister) -> Option<(CsrRegister, u64, u64)> {
+        let delegated = || self.read_csr(csr::MIDELEG).to_u64();
+
+        match reg {
+            csr::SSTATUS => Some((csr::MSTATUS, SSTATUS_MASK, SSTATUS_MASK)),
+            csr::SIE => Some((csr::MIE, delegated(), delegated())),
+            csr::SIP => Some((csr::MIP, delegated(), delegated() & SIP_WRITE_MASK)),
+            _ => None,
+        }
+    }
+
+    /// Converts `value` into a legal value for the given CSR, by keeping
+    /// all fields that can't be written.
+    ///
+    /// Returns `None` if the write should be ignored completely.
+    fn legalize_csr(&self, reg: CsrRegister, value: u64) -> Option<u64> {
+        let old = self.read_csr(reg).to_u64();
+        let masked = |mask: u64| (old & !mask) | (value & mask);
+
+        let value = match reg {
+            // Machine information registers are read-only, and `misa` is
+            // not writable, to prevent disabling the base ISA.
+            csr::MVENDORID | csr::MARCHID | csr::MIMPID | csr::MHARTID | csr::MISA => return None,
+
+            // The unprivileged counters are read-only shadows of the machine counters.
+            csr::CYCLE | csr::TIME | csr::INSTRET | csr::CYCLEH | csr::TIMEH | csr::INSTRETH => {
+                return None
+            }
+
+            csr::MSTATUS => {
+                let mut status = masked(MSTATUS_WRITE_MASK);
+                // `MPP` can't hold the reserved privilege mode.
+                if (status & mstatus::MPP) >> mstatus::MPP_SHIFT == PrivilegeMode::Reserved as u64 {
+                    status = (status & !mstatus::MPP) | (old & mstatus::MPP);
+                }
+                status
+            }
+
+            csr::MEDELEG => masked(MEDELEG_WRITE_MASK),
+            csr::MIDELEG => masked(MIDELEG_WRITE_MASK),
+            csr::MIE => masked(INTERRUPT_MASK),
+
+            // The modes above `1` are reserved.
+            csr::MTVEC | csr::STVEC if value & 0b11 > 1 => (value & !0b11) | (old & 0b11),
+
+            // Instructions are aligned to 4 bytes, or to 2 bytes if the C extension is enabled.
+            csr::MEPC | csr::SEPC if self.has_extension('C') => value & !0b1,
+            csr::MEPC | csr::SEPC => value & !0b11,
+
+            // Writing an unsupported mode to `satp` has no effect at all.
+            csr::SATP => {
+                let legal = match B::XLEN {
+                    32 => true,
+                    _ => matches!(value >> 60, 0 | 8 | 9),
+                };
+
+                if !legal {
+                    return None;
+                }
+                value
+            }
+
+            _ => value,
+        };
+
+        Some(value)
+    }
+
+    /// Increments the `cycle` counter, and the `instret` counter
+    /// if an instruction was `retired`.
+    pub(crate) fn increment_counters(&self, retired: bool) {
+        self.cycle.set(self.cycle.get().wrapping_add(1));
+        if retired {
+            self.instret.set(self.instret.get().wrapping_add(1));
+        }
+    }
+
+    /// Checks if the given extension, identified by it's letter,
+    /// is enabled in `misa`.
+    pub fn has_extension(&self, ext: char) -> bool {
+        if !ext.is_ascii_uppercase() {
+            return false;
+        }
+
+        let misa = self.read_csr(csr::MISA).to_u64();
+        misa & 1 << (ext as u8 - b'A') != 0
+    }
+
+    /// Returns a typed view of the `mstatus` register.
+    pub fn mstatus(&self) -> Mstatus<'_, B> {
+        Mstatus { registers: self }
+    }
+}
+
+/// Generates a getter and setter for single bit fields of `mstatus`.
+macro_rules! mstatus_bits {
+    ($($(#[$doc:meta])* $get:ident, $set:ident = $mask:path;)*) => {
+        $(
+            $(#[$doc])*
+            pub fn $get(&self) -> bool {
+                self.read() & $mask != 0
+            }
+
+            $(#[$doc])*
+            pub fn $set(&self, val: bool) {
+                self.write_bits($mask, if val { $mask } else { 0 });
+            }
+        )*
+    };
+}
+
+/// A typed view of the `mstatus` register, that reads and writes
+/// the fields directly from and into the [`Registers`].
+pub struct Mstatus<'a, B: Base> {
+    registers: &'a Registers<B>,
+}
+
+impl<B: Base> Mstatus<'_, B> {
+    /// Returns the raw value of `mstatus`.
+    pub fn read(&self) -> u64 {
+        self.registers.read_csr(csr::MSTATUS).to_u64()
+    }
+
+    /// Replaces the bits of `mask` with the bits in `val`.
+    fn write_bits(&self, mask: u64, val: u64) {
+        let status = (self.read() & !mask) | (val & mask);
+        self.registers
+            .write_csr(csr::MSTATUS, B::Addr::from_u64(status));
+    }
+
+    mstatus_bits! {
+        /// The global interrupt-enable bit for supervisor mode.
+        sie, set_sie = mstatus::SIE;
+        /// The global interrupt-enable bit for machine mode.
+        mie, set_mie = mstatus::MIE;
+        /// The interrupt-enable bit that was active prior to the supervisor trap.
+        spie, set_spie = mstatus::SPIE;
+        /// The interrupt-enable bit that was active prior to the machine trap.
+        mpie, set_mpie = mstatus::MPIE;
+        /// Modify the privilege of loads and stores to the mode in `MPP`.
+        mprv, set_mprv = mstatus::MPRV;
+        /// Permit supervisor mode to access user pages.
+        sum, set_sum = mstatus::SUM;
+        /// Make executable pages readable.
+        mxr, set_mxr = mstatus::MXR;
+        /// Timeout wait, which makes `WFI` illegal in modes below machine mode.
+        tw, set_tw = mstatus::TW;
+    }
+
+    /// Returns the privilege mode that was active prior to the supervisor trap.
+    pub fn spp(&self) -> PrivilegeMode {
+        PrivilegeMode::from_bits((self.read() & mstatus::SPP) >> mstatus::SPP_SHIFT)
+    }
+
+    /// Sets the privilege mode that will be active after returning from
+    /// the supervisor trap.
+    ///
+    /// `SPP` is only a single bit, so only user and supervisor
+    /// mode can be stored.
+    pub fn set_spp(&self, mode: PrivilegeMode) {
+        self.write_bits(mstatus::SPP, (mode as u64) << mstatus::SPP_SHIFT);
+    }
+
+    /// Returns the privilege mode that was active prior to the machine trap.
+    pub fn mpp(&self) -> PrivilegeMode {
+        PrivilegeMode::from_bits((self.read() & mstatus::MPP) >> mstatus::MPP_SHIFT)
+    }
+
+    /// Sets the privilege mode that will be active after returning from
+    /// the machine trap.
+    pub fn set_mpp(&self, mode: PrivilegeMode) {
+        self.write_bits(mstatus::MPP, (mode as u64) << mstatus::MPP_SHIFT);
+    }
+
+    /// Returns the `SD` bit, which is the most significant bit of `mstatus`.
+    pub fn sd(&self) -> bool {
+        self.read() >> (B::XLEN - 1) != 0
+    }
+}
+
+/// A copy of the program counter and all integer registers at a single point in time.
+///
+/// The [`Display`](fmt::Display) implementation lists the registers by their
+/// ABI names in four columns, like GDB's `info registers`.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone, Copy)]
+pub struct RegisterSnapshot<B: Base> {
+    /// The program counter.
+    pub pc: B::Addr,
+    /// The integer registers `x0`-`x31`.
+    pub xregs: [B::Addr; 32],
+}
+
+impl<B: Base> fmt::Display for RegisterSnapshot<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = B::XLEN / 4;
+        for (idx, val) in self.xregs.iter().enumerate() {
+            let sep = if idx % 4 == 3 { "\n" } else { "  " };
+            write!(
+                f,
+                "{:>4} 0x{:0width$x}{}",
+                ABI_NAMES[idx],
+                val.to_u64(),
+                sep,
+                width = width
+            )?;
+        }
+        write!(
+            f,
+            "{:>4} 0x{:0width$x}",
+            "pc",
+            self.pc.to_u64(),
+            width = width
+        )
+    }
+}
+
+impl<B: Base> Registers<B> {
+    /// Takes a [`RegisterSnapshot`] of the program counter and all integer registers.
+    pub fn dump(&self) -> RegisterSnapshot<B> {
+        let mut xregs = [B::Addr::zero(); 32];
+        for (idx, val) in xregs.iter_mut().enumerate() {
+            *val = self.read_x(XRegister(idx as u8));
+        }
+
+        RegisterSnapshot { pc: self.pc, xregs }
+    }
+
+    /// Reads the value of every CSR in `csrs`.
+    pub fn dump_csrs(&self, csrs: &[CsrRegister]) -> Vec<(CsrRegister, B::Addr)> {
+        csrs.iter().map(|&reg| (reg, self.read_csr(reg))).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{csr, mstatus, Registers, XRegister};
+    use crate::{cpu::PrivilegeMode, trap::Exception, Extensions, RV32I, RV64I};
+
+    #[test]
+    fn mstatus_fields() {
+        let regs = Registers::<RV64I>::new();
+        let status = regs.mstatus();
+
+        status.set_mpp(PrivilegeMode::Supervisor);
+        assert_eq!(status.mpp(), PrivilegeMode::Supervisor);
+        assert_eq!(regs.read_csr(csr::MSTATUS), 0b01 << 11);
+
+        status.set_spp(PrivilegeMode::Supervisor);
+        status.set_mie(true);
+        status.set_sum(true);
+        assert_eq!(status.spp(), PrivilegeMode::Supervisor);
+        assert!(status.mie() && status.sum());
+        assert!(!status.sie() && !status.mxr());
+        assert_eq!(
+            regs.read_csr(csr::MSTATUS),
+            mstatus::SUM | (0b01 << 11) | mstatus::SPP | mstatus::MIE
+        );
+
+        status.set_mie(false);
+        assert_eq!(regs.read_csr(csr::MSTATUS) & mstatus::MIE, 0);
+    }
+
+    #[test]
+    fn csr_access() {
+        let regs = Registers::<RV64I>::new();
+        let illegal = Err(Exception::IllegalInstruction);
+
+        assert_eq!(
+            regs.access_csr(csr::MSTATUS, PrivilegeMode::Machine, true),
+            Ok(())
+        );
+        assert_eq!(
+            regs.access_csr(csr::MSTATUS, PrivilegeMode::Supervisor, false),
+            illegal
+        );
+        assert_eq!(
+            regs.access_csr(csr::MSTATUS, PrivilegeMode::User, false),
+            illegal
+        );
+        assert_eq!(
+            regs.access_csr(csr::SSTATUS, PrivilegeMode::Supervisor, true),
+            Ok(())
+        );
+
+        // The counters are read-only, even in machine mode.
+        assert_eq!(
+            regs.access_csr(csr::CYCLE, PrivilegeMode::Machine, false),
+            Ok(())
+        );
+        assert_eq!(
+            regs.access_csr(csr::CYCLE, PrivilegeMode::Machine, true),
+            illegal
+        );
+        assert_eq!(
+            regs.access_csr(csr::MHARTID, PrivilegeMode::Machine, true),
+            illegal
+        );
+    }
+
+    #[test]
+    fn supervisor_views() {
+        let regs = Registers::<RV64I>::new();
+
+        regs.write_csr(csr::MSTATUS, mstatus::SIE | mstatus::MIE);
+        assert_eq!(regs.read_csr(csr::SSTATUS), mstatus::SIE);
+
+        // Machine mode fields can't be written through `sstatus`.
+        regs.write_csr(csr::SSTATUS, mstatus::SPP | mstatus::MPRV);
+        assert_eq!(regs.read_csr(csr::MSTATUS), mstatus::MIE | mstatus::SPP);
+        assert_eq!(regs.read_csr(csr::SSTATUS), mstatus::SPP);
+
+        // Only the delegated interrupts are visible through `sie` and `sip`.
+        regs.write_csr(csr::MIDELEG, 1 << 1 | 1 << 5);
+        regs.write_csr(csr::MIE, 1 << 5 | 1 << 7 | 1 << 9);
+        assert_eq!(regs.read_csr(csr::SIE), 1 << 5);
+        regs.write_csr(csr::SIE, 1 << 1 | 1 << 9);
+        assert_eq!(regs.read_csr(csr::MIE), 1 << 1 | 1 << 7 | 1 << 9);
+
+        regs.write_csr(csr::MIP, 1 << 5);
+        regs.write_csr(csr::SIP, 1 << 1);
+        assert_eq!(regs.read_csr(csr::MIP), 1 << 1 | 1 << 5);
+        regs.write_csr(csr::SIP, 0);
+        assert_eq!(regs.read_csr(csr::SIP), 1 << 5);
+    }
+
+    #[test]
+    fn mstatus_sd() {
+        // `SD` is read-only, so the raw value has to be written directly.
+        let regs = Registers::<RV32I>::new();
+        regs.csr[csr::MSTATUS.0 as usize].set(1 << 31);
+        assert!(regs.mstatus().sd());
+
+        let regs = Registers::<RV64I>::new();
+        regs.csr[csr::MSTATUS.0 as usize].set(1 << 31);
+        assert!(!regs.mstatus().sd());
+        regs.csr[csr::MSTATUS.0 as usize].set(1 << 63);
+        assert!(regs.mstatus().sd());
+    }
+
+    #[test]
+    fn legalize_csr_writes() {
+        let regs = Registers::<RV64I>::new();
+
+        // Sv39, Sv48 and bare mode are supported, Sv57 and reserved modes are not.
+        regs.write_csr(csr::SATP, (8 << 60) | 0x1234);
+        assert_eq!(regs.read_csr(csr::SATP), (8 << 60) | 0x1234);
+        regs.write_csr(csr::SATP, (10 << 60) | 0x5678);
+        assert_eq!(regs.read_csr(csr::SATP), (8 << 60) | 0x1234);
+        regs.write_csr(csr::SATP, (3 << 60) | 0x5678);
+        assert_eq!(regs.read_csr(csr::SATP), (8 << 60) | 0x1234);
+        regs.write_csr(csr::SATP, 0);
+        assert_eq!(regs.read_csr(csr::SATP), 0);
+
+        regs.write_csr(csr::MISA, 0);
+        regs.write_csr(csr::MHARTID, 1);
+        assert_eq!(regs.read_csr(csr::MHARTID), 0);
+
+        regs.write_csr(csr::MSTATUS, u64::MAX);
+        assert_eq!(regs.read_csr(csr::MSTATUS), super::MSTATUS_WRITE_MASK);
+        regs.write_csr(csr::MSTATUS, 0b10 << mstatus::MPP_SHIFT);
+        assert_eq!(regs.mstatus().mpp(), PrivilegeMode::Machine);
+
+        regs.write_csr(csr::MEPC, 0x1003);
+        assert_eq!(regs.read_csr(csr::MEPC), 0x1000);
+        regs.set_extensions(Extensions::from_letters(&['C', 'I']).unwrap());
+        regs.write_csr(csr::MEPC, 0x1003);
+        assert_eq!(regs.read_csr(csr::MEPC), 0x1002);
+        regs.write_csr(csr::SEPC, 0x1006);
+        assert_eq!(regs.read_csr(csr::SEPC), 0x1006);
+
+        regs.write_csr(csr::MTVEC, 0x101);
+        regs.write_csr(csr::MTVEC, 0x202);
+        assert_eq!(regs.read_csr(csr::MTVEC), 0x201);
+
+        regs.write_csr(csr::MEDELEG, u64::MAX);
+        assert_eq!(regs.read_csr(csr::MEDELEG) & (1 << 11), 0);
+        regs.write_csr(csr::MIDELEG, u64::MAX);
+        assert_eq!(regs.read_csr(csr::MIDELEG), 0x222);
+    }
+
+    #[test]
+    fn misa() {
+        let regs = Registers::<RV32I>::new();
+        assert_eq!(regs.read_csr(csr::MISA) >> 30, 1);
+        assert!(regs.has_extension('I'));
+        assert!(regs.has_extension('S'));
+        assert!(!regs.has_extension('C'));
+        assert!(!regs.has_extension('i'));
+
+        let regs = Registers::<RV64I>::new();
+        assert_eq!(regs.read_csr(csr::MISA) >> 62, 2);
+        assert_eq!(regs.read_csr(csr::MISA) & 0x3FF_FFFF, 0x14_1101);
+    }
+
+    #[test]
+    fn xregisters() {
+        assert_eq!(XRegister::new(31), Some(XRegister::from(31)));
+        assert_eq!(XRegister::new(32), None);
+
+        let regs = Registers::<RV64I>::new();
+        regs.write_x(XRegister::from(0), 1);
+        regs.write_x(XRegister::from(1), 2);
+        regs.write_x(XRegister::from(31), 3);
+        assert_eq!(regs.read_x(XRegister::from(0)), 0);
+        assert_eq!(regs.read_x(XRegister::from(1)), 2);
+        assert_eq!(regs.read_x(XRegister::from(31)), 3);
+    }
+
+    #[test]
+    fn dump() {
+        let mut regs = Registers::<RV32I>::new();
+        regs.write_x(XRegister::from(10), 0xDEAD);
+        *regs.pc_mut() = 0x100;
+
+        let snapshot = regs.dump();
+        assert_eq!(snapshot.pc, 0x100);
+        assert_eq!(snapshot.xregs[0], 0);
+        assert_eq!(snapshot.xregs[10], 0xDEAD);
+
+        let text = snapshot.to_string();
+        assert!(text.starts_with("zero 0x00000000    ra 0x00000000"));
+        assert!(text.contains("  a0 0x0000dead"));
+        assert!(text.ends_with("  pc 0x00000100"));
+        assert_eq!(text.lines().count(), 9);
+
+        regs.write_csr(csr::MSCRATCH, 7);
+        assert_eq!(
+            regs.dump_csrs(&[csr::MSCRATCH, csr::MEPC]),
+            vec![(csr::MSCRATCH, 7), (csr::MEPC, 0)]
+        );
+    }
+
+    #[test]
+    fn integer_registers() {
+        let regs = Registers::<RV64I>::new();
+        for idx in 0..32 {
+            regs.write_x(XRegister::from(idx), u64::from(idx) + 100);
+        }
+
+        assert_eq!(regs.read_x(XRegister::from(0)), 0);
+        assert_eq!(regs.read_x(XRegister::from(1)), 101);
+        assert_eq!(regs.read_x(XRegister::from(31)), 131);
 
-        self.csr[reg.0 as usize].set(value);
+        let copy = regs.clone();
+        regs.write_x(XRegister::from(31), 0);
+        assert_eq!(copy.read_x(XRegister::from(31)), 131);
     }
 }
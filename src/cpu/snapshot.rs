@@ -0,0 +1,175 @@
+//! Saving and restoring the whole state of a CPU.
+
+use super::{Cpu, Hooks, PrivilegeMode, Registers};
+use crate::{memory::Memory, Base};
+
+/// A copy of the complete architectural state of a [`Cpu`].
+///
+/// If the `serde` feature is enabled, a snapshot can be serialized, which
+/// allows saving it to disk for deterministic replays or test fixtures.
+/// The hooks and environment call handler of the CPU are not part of the snapshot.
+///
+/// The state of the devices is not captured either. Only the RAM is restored,
+/// and the devices that are mapped into the memory of the CPU are kept.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound = "")
+)]
+pub struct Snapshot<B: Base> {
+    registers: Registers<B>,
+    memory: Memory<B>,
+    privilege: PrivilegeMode,
+    reservation: Option<u64>,
+    waiting: bool,
+    halted_at: Option<u64>,
+    modeled_cycles: Option<u64>,
+}
+
+impl<B: Base> Clone for Snapshot<B> {
+    fn clone(&self) -> Self {
+        Self {
+            registers: self.registers.clone(),
+            memory: self.memory.clone(),
+            privilege: self.privilege,
+            reservation: self.reservation,
+            waiting: self.waiting,
+            halted_at: self.halted_at,
+            modeled_cycles: self.modeled_cycles,
+        }
+    }
+}
+
+impl<B: Base, H: Hooks<B>> Cpu<B, H> {
+    /// Takes a [`Snapshot`] of the current state of this CPU.
+    pub fn snapshot(&self) -> Snapshot<B> {
+        Snapshot {
+            registers: self.registers.clone(),
+            memory: self.memory.clone(),
+            privilege: self.privilege,
+            reservation: self.reservation,
+            waiting: self.waiting,
+            halted_at: self.halted_at,
+            modeled_cycles: self.modeled_cycles,
+        }
+    }
+
+    /// Restores the state of this CPU from the given [`Snapshot`].
+    ///
+    /// The RAM is replaced, but the devices of the current memory stay mapped.
+    pub fn restore(&mut self, snapshot: Snapshot<B>) {
+        let Snapshot {
+            registers,
+            memory,
+            privilege,
+            reservation,
+            waiting,
+            halted_at,
+            modeled_cycles,
+        } = snapshot;
+
+        self.registers = registers;
+        self.memory.replace_ram(memory);
+        self.privilege = privilege;
+        self.reservation = reservation;
+        self.waiting = waiting;
+        self.halted_at = halted_at;
+        self.modeled_cycles = modeled_cycles;
+
+        // The page tables may be different in the restored memory.
+        self.mmu.flush_tlb(None, None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cpu::{csr, Cpu, HaltReason, StepOutcome},
+        device::clint::Clint,
+        memory::Memory,
+        Address, RV32I,
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn snapshot_and_restore() {
+        let program: &[u32] = &[
+            0x0000_0093, // addi x1, x0, 0
+            0x0640_0113, // addi x2, x0, 100
+            0x0020_80B3, // add x1, x1, x2
+            0x1011_0023, // sb x1, 256(x2)
+            0xFFF1_0113, // addi x2, x2, -1
+            0xFE01_1AE3, // bne x2, x0, -12
+            0x0010_0073, // ebreak
+        ];
+
+        let mut memory = Memory::with_size(0x1000);
+        for (idx, inst) in program.iter().enumerate() {
+            memory.write(idx as u32 * 4, *inst);
+        }
+        let mut cpu = Cpu::<RV32I>::new(memory);
+
+        for _ in 0..50 {
+            cpu.step().unwrap();
+        }
+        let snapshot = cpu.snapshot();
+
+        cpu.run().unwrap();
+        let expected = (
+            cpu.registers().dump(),
+            cpu.memory().read::<[u8; 0x80]>(0x100),
+        );
+        let cycles = cpu.registers().read_csr(csr::CYCLE);
+
+        cpu.restore(snapshot);
+        assert_ne!(cpu.registers().pc().to_u64(), 24);
+        cpu.run().unwrap();
+
+        let actual = (
+            cpu.registers().dump(),
+            cpu.memory().read::<[u8; 0x80]>(0x100),
+        );
+        assert_eq!(actual.0.pc, expected.0.pc);
+        assert_eq!(actual.0.xregs, expected.0.xregs);
+        assert_eq!(&actual.1[..], &expected.1[..]);
+        assert_eq!(cpu.registers().read_csr(csr::CYCLE), cycles);
+    }
+
+    #[test]
+    fn restore_keeps_devices() {
+        let mut cpu = Cpu::<RV32I>::new(Memory::with_size(0x1000));
+        cpu.memory_mut().write(0x100_u32, 0xAB_u8);
+        let snapshot = cpu.snapshot();
+
+        cpu.memory_mut().write(0x100_u32, 0xCD_u8);
+        cpu.memory_mut()
+            .map_device(0x0200_0000, Rc::new(RefCell::new(Clint::new())));
+        cpu.restore(snapshot);
+
+        assert_eq!(cpu.memory().read::<u8>(0x100), 0xAB);
+        assert_eq!(cpu.memory().read_int(0x0200_0000, 4, false), Ok(0));
+    }
+
+    #[test]
+    fn breakpoints_and_cycles() {
+        let mut memory = Memory::with_size(0x100);
+        memory.write(0_u32, 0x0010_0093_u32); // addi x1, x0, 1
+        let mut cpu = Cpu::<RV32I>::new(memory);
+        cpu.enable_cycle_model();
+        cpu.set_breakpoint(0);
+
+        assert_eq!(
+            cpu.step(),
+            Ok(StepOutcome::Halted(HaltReason::BreakpointHit))
+        );
+        let snapshot = cpu.snapshot();
+        cpu.step().unwrap();
+        assert_eq!(cpu.modeled_cycles(), 1);
+
+        // The restored CPU resumes from the breakpoint, instead of halting again.
+        cpu.restore(snapshot);
+        assert_eq!(cpu.modeled_cycles(), 0);
+        assert_eq!(cpu.step(), Ok(StepOutcome::Retired));
+        assert_eq!(cpu.modeled_cycles(), 1);
+    }
+}
@@ -0,0 +1,484 @@
+//! Taking traps into a trap handler.
+
+use super::{csr, Cpu, Hooks, PrivilegeMode};
+use crate::{
+    trap::{Exception, Interrupt, Trap},
+    Address, Base,
+};
+
+/// All interrupts ordered by their priority, starting with the highest one.
+const INTERRUPT_PRIORITY: [Interrupt; 9] = [
+    Interrupt::MachineExternal,
+    Interrupt::MachineSoftware,
+    Interrupt::MachineTimer,
+    Interrupt::SupervisorExternal,
+    Interrupt::SupervisorSoftware,
+    Interrupt::SupervisorTimer,
+    Interrupt::UserExternal,
+    Interrupt::UserSoftware,
+    Interrupt::UserTimer,
+];
+
+impl<B: Base, H: Hooks<B>> Cpu<B, H> {
+    /// Takes the given trap, by entering the trap handler.
+    ///
+    /// If the CPU is not running in machine mode, and the trap is delegated using
+    /// `medeleg` or `mideleg`, the trap is taken in supervisor mode.
+    /// Otherwise it's taken in machine mode.
+    ///
+    /// The current `pc` is saved in `xepc`, the cause is written into `xcause`,
+    /// and the interrupt-enable and privilege stack in `mstatus` is pushed.
+    /// Afterwards, the CPU continues execution at the trap vector inside `xtvec`.
+    /// In vectored mode, interrupts jump to `BASE + 4 * cause`, while exceptions
+    /// always jump to `BASE`.
+    ///
+    /// `xtval` is set to `tval`, which should be the [trap value](Cpu::trap_value)
+    /// of the exception, or zero for interrupts.
+    pub fn take_trap(&mut self, trap: Trap, tval: u64) {
+        self.reservation = None;
+        let regs = &self.registers;
+
+        let deleg = if trap.is_interrupt() {
+            csr::MIDELEG
+        } else {
+            csr::MEDELEG
+        };
+        let delegated = self.privilege <= PrivilegeMode::Supervisor
+            && regs.read_csr(deleg).to_u64() & (1 << trap.code()) != 0;
+
+        let status = regs.mstatus();
+        let (epc, cause, tval_reg, tvec) = if delegated {
+            status.set_spie(status.sie());
+            status.set_sie(false);
+            status.set_spp(self.privilege);
+            self.privilege = PrivilegeMode::Supervisor;
+
+            (csr::SEPC, csr::SCAUSE, csr::STVAL, csr::STVEC)
+        } else {
+            status.set_mpie(status.mie());
+            status.set_mie(false);
+            status.set_mpp(self.privilege);
+            self.privilege = PrivilegeMode::Machine;
+
+            (csr::MEPC, csr::MCAUSE, csr::MTVAL, csr::MTVEC)
+        };
+
+        regs.write_csr(epc, regs.pc());
+        regs.write_csr(cause, B::Addr::from_u64(trap.cause::<B>()));
+        regs.write_csr(
+            tval_reg,
+            B::Addr::from_u64(tval & (u64::MAX >> (64 - B::XLEN))),
+        );
+
+        let tvec = regs.read_csr(tvec).to_u64();
+        let base = tvec & !0b11;
+        let target = match tvec & 0b11 {
+            // Vectored mode
+            1 if trap.is_interrupt() => base.wrapping_add(4 * trap.code()),
+            // Direct mode
+            _ => base,
+        };
+        *self.registers.pc_mut() = B::Addr::from_u64(target & (u64::MAX >> (64 - B::XLEN)));
+    }
+
+    /// Returns from a machine mode trap handler.
+    ///
+    /// The privilege mode and interrupt-enable stack in `mstatus` is popped,
+    /// and execution continues at the address inside `mepc`. Returning to
+    /// a mode below machine mode also clears `mstatus.MPRV`.
+    pub(super) fn mret(&mut self) -> Result<(), Exception> {
+        if self.privilege < PrivilegeMode::Machine {
+            return Err(Exception::IllegalInstruction);
+        }
+
+        let status = self.registers.mstatus();
+        self.privilege = status.mpp();
+        status.set_mie(status.mpie());
+        status.set_mpie(true);
+        status.set_mpp(PrivilegeMode::User);
+        if self.privilege != PrivilegeMode::Machine {
+            status.set_mprv(false);
+        }
+
+        *self.registers.pc_mut() = self.registers.read_csr(csr::MEPC);
+        Ok(())
+    }
+
+    /// Returns from a supervisor mode trap handler.
+    ///
+    /// The privilege mode and interrupt-enable stack in `mstatus` is popped,
+    /// and execution continues at the address inside `sepc`. This also clears
+    /// `mstatus.MPRV`.
+    pub(super) fn sret(&mut self) -> Result<(), Exception> {
+        if self.privilege < PrivilegeMode::Supervisor {
+            return Err(Exception::IllegalInstruction);
+        }
+
+        let status = self.registers.mstatus();
+        self.privilege = status.spp();
+        status.set_sie(status.spie());
+        status.set_spie(true);
+        status.set_spp(PrivilegeMode::User);
+        status.set_mprv(false);
+
+        *self.registers.pc_mut() = self.registers.read_csr(csr::SEPC);
+        Ok(())
+    }
+
+    /// Checks if any interrupt is pending in `mip` and enabled in `mie`,
+    /// regardless of the global interrupt-enable bits.
+    pub(super) fn interrupt_pending(&self) -> bool {
+        let regs = &self.registers;
+        regs.read_csr(csr::MIP).to_u64() & regs.read_csr(csr::MIE).to_u64() != 0
+    }
+
+    /// Returns the interrupt that should be taken next, if there's any.
+    ///
+    /// An interrupt is taken if it's pending in `mip`, enabled in `mie`, and
+    /// interrupts are globally enabled for the privilege mode that will handle it.
+    /// Interrupts that are handled in machine mode always take precedence over
+    /// the ones that are delegated to supervisor mode. Inside each privilege mode,
+    /// the order is external, software, and then timer interrupts.
+    pub fn check_interrupts(&self) -> Option<Interrupt> {
+        let regs = &self.registers;
+        let pending = regs.read_csr(csr::MIP).to_u64() & regs.read_csr(csr::MIE).to_u64();
+        if pending == 0 {
+            return None;
+        }
+
+        let status = regs.mstatus();
+        let mideleg = regs.read_csr(csr::MIDELEG).to_u64();
+
+        let machine_enabled = self.privilege < PrivilegeMode::Machine || status.mie();
+        let supervisor_enabled = self.privilege < PrivilegeMode::Supervisor
+            || (self.privilege == PrivilegeMode::Supervisor && status.sie());
+
+        let machine = if machine_enabled {
+            pending & !mideleg
+        } else {
+            0
+        };
+        let supervisor = if supervisor_enabled {
+            pending & mideleg
+        } else {
+            0
+        };
+
+        [machine, supervisor].iter().find_map(|&pending| {
+            INTERRUPT_PRIORITY
+                .iter()
+                .copied()
+                .find(|int| pending & (1 << int.code()) != 0)
+        })
+    }
+
+    /// Asserts the interrupt `int` on behalf of the `source` line of `device`.
+    ///
+    /// The interrupt is passed through the [`Hooks`], which may remap
+    /// or drop it, before it's marked as pending in `mip`.
+    pub fn assert_interrupt(&mut self, device: &str, source: u32, int: Interrupt) {
+        let int = if H::ENABLED {
+            match self.hooks.on_interrupt(device, source, int) {
+                Some(int) => int,
+                None => return,
+            }
+        } else {
+            int
+        };
+
+        self.set_pending_interrupt(int);
+    }
+
+    /// Updates the bits of `mip`, that are driven by the interrupt lines of the devices.
+    ///
+    /// A bit is only cleared if it was set by a device before,
+    /// so interrupts that are set by [`Cpu::set_pending_interrupt`] stay pending.
+    pub(super) fn update_device_interrupts(&mut self) {
+        let hart = self.registers.read_csr(csr::MHARTID).to_u64();
+        let lines = self.memory.device_interrupts(hart);
+        if lines == self.device_interrupts {
+            return;
+        }
+
+        let mip = self.registers.read_csr(csr::MIP).to_u64();
+        let mip = (mip & !self.device_interrupts) | lines;
+        self.registers.write_csr(csr::MIP, B::Addr::from_u64(mip));
+        self.device_interrupts = lines;
+    }
+
+    /// Marks the given interrupt as pending in `mip`.
+    pub fn set_pending_interrupt(&mut self, int: Interrupt) {
+        let mip = self.registers.read_csr(csr::MIP).to_u64() | (1 << int.code());
+        self.registers.write_csr(csr::MIP, B::Addr::from_u64(mip));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cpu::{csr, mstatus, BareCpu, Cpu, CpuConfig, PrivilegeMode},
+        trap::{Exception, Interrupt, Trap},
+        Address, RV32I, RV64I,
+    };
+
+    #[test]
+    fn illegal_instruction() {
+        let mut binary = vec![0x13, 0, 0, 0]; // nop
+        binary.extend_from_slice(&[0; 4]); // illegal instruction
+        let mut cpu = Cpu::<RV64I>::from_config(CpuConfig::raw(binary)).unwrap();
+
+        cpu.registers().write_csr(csr::MTVEC, 0x100);
+        cpu.registers().write_csr(csr::MSTATUS, mstatus::MIE);
+
+        cpu.step().unwrap();
+        let err = cpu.step().unwrap_err();
+        assert_eq!(err, Exception::IllegalInstruction);
+        cpu.take_trap(err.into(), cpu.trap_value());
+
+        let regs = cpu.registers();
+        assert_eq!(regs.read_csr(csr::MEPC), 4);
+        assert_eq!(regs.read_csr(csr::MCAUSE), 2);
+        assert_eq!(regs.read_csr(csr::MTVAL), 0);
+        assert_eq!(regs.read_csr(csr::MSTATUS), mstatus::MPIE | mstatus::MPP);
+        assert_eq!(regs.pc(), 0x100);
+        assert_eq!(cpu.privilege(), PrivilegeMode::Machine);
+    }
+
+    #[test]
+    fn trap_value() {
+        let program: &[u32] = &[
+            0x1231_008B, // custom-0 instruction
+            0xF140_1073, // csrw mhartid, x0
+            0x0010_2083, // lw x1, 1(x0)
+            0x0000_0073, // ecall
+        ];
+        let binary = program.iter().flat_map(|inst| inst.to_le_bytes().to_vec());
+        let mut cpu = Cpu::<RV64I>::from_config(CpuConfig::raw(binary.collect())).unwrap();
+        cpu.registers().write_csr(csr::MTVEC, 0x100);
+
+        let expected = [
+            (Exception::IllegalInstruction, 0x1231_008B),
+            (Exception::IllegalInstruction, 0xF140_1073),
+            (Exception::LoadAddressMisaligned, 1),
+            (Exception::MachineModeEnvironmentCall, 0),
+        ];
+        for (idx, (exception, tval)) in expected.iter().enumerate() {
+            *cpu.registers_mut().pc_mut() = idx as u64 * 4;
+            assert_eq!(cpu.step(), Err(*exception));
+
+            cpu.take_trap((*exception).into(), cpu.trap_value());
+            assert_eq!(cpu.registers().read_csr(csr::MTVAL), *tval);
+        }
+    }
+
+    #[test]
+    fn vectored_mode() {
+        let mut cpu = Cpu::<RV64I>::from_config(CpuConfig::raw(vec![])).unwrap();
+        cpu.registers().write_csr(csr::MTVEC, 0x100 | 1);
+
+        cpu.take_trap(Trap::Interrupt(Interrupt::MachineTimer), 0);
+        assert_eq!(cpu.registers().pc(), 0x100 + 4 * 7);
+
+        cpu.take_trap(Exception::IllegalInstruction.into(), 0);
+        assert_eq!(cpu.registers().pc(), 0x100);
+
+        // Direct mode
+        cpu.registers().write_csr(csr::MTVEC, 0x200);
+        cpu.take_trap(Trap::Interrupt(Interrupt::MachineTimer), 0);
+        assert_eq!(cpu.registers().pc(), 0x200);
+    }
+
+    #[test]
+    fn interrupt_cause() {
+        let mut cpu = Cpu::<RV32I>::from_config(CpuConfig::raw(vec![])).unwrap();
+        cpu.take_trap(Trap::Interrupt(Interrupt::MachineTimer), 0);
+        assert_eq!(cpu.registers().read_csr(csr::MCAUSE), 0x8000_0007);
+        assert_eq!(
+            cpu.registers().read_csr(csr::MSTATUS).to_u64() & mstatus::MPIE,
+            0
+        );
+
+        let mut cpu = Cpu::<RV64I>::from_config(CpuConfig::raw(vec![])).unwrap();
+        cpu.take_trap(Trap::Interrupt(Interrupt::MachineExternal), 0);
+        assert_eq!(cpu.registers().read_csr(csr::MCAUSE), 0x8000_0000_0000_000B);
+    }
+
+    #[test]
+    fn delegate_to_supervisor() {
+        let mut cpu = Cpu::<RV64I>::from_config(CpuConfig::raw(vec![])).unwrap();
+        {
+            let regs = cpu.registers_mut();
+            regs.write_csr(csr::MEDELEG, 1 << 8);
+            regs.write_csr(csr::MTVEC, 0x100);
+            regs.write_csr(csr::STVEC, 0x200);
+            regs.write_csr(csr::MSTATUS, mstatus::SIE);
+            *regs.pc_mut() = 0x40;
+        }
+        cpu.privilege = PrivilegeMode::User;
+
+        cpu.take_trap(Exception::UserModeEnvironmentCall.into(), 0);
+
+        let regs = cpu.registers();
+        assert_eq!(regs.read_csr(csr::SEPC), 0x40);
+        assert_eq!(regs.read_csr(csr::SCAUSE), 8);
+        assert_eq!(regs.read_csr(csr::STVAL), 0);
+        assert_eq!(regs.read_csr(csr::MCAUSE), 0);
+        assert_eq!(regs.read_csr(csr::MSTATUS), mstatus::SPIE);
+        assert_eq!(regs.pc(), 0x200);
+        assert_eq!(cpu.privilege(), PrivilegeMode::Supervisor);
+
+        // Traps are never delegated when running in machine mode.
+        cpu.privilege = PrivilegeMode::Machine;
+        cpu.take_trap(Exception::UserModeEnvironmentCall.into(), 0);
+        assert_eq!(cpu.registers().pc(), 0x100);
+        assert_eq!(cpu.registers().read_csr(csr::MSTATUS) & mstatus::SPP, 0);
+    }
+
+    #[test]
+    fn remap_interrupt() {
+        let mut cpu = Cpu::<RV64I>::from_config(CpuConfig::raw(vec![])).unwrap();
+        cpu.set_interrupt_hook(|device, source, int| match (device, source) {
+            ("uart", 1) => Some(Interrupt::SupervisorExternal),
+            ("uart", _) => None,
+            _ => Some(int),
+        });
+
+        cpu.assert_interrupt("uart", 1, Interrupt::MachineExternal);
+        assert_eq!(cpu.registers().read_csr(csr::MIP), 1 << 9);
+
+        cpu.assert_interrupt("uart", 2, Interrupt::MachineExternal);
+        assert_eq!(cpu.registers().read_csr(csr::MIP), 1 << 9);
+
+        cpu.assert_interrupt("timer", 0, Interrupt::MachineTimer);
+        assert_eq!(cpu.registers().read_csr(csr::MIP), (1 << 9) | (1 << 7));
+
+        let mut cpu = BareCpu::<RV32I>::from_config(CpuConfig::raw(vec![])).unwrap();
+        cpu.assert_interrupt("uart", 1, Interrupt::MachineExternal);
+        assert_eq!(cpu.registers().read_csr(csr::MIP), 1 << 11);
+    }
+
+    #[test]
+    fn trap_return() {
+        let mut binary = vec![];
+        binary.extend_from_slice(&0x0000_0073_u32.to_le_bytes()); // ecall
+        binary.extend_from_slice(&0x1020_0073_u32.to_le_bytes()); // sret
+        binary.extend_from_slice(&0x3020_0073_u32.to_le_bytes()); // mret
+        let mut cpu = Cpu::<RV64I>::from_config(CpuConfig::raw(binary)).unwrap();
+
+        // Enter the handler from supervisor mode, and return from it.
+        cpu.registers().write_csr(csr::MTVEC, 0x8);
+        cpu.registers().write_csr(csr::MSTATUS, mstatus::MIE);
+        cpu.privilege = PrivilegeMode::Supervisor;
+        let err = cpu.step().unwrap_err();
+        cpu.take_trap(err.into(), cpu.trap_value());
+        assert_eq!(cpu.registers().pc(), 0x8);
+        assert_eq!(cpu.privilege(), PrivilegeMode::Machine);
+
+        // Returning to supervisor mode clears `MPRV`.
+        cpu.registers().mstatus().set_mprv(true);
+        cpu.registers().write_csr(csr::MEPC, 0x4);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().pc(), 0x4);
+        assert_eq!(cpu.privilege(), PrivilegeMode::Supervisor);
+        assert_eq!(
+            cpu.registers().read_csr(csr::MSTATUS),
+            mstatus::MIE | mstatus::MPIE
+        );
+
+        // Return from supervisor to user mode.
+        cpu.registers().write_csr(csr::SEPC, 0x40);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().pc(), 0x40);
+        assert_eq!(cpu.privilege(), PrivilegeMode::User);
+
+        // `sret` and `mret` are illegal in user mode.
+        *cpu.registers_mut().pc_mut() = 0x4;
+        assert_eq!(cpu.step(), Err(Exception::IllegalInstruction));
+        *cpu.registers_mut().pc_mut() = 0x8;
+        assert_eq!(cpu.step(), Err(Exception::IllegalInstruction));
+    }
+
+    #[test]
+    fn interrupt_priority() {
+        let mut cpu = Cpu::<RV64I>::from_config(CpuConfig::raw(vec![])).unwrap();
+        let machine_ext = 1 << Interrupt::MachineExternal.code();
+        let machine_timer = 1 << Interrupt::MachineTimer.code();
+        let supervisor_ext = 1 << Interrupt::SupervisorExternal.code();
+
+        let regs = cpu.registers();
+        regs.write_csr(csr::MIP, machine_timer | supervisor_ext | machine_ext);
+        regs.write_csr(csr::MIE, machine_timer | supervisor_ext | machine_ext);
+        assert_eq!(cpu.check_interrupts(), None);
+
+        regs.write_csr(csr::MSTATUS, mstatus::MIE);
+        assert_eq!(cpu.check_interrupts(), Some(Interrupt::MachineExternal));
+
+        regs.write_csr(csr::MIP, machine_timer | supervisor_ext);
+        assert_eq!(cpu.check_interrupts(), Some(Interrupt::MachineTimer));
+
+        // Machine interrupts take precedence over delegated ones,
+        // even if the delegated interrupt has a higher priority.
+        cpu.privilege = PrivilegeMode::Supervisor;
+        cpu.registers().write_csr(csr::MSTATUS, mstatus::SIE);
+        cpu.registers()
+            .write_csr(csr::MIDELEG, supervisor_ext | machine_ext);
+        assert_eq!(cpu.check_interrupts(), Some(Interrupt::MachineTimer));
+
+        cpu.registers()
+            .write_csr(csr::MIP, supervisor_ext | machine_ext);
+        assert_eq!(cpu.check_interrupts(), Some(Interrupt::MachineExternal));
+
+        // Delegated interrupts are never taken in machine mode.
+        cpu.privilege = PrivilegeMode::Machine;
+        assert_eq!(cpu.check_interrupts(), None);
+    }
+
+    #[test]
+    fn deliver_interrupt() {
+        let mut binary = vec![];
+        binary.extend_from_slice(&0x0000_0013_u32.to_le_bytes()); // nop
+        binary.extend_from_slice(&0x0000_0013_u32.to_le_bytes()); // nop
+        let mut cpu = Cpu::<RV64I>::from_config(CpuConfig::raw(binary)).unwrap();
+
+        let timer = 1 << Interrupt::MachineTimer.code();
+        cpu.registers().write_csr(csr::MTVEC, 0x4);
+        cpu.registers().write_csr(csr::MIE, timer);
+
+        // The interrupt is not taken, while it's globally disabled.
+        cpu.set_pending_interrupt(Interrupt::MachineTimer);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().pc(), 0x4);
+
+        *cpu.registers_mut().pc_mut() = 0;
+        cpu.registers().mstatus().set_mie(true);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().pc(), 0x4);
+        assert_eq!(cpu.registers().read_csr(csr::MEPC), 0);
+        assert_eq!(cpu.registers().read_csr(csr::MCAUSE), (1 << 63) | 7);
+        assert!(!cpu.registers().mstatus().mie());
+    }
+
+    #[test]
+    fn compressed_trap_return() {
+        let mut binary = vec![0; 0x14];
+        binary[..4].copy_from_slice(&[0x01, 0x00, 0x02, 0x90]); // c.nop, c.ebreak
+        binary[0x10..].copy_from_slice(&0x3020_0073_u32.to_le_bytes()); // mret
+
+        let config = CpuConfig::builder()
+            .binary(binary)
+            .memory_size(0x100)
+            .pc(2)
+            .extensions(vec!['C', 'I'])
+            .build();
+        let mut cpu = Cpu::<RV64I>::from_config(config).unwrap();
+        cpu.registers().write_csr(csr::MTVEC, 0x10);
+
+        // Compressed instructions are not supported yet, so this traps at `pc = 2`.
+        let err = cpu.step().unwrap_err();
+        cpu.take_trap(err.into(), cpu.trap_value());
+        assert_eq!(cpu.registers().read_csr(csr::MEPC), 2);
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().pc(), 2);
+    }
+}
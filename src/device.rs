@@ -0,0 +1,80 @@
+//! Memory mapped I/O devices, that can be attached to the [`Memory`](crate::memory::Memory).
+
+pub mod clint;
+pub mod htif;
+pub mod plic;
+pub mod uart;
+
+use crate::dtb::DeviceNode;
+use std::{cell::RefCell, rc::Rc};
+
+/// A device, that is accessed through a region of the physical address space.
+///
+/// All accesses are forwarded to the device with the offset
+/// inside it's region, and are at most 8 bytes wide.
+#[allow(clippy::module_name_repetitions)]
+pub trait MmioDevice {
+    /// Returns the size of the region, that is occupied by this device, in bytes.
+    fn size(&self) -> u64;
+
+    /// Reads `width` bytes at `offset`.
+    ///
+    /// Returns `None` if the device doesn't support the access,
+    /// which raises an access fault.
+    fn read(&mut self, offset: u64, width: usize) -> Option<u64>;
+
+    /// Writes the lower `width` bytes of `val` at `offset`.
+    ///
+    /// Returns `None` if the device doesn't support the access,
+    /// which raises an access fault.
+    fn write(&mut self, offset: u64, width: usize, val: u64) -> Option<()>;
+
+    /// Returns the interrupt lines of the hart with the given `mhartid`,
+    /// that are currently asserted by this device.
+    ///
+    /// Every set bit corresponds to the bit of the interrupt in `mip`, which
+    /// is set as long as the line is asserted. See [`Interrupt::code`](crate::trap::Interrupt::code).
+    fn interrupts(&self, _hart: u64) -> u64 {
+        0
+    }
+
+    /// Returns the description of this device, that is added to the
+    /// [device tree](crate::dtb), or `None` if the device is not part of it.
+    fn device_tree_node(&self) -> Option<DeviceNode> {
+        None
+    }
+
+    /// Returns the exit code, if the guest requested to halt through this
+    /// device since the last call.
+    ///
+    /// The CPU checks this after every store, and halts with the code.
+    fn take_exit(&mut self) -> Option<u64> {
+        None
+    }
+}
+
+/// A device that is shared between the memory and the embedder,
+/// which can use it to interact with the device while the CPU is running.
+#[allow(clippy::module_name_repetitions)]
+pub type SharedDevice = Rc<RefCell<dyn MmioDevice>>;
+
+/// Reads `width` bytes at `offset` inside the 64-bit register `reg`.
+fn read_part(reg: u64, offset: u64, width: usize) -> Option<u64> {
+    match (offset, width) {
+        (0, 8) => Some(reg),
+        (0, 4) => Some(reg & 0xFFFF_FFFF),
+        (4, 4) => Some(reg >> 32),
+        _ => None,
+    }
+}
+
+/// Writes the lower `width` bytes of `val` at `offset` inside the 64-bit register `reg`.
+fn write_part(reg: &mut u64, offset: u64, width: usize, val: u64) -> Option<()> {
+    *reg = match (offset, width) {
+        (0, 8) => val,
+        (0, 4) => (*reg & !0xFFFF_FFFF) | (val & 0xFFFF_FFFF),
+        (4, 4) => (*reg & 0xFFFF_FFFF) | (val << 32),
+        _ => return None,
+    };
+    Some(())
+}
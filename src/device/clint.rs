@@ -0,0 +1,184 @@
+//! The core-local interruptor, which provides the timer and
+//! software interrupts of a hart.
+
+use super::{read_part, write_part, MmioDevice};
+use crate::{dtb::DeviceNode, trap::Interrupt};
+
+/// The offset of the `msip` register of the first hart, one word for every hart.
+const MSIP: u64 = 0x0000;
+/// The offset of the `mtimecmp` register of the first hart, one doubleword for every hart.
+const MTIMECMP: u64 = 0x4000;
+/// The offset of the `mtime` register.
+const MTIME: u64 = 0xBFF8;
+
+/// A core-local interruptor, which uses the register layout
+/// of the CLINT that is used by `SiFive` cores.
+///
+/// Every hart has it's own `msip` and `mtimecmp` register, which are indexed
+/// by the `mhartid` of the hart. The timer interrupt of a hart is asserted as long as
+/// `mtime >= mtimecmp`, and the software interrupt as long as the lowest bit of `msip`
+/// is set, which allows harts to send inter-processor interrupts to each other.
+/// `mtime` only advances if [`Clint::tick`] is called, which leaves it up to the
+/// embedder to decide how time relates to the executed instructions.
+#[derive(Debug, Clone)]
+pub struct Clint {
+    msip: Vec<bool>,
+    mtime: u64,
+    mtimecmp: Vec<u64>,
+}
+
+impl Default for Clint {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clint {
+    /// Creates a new CLINT for a single hart, where `mtime` starts at zero,
+    /// and the timer interrupt is disabled.
+    pub fn new() -> Self {
+        Self::with_harts(1)
+    }
+
+    /// Creates a new CLINT for the given number of harts, where `mtime`
+    /// starts at zero, and the timer interrupts are disabled.
+    ///
+    /// ## Panics
+    ///
+    /// If there are more than `4095` harts.
+    pub fn with_harts(harts: usize) -> Self {
+        assert!(harts <= 4095, "the CLINT supports at most 4095 harts");
+
+        Self {
+            msip: vec![false; harts],
+            mtime: 0,
+            mtimecmp: vec![u64::MAX; harts],
+        }
+    }
+
+    /// Advances `mtime` by `delta` ticks.
+    pub fn tick(&mut self, delta: u64) {
+        self.mtime = self.mtime.wrapping_add(delta);
+    }
+
+    /// Returns the current value of `mtime`.
+    pub fn mtime(&self) -> u64 {
+        self.mtime
+    }
+}
+
+impl MmioDevice for Clint {
+    fn size(&self) -> u64 {
+        0x1_0000
+    }
+
+    fn device_tree_node(&self) -> Option<DeviceNode> {
+        Some(DeviceNode {
+            name: "clint",
+            compatible: "riscv,clint0",
+            hart_interrupts: vec![Interrupt::MachineSoftware, Interrupt::MachineTimer],
+            properties: vec![],
+        })
+    }
+
+    fn read(&mut self, offset: u64, width: usize) -> Option<u64> {
+        match offset {
+            MSIP..=0x3FFF if width == 4 && offset % 4 == 0 => {
+                let hart = ((offset - MSIP) / 4) as usize;
+                Some(*self.msip.get(hart)? as u64)
+            }
+            MTIMECMP..=0xBFF7 => {
+                let hart = ((offset - MTIMECMP) / 8) as usize;
+                read_part(*self.mtimecmp.get(hart)?, (offset - MTIMECMP) % 8, width)
+            }
+            MTIME..=0xBFFF => read_part(self.mtime, offset - MTIME, width),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, offset: u64, width: usize, val: u64) -> Option<()> {
+        match offset {
+            MSIP..=0x3FFF if width == 4 && offset % 4 == 0 => {
+                let hart = ((offset - MSIP) / 4) as usize;
+                *self.msip.get_mut(hart)? = val & 1 != 0;
+                Some(())
+            }
+            MTIMECMP..=0xBFF7 => {
+                let hart = ((offset - MTIMECMP) / 8) as usize;
+                write_part(
+                    self.mtimecmp.get_mut(hart)?,
+                    (offset - MTIMECMP) % 8,
+                    width,
+                    val,
+                )
+            }
+            MTIME..=0xBFFF => write_part(&mut self.mtime, offset - MTIME, width, val),
+            _ => None,
+        }
+    }
+
+    fn interrupts(&self, hart: u64) -> u64 {
+        let hart = hart as usize;
+        let (msip, mtimecmp) = match (self.msip.get(hart), self.mtimecmp.get(hart)) {
+            (Some(msip), Some(mtimecmp)) => (*msip, *mtimecmp),
+            _ => return 0,
+        };
+
+        let timer = (self.mtime >= mtimecmp) as u64;
+        let software = msip as u64;
+        timer << Interrupt::MachineTimer.code() | software << Interrupt::MachineSoftware.code()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Clint;
+    use crate::{
+        cpu::{csr, Cpu},
+        memory::Memory,
+        RV32I,
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    const BASE: u64 = 0x0200_0000;
+
+    #[test]
+    fn timer_interrupt() {
+        let clint = Rc::new(RefCell::new(Clint::new()));
+        let mut memory = Memory::<RV32I>::with_size(0x1000);
+        for idx in 0..4 {
+            memory.write(idx * 4, 0x0000_0013_u32); // nop
+        }
+        memory.map_device(BASE, clint.clone());
+        let mut cpu = Cpu::<RV32I>::new(memory);
+
+        // A 32-bit hart writes `mtimecmp` in two halves.
+        let memory = cpu.memory_mut();
+        memory.write_int(BASE as u32 + 0x4000, 4, 100).unwrap();
+        memory.write_int(BASE as u32 + 0x4004, 4, 0).unwrap();
+        assert_eq!(memory.read_int(BASE as u32 + 0x4000, 4, false), Ok(100));
+
+        clint.borrow_mut().tick(99);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().read_csr(csr::MIP), 0);
+
+        clint.borrow_mut().tick(1);
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().read_csr(csr::MIP), 1 << 7);
+        assert_eq!(
+            cpu.memory().read_int(BASE as u32 + 0xBFF8, 4, false),
+            Ok(100)
+        );
+
+        // Writing `mtimecmp` clears the interrupt.
+        cpu.memory_mut()
+            .write_int(BASE as u32 + 0x4000, 4, 200)
+            .unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().read_csr(csr::MIP), 0);
+
+        cpu.memory_mut().write_int(BASE as u32, 4, 1).unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().read_csr(csr::MIP), 1 << 3);
+    }
+}
@@ -0,0 +1,135 @@
+//! The host-target interface, which is used by the `riscv-tests`
+//! to report whether a test passed.
+
+use super::{read_part, write_part, MmioDevice};
+
+/// The offset of the `tohost` register.
+const TOHOST: u64 = 0x0;
+/// The offset of the `fromhost` register.
+const FROMHOST: u64 = 0x8;
+
+/// A minimal host-target interface, that consists of the
+/// 64-bit `tohost` and `fromhost` registers.
+///
+/// The upper 16 bits of `tohost` select the device and the command.
+/// The only supported command is the exit command of device `0`, which is written
+/// into `tohost` as `(code << 1) | 1`. An exit code of `0` means that the test passed, otherwise
+/// the code is the number of the failed test. Every other command is ignored.
+///
+/// The device should be mapped at the address of the `tohost` symbol
+/// of the test binary.
+#[derive(Debug, Clone, Default)]
+pub struct Htif {
+    tohost: u64,
+    fromhost: u64,
+    exit: Option<u64>,
+}
+
+impl Htif {
+    /// Creates a new HTIF, where no command was sent yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Executes the command in `tohost`, and acknowledges it by clearing `tohost`.
+    fn command(&mut self) {
+        let (device, payload) = (self.tohost >> 48, self.tohost & 0xFFFF_FFFF_FFFF);
+        if device == 0 && payload & 1 != 0 {
+            self.exit = Some(payload >> 1);
+            self.tohost = 0;
+        }
+    }
+}
+
+impl MmioDevice for Htif {
+    fn size(&self) -> u64 {
+        0x10
+    }
+
+    fn read(&mut self, offset: u64, width: usize) -> Option<u64> {
+        match offset {
+            TOHOST..=0x7 => read_part(self.tohost, offset - TOHOST, width),
+            FROMHOST..=0xF => read_part(self.fromhost, offset - FROMHOST, width),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, offset: u64, width: usize, val: u64) -> Option<()> {
+        match offset {
+            TOHOST..=0x7 => {
+                write_part(&mut self.tohost, offset - TOHOST, width, val)?;
+                self.command();
+                Some(())
+            }
+            FROMHOST..=0xF => write_part(&mut self.fromhost, offset - FROMHOST, width, val),
+            _ => None,
+        }
+    }
+
+    fn take_exit(&mut self) -> Option<u64> {
+        self.exit.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Htif;
+    use crate::{
+        cpu::{Cpu, HaltReason, RunOutcome, StepOutcome, XRegister},
+        device::MmioDevice,
+        memory::Memory,
+        RV32I,
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn exit_code() {
+        let program: &[u32] = &[
+            0x0010_0093, // addi x1, x0, 1
+            0x0011_2023, // sw x1, 0(x2)
+            0x0070_0093, // addi x1, x0, 7
+            0x0011_2023, // sw x1, 0(x2)
+            0x0001_2223, // sw x0, 4(x2)
+        ];
+
+        let htif = Rc::new(RefCell::new(Htif::new()));
+        let mut memory = Memory::<RV32I>::with_size(0x100);
+        for (idx, inst) in program.iter().enumerate() {
+            memory.write(idx as u32 * 4, *inst);
+        }
+        memory.map_device(0x1000, htif);
+
+        let mut cpu = Cpu::<RV32I>::new(memory);
+        cpu.registers().write_x(XRegister::from(2), 0x1000);
+
+        // The test passed.
+        assert_eq!(cpu.step(), Ok(StepOutcome::Retired));
+        assert_eq!(cpu.step(), Ok(StepOutcome::Halted(HaltReason::Exit(0))));
+
+        // The third test failed.
+        assert_eq!(cpu.step(), Ok(StepOutcome::Retired));
+        assert_eq!(cpu.step(), Ok(StepOutcome::Halted(HaltReason::Exit(3))));
+
+        // Writing the upper half doesn't send a command, and `tohost` was acknowledged.
+        assert_eq!(cpu.step(), Ok(StepOutcome::Retired));
+        assert_eq!(cpu.memory_mut().read_int(0x1000, 4, false), Ok(0));
+
+        // Running the program returns the exit code.
+        *cpu.registers_mut().pc_mut() = 0;
+        assert_eq!(cpu.run().unwrap(), RunOutcome::Halted(HaltReason::Exit(0)));
+    }
+
+    #[test]
+    fn ignores_other_commands() {
+        let mut htif = Htif::new();
+
+        // A console write of the character `a`.
+        let putchar = 0x0101_0000_0000_0061;
+        assert_eq!(htif.write(0, 8, putchar), Some(()));
+        assert_eq!(htif.take_exit(), None);
+        assert_eq!(htif.read(0, 8), Some(putchar));
+
+        assert_eq!(htif.write(0, 8, 0x5), Some(()));
+        assert_eq!(htif.take_exit(), Some(2));
+    }
+}
@@ -0,0 +1,366 @@
+//! The platform-level interrupt controller.
+//!
+//! See the [`PLIC spec`] for the register layout and behaviour.
+//!
+//! [`PLIC spec`]: https://github.com/riscv/riscv-plic-spec/blob/master/riscv-plic.adoc
+
+use super::MmioDevice;
+use crate::{
+    dtb::{DeviceNode, Property},
+    trap::Interrupt,
+};
+
+/// The offset of the priority registers, one word for every source.
+const PRIORITY_BASE: u64 = 0x00_0000;
+/// The offset of the pending bits, one bit for every source.
+const PENDING_BASE: u64 = 0x00_1000;
+/// The offset of the enable bits of the first context.
+const ENABLE_BASE: u64 = 0x00_2000;
+/// The size of the enable bits of each context.
+const ENABLE_STRIDE: u64 = 0x80;
+/// The offset of the threshold and claim registers of the first context.
+const CONTEXT_BASE: u64 = 0x20_0000;
+/// The size of the threshold and claim registers of each context.
+const CONTEXT_STRIDE: u64 = 0x1000;
+
+/// The highest priority a source can have.
+const MAX_PRIORITY: u32 = 7;
+
+/// The state of a single interrupt source.
+#[derive(Debug, Clone, Copy, Default)]
+struct Source {
+    priority: u32,
+    /// The level of the interrupt line.
+    level: bool,
+    pending: bool,
+    /// Set while the interrupt is claimed by a context, and not completed yet.
+    claimed: bool,
+}
+
+/// A single interrupt target, which is a privilege mode on a hart.
+#[derive(Debug, Clone, Default)]
+struct Context {
+    enabled: Vec<u32>,
+    threshold: u32,
+}
+
+/// A platform-level interrupt controller, which prioritizes and distributes
+/// the interrupts of all level-triggered sources to the contexts.
+///
+/// Source `0` doesn't exist, so the sources are numbered starting at `1`.
+/// The contexts are the machine and supervisor mode of each hart, so even
+/// contexts raise [`Interrupt::MachineExternal`], and odd contexts raise
+/// [`Interrupt::SupervisorExternal`]. Contexts `2 * n` and `2 * n + 1`
+/// belong to the hart with `mhartid` `n`.
+#[derive(Debug, Clone)]
+pub struct Plic {
+    sources: Vec<Source>,
+    contexts: Vec<Context>,
+}
+
+impl Plic {
+    /// Creates a new PLIC with the given number of sources and contexts.
+    ///
+    /// ## Panics
+    ///
+    /// If there are more than `1023` sources, or more than `15872` contexts.
+    pub fn new(sources: usize, contexts: usize) -> Self {
+        assert!(sources <= 1023, "the PLIC supports at most 1023 sources");
+        assert!(
+            contexts <= 15872,
+            "the PLIC supports at most 15872 contexts"
+        );
+
+        let words = (sources + 1 + 31) / 32;
+        Self {
+            sources: vec![Source::default(); sources + 1],
+            contexts: vec![
+                Context {
+                    enabled: vec![0; words],
+                    threshold: 0,
+                };
+                contexts
+            ],
+        }
+    }
+
+    /// Sets the level of the interrupt line of the source `id`.
+    ///
+    /// The source becomes pending if the line is high, and the source
+    /// is not currently claimed.
+    ///
+    /// ## Panics
+    ///
+    /// If the source doesn't exist.
+    pub fn set_source(&mut self, id: usize, level: bool) {
+        assert!(
+            id != 0 && id < self.sources.len(),
+            "source {} doesn't exist",
+            id
+        );
+
+        let source = &mut self.sources[id];
+        source.level = level;
+        if level && !source.claimed {
+            source.pending = true;
+        }
+    }
+
+    fn is_enabled(&self, ctx: usize, id: usize) -> bool {
+        self.contexts[ctx].enabled[id / 32] & (1 << (id % 32)) != 0
+    }
+
+    /// Returns the pending and enabled source with the highest priority,
+    /// that exceeds the threshold of the context.
+    fn highest_pending(&self, ctx: usize) -> Option<usize> {
+        let threshold = self.contexts[ctx].threshold;
+
+        self.sources
+            .iter()
+            .enumerate()
+            .skip(1)
+            .filter(|(id, source)| {
+                source.pending && source.priority > threshold && self.is_enabled(ctx, *id)
+            })
+            // `max_by_key` returns the last maximum, but ties are won by the lowest id.
+            .rev()
+            .max_by_key(|(_, source)| source.priority)
+            .map(|(id, _)| id)
+    }
+
+    /// Claims the highest priority interrupt of the context, and returns it's id,
+    /// or `0` if there's no interrupt.
+    fn claim(&mut self, ctx: usize) -> u32 {
+        match self.highest_pending(ctx) {
+            Some(id) => {
+                let source = &mut self.sources[id];
+                source.pending = false;
+                source.claimed = true;
+                id as u32
+            }
+            None => 0,
+        }
+    }
+
+    /// Completes the interrupt `id`, which makes it pending again
+    /// if the line is still high.
+    fn complete(&mut self, ctx: usize, id: usize) {
+        if id == 0 || id >= self.sources.len() || !self.is_enabled(ctx, id) {
+            return;
+        }
+
+        let source = &mut self.sources[id];
+        source.claimed = false;
+        source.pending = source.level;
+    }
+
+    fn read_word(&mut self, offset: u64) -> Option<u32> {
+        match offset {
+            PRIORITY_BASE..=0x0FFF => Some(self.sources.get(offset as usize / 4)?.priority),
+            PENDING_BASE..=0x1FFF => {
+                let word = (offset - PENDING_BASE) as usize / 4;
+                let bits = self
+                    .sources
+                    .iter()
+                    .enumerate()
+                    .skip(word * 32)
+                    .take(32)
+                    .filter(|(_, source)| source.pending)
+                    .fold(0, |bits, (id, _)| bits | 1 << (id % 32));
+                Some(bits)
+            }
+            ENABLE_BASE..=0x1F_FFFF => {
+                let (ctx, word) = enable_offset(offset);
+                self.contexts.get(ctx)?.enabled.get(word).copied()
+            }
+            _ => match context_offset(offset)? {
+                (ctx, 0) => Some(self.contexts.get(ctx)?.threshold),
+                (ctx, 4) if ctx < self.contexts.len() => Some(self.claim(ctx)),
+                _ => None,
+            },
+        }
+    }
+
+    fn write_word(&mut self, offset: u64, value: u32) -> Option<()> {
+        match offset {
+            // The pending bits are read-only, and source `0` doesn't exist,
+            // so it's priority is always zero.
+            PRIORITY_BASE..=0x0003 | PENDING_BASE..=0x1FFF => {}
+            0x0004..=0x0FFF => {
+                self.sources.get_mut(offset as usize / 4)?.priority = value.min(MAX_PRIORITY);
+            }
+            ENABLE_BASE..=0x1F_FFFF => {
+                let (ctx, word) = enable_offset(offset);
+                // Source `0` doesn't exist, so it can't be enabled.
+                let mask = if word == 0 { !1 } else { !0 };
+                *self.contexts.get_mut(ctx)?.enabled.get_mut(word)? = value & mask;
+            }
+            _ => match context_offset(offset)? {
+                (ctx, 0) => self.contexts.get_mut(ctx)?.threshold = value.min(MAX_PRIORITY),
+                (ctx, 4) if ctx < self.contexts.len() => self.complete(ctx, value as usize),
+                _ => return None,
+            },
+        }
+        Some(())
+    }
+}
+
+/// Splits the offset inside the enable bits into the context and word index.
+fn enable_offset(offset: u64) -> (usize, usize) {
+    let offset = offset - ENABLE_BASE;
+    let word = (offset % ENABLE_STRIDE) / 4;
+    ((offset / ENABLE_STRIDE) as usize, word as usize)
+}
+
+/// Splits the offset inside the context registers into the context
+/// and the offset of the register.
+fn context_offset(offset: u64) -> Option<(usize, u64)> {
+    let offset = offset.checked_sub(CONTEXT_BASE)?;
+    Some(((offset / CONTEXT_STRIDE) as usize, offset % CONTEXT_STRIDE))
+}
+
+impl MmioDevice for Plic {
+    fn size(&self) -> u64 {
+        CONTEXT_BASE + CONTEXT_STRIDE * self.contexts.len() as u64
+    }
+
+    fn device_tree_node(&self) -> Option<DeviceNode> {
+        Some(DeviceNode {
+            name: "plic",
+            compatible: "riscv,plic0",
+            hart_interrupts: vec![Interrupt::MachineExternal, Interrupt::SupervisorExternal],
+            properties: vec![
+                ("#interrupt-cells", Property::Cells(vec![1])),
+                ("interrupt-controller", Property::Empty),
+                (
+                    "riscv,ndev",
+                    Property::Cells(vec![self.sources.len() as u32 - 1]),
+                ),
+            ],
+        })
+    }
+
+    fn read(&mut self, offset: u64, width: usize) -> Option<u64> {
+        if width != 4 {
+            return None;
+        }
+        self.read_word(offset).map(u64::from)
+    }
+
+    fn write(&mut self, offset: u64, width: usize, val: u64) -> Option<()> {
+        if width != 4 {
+            return None;
+        }
+        self.write_word(offset, val as u32)
+    }
+
+    fn interrupts(&self, hart: u64) -> u64 {
+        let machine = hart as usize * 2;
+        (machine..machine + 2)
+            .filter(|&ctx| ctx < self.contexts.len() && self.highest_pending(ctx).is_some())
+            .fold(0, |lines, ctx| {
+                let int = if ctx % 2 == 0 {
+                    Interrupt::MachineExternal
+                } else {
+                    Interrupt::SupervisorExternal
+                };
+                lines | 1 << int.code()
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Plic;
+    use crate::{
+        cpu::{csr, Cpu},
+        device::MmioDevice,
+        memory::Memory,
+        trap::{Exception, Interrupt},
+        RV64I,
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    const BASE: u64 = 0x0C00_0000;
+
+    #[test]
+    fn claim_and_complete() {
+        let plic = Rc::new(RefCell::new(Plic::new(32, 2)));
+        let mut memory = Memory::<RV64I>::with_size(0x1000);
+        memory.map_device(BASE, plic.clone());
+
+        // Source 3 with priority 2, and source 5 with priority 1 are
+        // enabled for the supervisor context.
+        memory.write_int(BASE + 3 * 4, 4, 2).unwrap();
+        memory.write_int(BASE + 5 * 4, 4, 1).unwrap();
+        memory.write_int(BASE + 0x2080, 4, 1 << 3 | 1 << 5).unwrap();
+        assert_eq!(memory.read_int(BASE + 3 * 4, 4, false), Ok(2));
+
+        plic.borrow_mut().set_source(5, true);
+        plic.borrow_mut().set_source(3, true);
+        assert_eq!(
+            memory.read_int(BASE + 0x1000, 4, false),
+            Ok(1 << 3 | 1 << 5)
+        );
+        assert_eq!(
+            plic.borrow().interrupts(0),
+            1 << Interrupt::SupervisorExternal.code()
+        );
+        assert_eq!(plic.borrow().interrupts(1), 0);
+
+        // The threshold masks source 5.
+        memory.write_int(BASE + 0x20_1000, 4, 1).unwrap();
+        let claim = BASE + 0x20_1004;
+        assert_eq!(memory.read_int(claim, 4, false), Ok(3));
+        assert_eq!(memory.read_int(claim, 4, false), Ok(0));
+        assert_eq!(plic.borrow().interrupts(0), 0);
+
+        // The line is still high, so completing makes the source pending again.
+        memory.write_int(claim, 4, 3).unwrap();
+        assert_eq!(
+            memory.read_int(BASE + 0x1000, 4, false),
+            Ok(1 << 3 | 1 << 5)
+        );
+        plic.borrow_mut().set_source(3, false);
+        assert_eq!(memory.read_int(claim, 4, false), Ok(3));
+        memory.write_int(claim, 4, 3).unwrap();
+        assert_eq!(memory.read_int(BASE + 0x1000, 4, false), Ok(1 << 5));
+
+        memory.write_int(BASE + 0x20_1000, 4, 0).unwrap();
+        assert_eq!(memory.read_int(claim, 4, false), Ok(5));
+
+        // Only word accesses are supported.
+        assert_eq!(
+            memory.read_int(BASE + 0x20_1000, 8, false),
+            Err(Exception::LoadAccessFault)
+        );
+    }
+
+    #[test]
+    fn machine_external_interrupt() {
+        let plic = Rc::new(RefCell::new(Plic::new(8, 2)));
+        let mut memory = Memory::<RV64I>::with_size(0x1000);
+        memory.write(0x100_u64, 0x0000_0013_u32); // nop
+        memory.map_device(BASE, plic.clone());
+
+        let mut cpu = Cpu::<RV64I>::new(memory);
+        cpu.registers().write_csr(csr::MIE, 1 << 11);
+        cpu.registers().write_csr(csr::MTVEC, 0x100);
+        cpu.registers().mstatus().set_mie(true);
+
+        let mut plic_ref = plic.borrow_mut();
+        plic_ref.write(4, 4, 1).unwrap();
+        plic_ref.write(0x2000, 4, 1 << 1).unwrap();
+        plic_ref.set_source(1, true);
+        drop(plic_ref);
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().read_csr(csr::MCAUSE), 1 << 63 | 11);
+        assert_eq!(cpu.registers().pc(), 0x100);
+
+        // Claiming the interrupt clears the pending bit in `mip`.
+        assert_eq!(plic.borrow_mut().read(0x20_0004, 4), Some(1));
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers().read_csr(csr::MIP), 0);
+    }
+}
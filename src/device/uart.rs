@@ -0,0 +1,232 @@
+//! A minimal serial console, that is compatible with the 16550 UART.
+
+use super::MmioDevice;
+use crate::{
+    dtb::{DeviceNode, Property},
+    trap::Interrupt,
+};
+use std::{collections::VecDeque, io::Write};
+
+/// Receive buffer (read) and transmit holding register (write).
+const RBR_THR: u64 = 0;
+/// Interrupt enable register.
+const IER: u64 = 1;
+/// Interrupt identification register (read) and FIFO control register (write).
+const IIR_FCR: u64 = 2;
+/// Line control register.
+const LCR: u64 = 3;
+/// Modem control register.
+const MCR: u64 = 4;
+/// Line status register.
+const LSR: u64 = 5;
+/// Modem status register.
+const MSR: u64 = 6;
+/// Scratch register.
+const SCR: u64 = 7;
+
+/// Enables the interrupt if received data is available.
+const IER_RX_AVAILABLE: u8 = 1 << 0;
+/// The divisor latch access bit, which maps the divisor to the first two registers.
+const LCR_DLAB: u8 = 1 << 7;
+/// Received data is available.
+const LSR_DATA_READY: u8 = 1 << 0;
+/// The transmit holding register, and the transmitter are empty.
+const LSR_TX_EMPTY: u8 = 1 << 5 | 1 << 6;
+/// No interrupt is pending.
+const IIR_NONE: u8 = 0x01;
+/// Received data is available.
+const IIR_RX_AVAILABLE: u8 = 0x04;
+
+/// A UART, that writes every transmitted byte into a [`Write`] sink,
+/// and receives bytes from an input queue.
+///
+/// Bytes are transmitted immediately, so the transmitter is always empty.
+/// The only supported interrupt is the received data available interrupt,
+/// which asserts the interrupt line, as long as there's input
+/// and the interrupt is enabled in `IER`.
+#[derive(Debug)]
+pub struct Uart<W> {
+    output: W,
+    input: VecDeque<u8>,
+    interrupt: Interrupt,
+    ier: u8,
+    lcr: u8,
+    mcr: u8,
+    scr: u8,
+    divisor: u16,
+}
+
+impl<W: Write> Uart<W> {
+    /// Creates a new UART, that writes all transmitted bytes into `output`,
+    /// and raises [`Interrupt::MachineExternal`] on the first hart.
+    pub fn new(output: W) -> Self {
+        Self::with_interrupt(output, Interrupt::MachineExternal)
+    }
+
+    /// Creates a new UART, that writes all transmitted bytes into `output`,
+    /// and raises the given interrupt on the first hart.
+    pub fn with_interrupt(output: W, interrupt: Interrupt) -> Self {
+        Self {
+            output,
+            input: VecDeque::new(),
+            interrupt,
+            ier: 0,
+            lcr: 0,
+            mcr: 0,
+            scr: 0,
+            divisor: 0,
+        }
+    }
+
+    /// Adds the bytes to the input queue, from which the guest can read them.
+    pub fn push_input(&mut self, bytes: &[u8]) {
+        self.input.extend(bytes);
+    }
+
+    /// Returns a reference to the output sink.
+    pub fn output(&self) -> &W {
+        &self.output
+    }
+
+    fn dlab(&self) -> bool {
+        self.lcr & LCR_DLAB != 0
+    }
+
+    fn rx_interrupt(&self) -> bool {
+        self.ier & IER_RX_AVAILABLE != 0 && !self.input.is_empty()
+    }
+}
+
+impl<W: Write> MmioDevice for Uart<W> {
+    fn size(&self) -> u64 {
+        8
+    }
+
+    /// The interrupt of the UART is wired directly to the first hart, and
+    /// can't be described by the device tree, so the guest has to poll it.
+    fn device_tree_node(&self) -> Option<DeviceNode> {
+        Some(DeviceNode {
+            name: "serial",
+            compatible: "ns16550a",
+            hart_interrupts: vec![],
+            properties: vec![("clock-frequency", Property::Cells(vec![3_686_400]))],
+        })
+    }
+
+    fn read(&mut self, offset: u64, width: usize) -> Option<u64> {
+        if width != 1 {
+            return None;
+        }
+
+        let val = match offset {
+            RBR_THR if self.dlab() => self.divisor as u8,
+            IER if self.dlab() => (self.divisor >> 8) as u8,
+            RBR_THR => self.input.pop_front().unwrap_or(0),
+            IER => self.ier,
+            IIR_FCR if self.rx_interrupt() => IIR_RX_AVAILABLE,
+            IIR_FCR => IIR_NONE,
+            LCR => self.lcr,
+            MCR => self.mcr,
+            LSR if self.input.is_empty() => LSR_TX_EMPTY,
+            LSR => LSR_TX_EMPTY | LSR_DATA_READY,
+            MSR => 0,
+            SCR => self.scr,
+            _ => return None,
+        };
+        Some(u64::from(val))
+    }
+
+    fn write(&mut self, offset: u64, width: usize, val: u64) -> Option<()> {
+        if width != 1 {
+            return None;
+        }
+
+        let val = val as u8;
+        match offset {
+            RBR_THR if self.dlab() => self.divisor = (self.divisor & 0xFF00) | u16::from(val),
+            IER if self.dlab() => self.divisor = (self.divisor & 0x00FF) | u16::from(val) << 8,
+            // Errors of the sink are ignored, like a disconnected serial line.
+            RBR_THR => drop(self.output.write_all(&[val])),
+            IER => self.ier = val & 0x0F,
+            LCR => self.lcr = val,
+            MCR => self.mcr = val & 0x1F,
+            SCR => self.scr = val,
+            // The FIFOs can't be configured, and the status registers are read-only.
+            IIR_FCR | LSR | MSR => {}
+            _ => return None,
+        }
+        Some(())
+    }
+
+    fn interrupts(&self, hart: u64) -> u64 {
+        // The interrupt line is only connected to the first hart.
+        ((hart == 0 && self.rx_interrupt()) as u64) << self.interrupt.code()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Uart;
+    use crate::{
+        cpu::{Cpu, XRegister},
+        device::MmioDevice,
+        memory::Memory,
+        trap::Interrupt,
+        RV64I,
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn transmit() {
+        let program: &[u32] = &[
+            0x0680_0093, // addi x1, x0, 'h'
+            0x0011_0023, // sb x1, 0(x2)
+            0x0690_0093, // addi x1, x0, 'i'
+            0x0011_0023, // sb x1, 0(x2)
+            0x0051_4183, // lbu x3, 5(x2)
+            0x0010_0073, // ebreak
+        ];
+
+        let uart = Rc::new(RefCell::new(Uart::new(Vec::new())));
+        let mut memory = Memory::<RV64I>::with_size(0x100);
+        for (idx, inst) in program.iter().enumerate() {
+            memory.write(idx as u64 * 4, *inst);
+        }
+        memory.map_device(0x1000, uart.clone());
+
+        let mut cpu = Cpu::<RV64I>::new(memory);
+        cpu.registers().write_x(XRegister::from(2), 0x1000);
+        cpu.run().unwrap();
+
+        assert_eq!(uart.borrow().output(), b"hi");
+        assert_eq!(cpu.registers().read_x(XRegister::from(3)), 0x60);
+    }
+
+    #[test]
+    fn receive() {
+        let mut uart = Uart::with_interrupt(Vec::new(), Interrupt::SupervisorExternal);
+        uart.push_input(b"ok");
+        assert_eq!(uart.read(5, 1), Some(0x61));
+        assert_eq!(uart.interrupts(0), 0);
+
+        uart.write(1, 1, 1).unwrap();
+        assert_eq!(uart.interrupts(0), 1 << 9);
+        assert_eq!(uart.interrupts(1), 0);
+        assert_eq!(uart.read(2, 1), Some(0x04));
+
+        assert_eq!(uart.read(0, 1), Some(u64::from(b'o')));
+        assert_eq!(uart.read(0, 1), Some(u64::from(b'k')));
+        assert_eq!(uart.read(5, 1), Some(0x60));
+        assert_eq!(uart.read(2, 1), Some(0x01));
+        assert_eq!(uart.interrupts(0), 0);
+
+        // The divisor latch shadows the first two registers.
+        uart.write(3, 1, 0x80).unwrap();
+        uart.write(0, 1, 0x03).unwrap();
+        assert_eq!(uart.read(0, 1), Some(0x03));
+        assert_eq!(uart.read(1, 1), Some(0x00));
+        uart.write(3, 1, 0x03).unwrap();
+        assert_eq!(uart.read(1, 1), Some(0x01));
+        assert_eq!(uart.read(4, 4), None);
+    }
+}
@@ -0,0 +1,351 @@
+//! Generation of flattened device trees, which describe the emulated
+//! machine to the guest.
+//!
+//! See the [`devicetree specification`] for the format.
+//!
+//! [`devicetree specification`]: https://www.devicetree.org/specifications/
+
+use crate::{memory::Memory, trap::Interrupt, Base, Extensions};
+use std::collections::HashMap;
+
+const FDT_MAGIC: u32 = 0xD00D_FEED;
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_END: u32 = 0x9;
+
+/// The size of the header, which is followed by the memory reservation block.
+const HEADER_SIZE: usize = 40;
+/// The size of the memory reservation block, which only contains the terminating entry.
+const RESERVATION_SIZE: usize = 16;
+
+/// The frequency of `mtime`, that is reported to the guest.
+///
+/// `mtime` only advances if the embedder [ticks](crate::device::clint::Clint::tick)
+/// the CLINT, so this is only a nominal value.
+pub const TIMEBASE_FREQUENCY: u32 = 10_000_000;
+
+/// The standard extensions in the order in which they appear in `riscv,isa`.
+const ISA_ORDER: &str = "IEMAFDQC";
+
+/// The value of a property inside the device tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Property {
+    /// A property without a value, which is used for boolean properties.
+    Empty,
+    /// A list of 32-bit cells.
+    Cells(Vec<u32>),
+    /// A string.
+    String(&'static str),
+}
+
+/// The description of a device inside the device tree, which is
+/// provided by [`MmioDevice::device_tree_node`](crate::device::MmioDevice::device_tree_node).
+///
+/// The node is named `name@base`, and contains the `compatible` and `reg`
+/// properties, in addition to the given ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceNode {
+    /// The name of the node, without the unit address.
+    pub name: &'static str,
+    /// The value of the `compatible` property.
+    pub compatible: &'static str,
+    /// The interrupts, that the device raises on every hart.
+    ///
+    /// They are listed in `interrupts-extended` for every hart in order,
+    /// using the interrupt controller of the hart.
+    pub hart_interrupts: Vec<Interrupt>,
+    /// Additional properties of the node.
+    pub properties: Vec<(&'static str, Property)>,
+}
+
+/// Writes the structure and strings blocks of a device tree.
+#[derive(Default)]
+struct Writer {
+    structure: Vec<u8>,
+    strings: Vec<u8>,
+    offsets: HashMap<&'static str, u32>,
+}
+
+impl Writer {
+    fn token(&mut self, token: u32) {
+        self.structure.extend_from_slice(&token.to_be_bytes());
+    }
+
+    /// Appends `data`, padded to a multiple of 4 bytes.
+    fn padded(&mut self, data: &[u8]) {
+        self.structure.extend_from_slice(data);
+        let padding = (4 - data.len() % 4) % 4;
+        self.structure.resize(self.structure.len() + padding, 0);
+    }
+
+    fn begin_node(&mut self, name: &str) {
+        self.token(FDT_BEGIN_NODE);
+        let mut name = name.as_bytes().to_vec();
+        name.push(0);
+        self.padded(&name);
+    }
+
+    fn end_node(&mut self) {
+        self.token(FDT_END_NODE);
+    }
+
+    fn property(&mut self, name: &'static str, value: &[u8]) {
+        let strings = &mut self.strings;
+        let offset = *self.offsets.entry(name).or_insert_with(|| {
+            let offset = strings.len() as u32;
+            strings.extend_from_slice(name.as_bytes());
+            strings.push(0);
+            offset
+        });
+
+        self.token(FDT_PROP);
+        self.token(value.len() as u32);
+        self.token(offset);
+        self.padded(value);
+    }
+
+    fn cells(&mut self, name: &'static str, cells: &[u32]) {
+        let value = cells
+            .iter()
+            .flat_map(|cell| cell.to_be_bytes().to_vec())
+            .collect::<Vec<_>>();
+        self.property(name, &value);
+    }
+
+    fn string(&mut self, name: &'static str, value: &str) {
+        let mut value = value.as_bytes().to_vec();
+        value.push(0);
+        self.property(name, &value);
+    }
+
+    /// Assembles the header, and all blocks of the device tree.
+    fn finish(mut self, boot_hart: u32) -> Vec<u8> {
+        self.token(FDT_END);
+
+        let structure = HEADER_SIZE + RESERVATION_SIZE;
+        let strings = structure + self.structure.len();
+        let total = strings + self.strings.len();
+
+        let header = [
+            FDT_MAGIC,
+            total as u32,
+            structure as u32,
+            strings as u32,
+            HEADER_SIZE as u32,
+            17,
+            16,
+            boot_hart,
+            self.strings.len() as u32,
+            self.structure.len() as u32,
+        ];
+
+        let mut fdt = Vec::with_capacity(total);
+        for field in &header {
+            fdt.extend_from_slice(&field.to_be_bytes());
+        }
+        fdt.resize(structure, 0);
+        fdt.extend_from_slice(&self.structure);
+        fdt.extend_from_slice(&self.strings);
+        fdt
+    }
+}
+
+/// Splits a 64-bit address and size into the cells of a `reg` property,
+/// with two address and two size cells.
+fn reg(addr: u64, size: u64) -> [u32; 4] {
+    [
+        (addr >> 32) as u32,
+        addr as u32,
+        (size >> 32) as u32,
+        size as u32,
+    ]
+}
+
+/// Generates a flattened device tree, that describes a machine with the given number
+/// of harts, the RAM of the `memory`, and all devices that are mapped into it.
+///
+/// The `riscv,isa` property of every hart contains the given `extensions`.
+/// Devices are placed inside the `/soc` node, if they provide a
+/// [node](crate::device::MmioDevice::device_tree_node).
+pub fn generate<B: Base>(extensions: Extensions, memory: &Memory<B>, harts: usize) -> Vec<u8> {
+    let mut fdt = Writer::default();
+
+    fdt.begin_node("");
+    fdt.cells("#address-cells", &[2]);
+    fdt.cells("#size-cells", &[2]);
+    fdt.string("compatible", "rivenu");
+    fdt.string("model", "rivenu");
+
+    fdt.begin_node(&format!("memory@{:x}", memory.base()));
+    fdt.string("device_type", "memory");
+    fdt.cells("reg", &reg(memory.base(), memory.size() as u64));
+    fdt.end_node();
+
+    let isa = ISA_ORDER
+        .chars()
+        .filter(|&ext| extensions.contains(ext))
+        .fold(format!("rv{}", B::XLEN), |mut isa, ext| {
+            isa.push(ext.to_ascii_lowercase());
+            isa
+        });
+    let mmu = if B::XLEN == 32 {
+        "riscv,sv32"
+    } else {
+        "riscv,sv39"
+    };
+
+    fdt.begin_node("cpus");
+    fdt.cells("#address-cells", &[1]);
+    fdt.cells("#size-cells", &[0]);
+    fdt.cells("timebase-frequency", &[TIMEBASE_FREQUENCY]);
+    for hart in 0..harts as u32 {
+        fdt.begin_node(&format!("cpu@{:x}", hart));
+        fdt.string("device_type", "cpu");
+        fdt.cells("reg", &[hart]);
+        fdt.string("status", "okay");
+        fdt.string("compatible", "riscv");
+        fdt.string("riscv,isa", &isa);
+        fdt.string("mmu-type", mmu);
+
+        fdt.begin_node("interrupt-controller");
+        fdt.cells("#interrupt-cells", &[1]);
+        fdt.property("interrupt-controller", &[]);
+        fdt.string("compatible", "riscv,cpu-intc");
+        fdt.cells("phandle", &[hart + 1]);
+        fdt.end_node();
+
+        fdt.end_node();
+    }
+    fdt.end_node();
+
+    fdt.begin_node("soc");
+    fdt.cells("#address-cells", &[2]);
+    fdt.cells("#size-cells", &[2]);
+    fdt.string("compatible", "simple-bus");
+    fdt.property("ranges", &[]);
+    for (base, device) in memory.devices() {
+        let device = device.borrow();
+        let node = match device.device_tree_node() {
+            Some(node) => node,
+            None => continue,
+        };
+
+        fdt.begin_node(&format!("{}@{:x}", node.name, base));
+        fdt.string("compatible", node.compatible);
+        fdt.cells("reg", &reg(base, device.size()));
+
+        if !node.hart_interrupts.is_empty() {
+            let interrupts = (0..harts as u32)
+                .flat_map(|hart| {
+                    node.hart_interrupts
+                        .iter()
+                        .flat_map(move |int| vec![hart + 1, int.code() as u32])
+                })
+                .collect::<Vec<_>>();
+            fdt.cells("interrupts-extended", &interrupts);
+        }
+
+        for (name, value) in &node.properties {
+            match value {
+                Property::Empty => fdt.property(name, &[]),
+                Property::Cells(cells) => fdt.cells(name, cells),
+                Property::String(string) => fdt.string(name, string),
+            }
+        }
+        fdt.end_node();
+    }
+    fdt.end_node();
+
+    fdt.end_node();
+    fdt.finish(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate, FDT_MAGIC};
+    use crate::{
+        device::{clint::Clint, plic::Plic, uart::Uart},
+        memory::Memory,
+        Extensions, RV64I,
+    };
+    use std::{cell::RefCell, collections::BTreeMap, convert::TryInto, rc::Rc};
+
+    fn be32(data: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn c_str(data: &[u8]) -> &str {
+        let end = data.iter().position(|&b| b == 0).unwrap();
+        std::str::from_utf8(&data[..end]).unwrap()
+    }
+
+    /// Parses a device tree into a map from the path of every property to it's value.
+    fn parse(fdt: &[u8]) -> BTreeMap<String, Vec<u8>> {
+        assert_eq!(be32(fdt, 0), FDT_MAGIC);
+        assert_eq!(be32(fdt, 4) as usize, fdt.len());
+        let mut offset = be32(fdt, 8) as usize;
+        let strings = be32(fdt, 12) as usize;
+
+        let mut path = Vec::new();
+        let mut props = BTreeMap::new();
+        loop {
+            let token = be32(fdt, offset);
+            offset += 4;
+            match token {
+                1 => {
+                    let name = c_str(&fdt[offset..]);
+                    offset += (name.len() + 4) & !3;
+                    path.push(name.to_string());
+                }
+                2 => drop(path.pop().unwrap()),
+                3 => {
+                    let len = be32(fdt, offset) as usize;
+                    let name = c_str(&fdt[strings + be32(fdt, offset + 4) as usize..]);
+                    let value = fdt[offset + 8..offset + 8 + len].to_vec();
+                    offset += 8 + ((len + 3) & !3);
+                    props.insert(format!("{}/{}", path.join("/"), name), value);
+                }
+                9 => break,
+                _ => panic!("invalid token {}", token),
+            }
+        }
+        assert!(path.is_empty());
+        props
+    }
+
+    #[test]
+    fn machine() {
+        let mut memory = Memory::<RV64I>::with_size(0x8000);
+        memory.map_device(0x0200_0000, Rc::new(RefCell::new(Clint::with_harts(2))));
+        memory.map_device(0x0C00_0000, Rc::new(RefCell::new(Plic::new(31, 4))));
+        memory.map_device(0x1000_0000, Rc::new(RefCell::new(Uart::new(Vec::new()))));
+
+        let extensions = Extensions::from_letters(&['A', 'C', 'I', 'S', 'U']).unwrap();
+        let props = parse(&generate(extensions, &memory, 2));
+
+        assert_eq!(props["/memory@0/device_type"], b"memory\0");
+        assert_eq!(
+            props["/memory@0/reg"],
+            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x80, 0]
+        );
+
+        assert_eq!(props["/cpus/cpu@1/reg"], [0, 0, 0, 1]);
+        assert_eq!(props["/cpus/cpu@1/riscv,isa"], b"rv64iac\0");
+        assert_eq!(
+            props["/cpus/cpu@1/interrupt-controller/phandle"],
+            [0, 0, 0, 2]
+        );
+
+        assert_eq!(props["/soc/clint@2000000/compatible"], b"riscv,clint0\0");
+        assert_eq!(
+            props["/soc/clint@2000000/interrupts-extended"],
+            [
+                0, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0, 1, 0, 0, 0, 7, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 2,
+                0, 0, 0, 7
+            ]
+        );
+        assert_eq!(props["/soc/plic@c000000/riscv,ndev"], [0, 0, 0, 31]);
+        assert_eq!(props["/soc/serial@10000000/compatible"], b"ns16550a\0");
+    }
+}
@@ -0,0 +1,43 @@
+use crate::{cpu::ElfError, instruction::DecodeError, trap::Exception};
+use derive_more::Display;
+
+/// The result type for the high-level emulation API.
+pub type Result<T, E = EmuError> = std::result::Result<T, E>;
+
+/// Any error that can occur while setting up, or running the emulator.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Display)]
+pub enum EmuError {
+    /// The configuration that was used to create the CPU is invalid.
+    #[display(fmt = "invalid configuration: {}", _0)]
+    InvalidConfig(&'static str),
+    /// Failed to load an ELF file.
+    #[display(fmt = "{}", _0)]
+    Elf(ElfError),
+    /// Failed to decode an instruction.
+    #[display(fmt = "{}", _0)]
+    Decode(DecodeError),
+    /// An exception was raised, that wasn't handled.
+    #[display(fmt = "unhandled exception: {:?}", _0)]
+    Trap(Exception),
+}
+
+impl std::error::Error for EmuError {}
+
+impl From<ElfError> for EmuError {
+    fn from(err: ElfError) -> Self {
+        Self::Elf(err)
+    }
+}
+
+impl From<DecodeError> for EmuError {
+    fn from(err: DecodeError) -> Self {
+        Self::Decode(err)
+    }
+}
+
+impl From<Exception> for EmuError {
+    fn from(err: Exception) -> Self {
+        Self::Trap(err)
+    }
+}
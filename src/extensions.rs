@@ -0,0 +1,62 @@
+use crate::cpu::MISA_EXTENSIONS;
+
+/// A set of ISA extensions, which are identified by their letter.
+///
+/// The extensions are stored as a bitset, that uses the same layout as the
+/// `Extensions` field of `misa`, so bit `0` is the `A` extension, and
+/// bit `25` is the `Z` extension.
+///
+/// The default set contains every extension that is supported by the emulator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Extensions(u32);
+
+impl Extensions {
+    /// Creates a set that doesn't contain any extension.
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Creates a set from the letters of the extensions.
+    ///
+    /// Returns `None` if a letter is not an uppercase ASCII letter.
+    pub fn from_letters(letters: &[char]) -> Option<Self> {
+        letters.iter().try_fold(Self::empty(), |set, &ext| {
+            ext.is_ascii_uppercase()
+                .then(|| Self(set.0 | 1 << (ext as u8 - b'A')))
+        })
+    }
+
+    /// Checks if this set contains the extension with the given letter.
+    pub fn contains(self, ext: char) -> bool {
+        ext.is_ascii_uppercase() && self.0 & 1 << (ext as u8 - b'A') != 0
+    }
+
+    /// Returns the bits of this set, as they are reported in `misa`.
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+}
+
+impl Default for Extensions {
+    fn default() -> Self {
+        Self::from_letters(MISA_EXTENSIONS).expect("the supported extensions are valid")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Extensions;
+
+    #[test]
+    fn letters() {
+        let set = Extensions::from_letters(&['A', 'I', 'Z']).unwrap();
+        assert_eq!(set.bits(), 1 | 1 << 8 | 1 << 25);
+        assert!(set.contains('A'));
+        assert!(!set.contains('M'));
+        assert!(!set.contains('a'));
+
+        assert_eq!(Extensions::from_letters(&['I', 'm']), None);
+        assert!(Extensions::default().contains('S'));
+        assert!(!Extensions::empty().contains('I'));
+    }
+}
@@ -0,0 +1,537 @@
+//! A stub for the GDB remote serial protocol.
+//!
+//! The [`GdbStub`] drives a [`Cpu`] and allows debugging the guest program
+//! using a real debugger, e.g. by running `target remote :1234` inside GDB.
+//!
+//! The registers are numbered `x0`-`x31`, followed by the `pc`.
+//!
+//! An exception stops the guest at the faulting instruction, and the trap is
+//! taken once the guest continues.
+//!
+//! The stub is single-threaded, and doesn't read from the connection while
+//! the guest is running, so a running guest can't be interrupted by the debugger.
+//!
+//! See the [`GDB documentation`] for more information about the protocol.
+//!
+//! [`GDB documentation`]: https://sourceware.org/gdb/onlinedocs/gdb/Remote-Protocol.html
+
+use crate::{
+    cpu::{Cpu, HaltReason, Hooks, StepOutcome, XRegister},
+    trap::Exception,
+    Address, Base,
+};
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    io::{self, Read, Write},
+    net::{TcpListener, ToSocketAddrs},
+};
+
+/// The encoding of the `ebreak` instruction, which is used for software breakpoints.
+const EBREAK: u32 = 0x0010_0073;
+
+/// Index of the `pc` in the register numbering of GDB.
+const PC_REGISTER: usize = 32;
+
+/// A GDB remote serial protocol server, that controls a single [`Cpu`].
+#[allow(clippy::module_name_repetitions)]
+pub struct GdbStub<'cpu, B: Base, H: Hooks<B>> {
+    cpu: &'cpu mut Cpu<B, H>,
+    /// The original instruction at every address, where a breakpoint was inserted.
+    breakpoints: HashMap<u64, u32>,
+    /// The exception, and its trap value, that stopped the execution and
+    /// is taken before the guest continues.
+    pending: Option<(Exception, u64)>,
+}
+
+impl<'cpu, B: Base, H: Hooks<B>> GdbStub<'cpu, B, H> {
+    /// Creates a new stub that will control the given `cpu`.
+    pub fn new(cpu: &'cpu mut Cpu<B, H>) -> Self {
+        Self {
+            cpu,
+            breakpoints: HashMap::new(),
+            pending: None,
+        }
+    }
+
+    /// Waits for a single debugger to connect to `addr`, and serves it
+    /// until it detaches.
+    ///
+    /// ## Errors
+    ///
+    /// Returns an error if binding to the address, or communicating with the debugger failed.
+    pub fn listen(&mut self, addr: impl ToSocketAddrs) -> io::Result<()> {
+        let (stream, _) = TcpListener::bind(addr)?.accept()?;
+        self.serve(stream)
+    }
+
+    /// Serves the debugger that is connected via `stream`, until it
+    /// detaches, kills the program, or the connection is closed.
+    ///
+    /// ## Errors
+    ///
+    /// Returns an error if reading from, or writing to the stream failed.
+    pub fn serve(&mut self, mut stream: impl Read + Write) -> io::Result<()> {
+        while let Some(packet) = read_packet(&mut stream)? {
+            let done = matches!(packet.as_bytes().first(), Some(b'D') | Some(b'k'));
+            if let Some(response) = self.handle(&packet) {
+                write_packet(&mut stream, &response)?;
+            }
+
+            if done {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles a single packet, without the framing, and returns the response.
+    ///
+    /// Returns `None` if the packet doesn't expect a response.
+    pub fn handle(&mut self, packet: &str) -> Option<String> {
+        let (cmd, args) = match packet.chars().next() {
+            Some(cmd) => packet.split_at(cmd.len_utf8()),
+            None => ("", ""),
+        };
+        let response = match cmd {
+            "?" => "S05".to_string(),
+            "g" => self.read_registers(),
+            "G" => self.write_registers(args),
+            "p" => self.read_register(args),
+            "P" => self.write_register(args),
+            "m" => self.read_memory(args),
+            "M" => self.write_memory(args),
+            "s" => self.step().unwrap_or_else(|| stop_reply(None)),
+            "c" => self.resume(),
+            "Z" => self.insert_breakpoint(args),
+            "z" => self.remove_breakpoint(args),
+            "D" => "OK".to_string(),
+            "k" => return None,
+            _ if packet.starts_with("qSupported") => "PacketSize=4000".to_string(),
+            _ => String::new(),
+        };
+        Some(response)
+    }
+
+    fn register(&self, idx: usize) -> Option<u64> {
+        let regs = self.cpu.registers();
+        match idx {
+            0..=31 => Some(regs.read_x(XRegister::from(idx as u8)).to_u64()),
+            PC_REGISTER => Some(regs.pc().to_u64()),
+            _ => None,
+        }
+    }
+
+    fn set_register(&mut self, idx: usize, val: u64) -> bool {
+        let regs = self.cpu.registers_mut();
+        match idx {
+            0..=31 => regs.write_x(XRegister::from(idx as u8), B::Addr::from_u64(val)),
+            PC_REGISTER => *regs.pc_mut() = B::Addr::from_u64(val),
+            _ => return false,
+        }
+        true
+    }
+
+    fn read_registers(&self) -> String {
+        (0..=PC_REGISTER)
+            .filter_map(|idx| self.register(idx).map(encode_register::<B>))
+            .collect()
+    }
+
+    fn write_registers(&mut self, args: &str) -> String {
+        let width = B::XLEN / 4;
+        if args.len() != width * (PC_REGISTER + 1) {
+            return "E01".to_string();
+        }
+
+        for idx in 0..=PC_REGISTER {
+            let val = match decode_register(&args[idx * width..(idx + 1) * width]) {
+                Some(val) => val,
+                None => return "E01".to_string(),
+            };
+            self.set_register(idx, val);
+        }
+        "OK".to_string()
+    }
+
+    fn read_register(&self, args: &str) -> String {
+        usize::from_str_radix(args, 16)
+            .ok()
+            .and_then(|idx| self.register(idx))
+            .map_or_else(|| "E01".to_string(), encode_register::<B>)
+    }
+
+    fn write_register(&mut self, args: &str) -> String {
+        let mut parts = args.splitn(2, '=');
+        let idx = parts
+            .next()
+            .and_then(|idx| usize::from_str_radix(idx, 16).ok());
+        let val = parts.next().and_then(decode_register);
+
+        match (idx, val) {
+            (Some(idx), Some(val)) if self.set_register(idx, val) => "OK".to_string(),
+            _ => "E01".to_string(),
+        }
+    }
+
+    /// Parses the `addr,len` arguments of a memory packet, and checks that
+    /// the range is inside the memory.
+    fn memory_range(&self, args: &str) -> Option<(u64, usize)> {
+        let mut parts = args.splitn(2, ',');
+        let addr = u64::from_str_radix(parts.next()?, 16).ok()?;
+        let len = usize::from_str_radix(parts.next()?, 16).ok()?;
+
+        self.cpu.memory().contains(addr, len).then(|| (addr, len))
+    }
+
+    fn read_memory(&self, args: &str) -> String {
+        let (addr, len) = match self.memory_range(args) {
+            Some(range) => range,
+            None => return "E01".to_string(),
+        };
+
+        let bytes = match self.cpu.memory().read_bytes(B::Addr::from_u64(addr), len) {
+            Ok(bytes) => bytes,
+            Err(_) => return "E01".to_string(),
+        };
+
+        let mut out = String::with_capacity(len * 2);
+        for byte in bytes {
+            let _ = write!(out, "{:02x}", byte);
+        }
+        out
+    }
+
+    fn write_memory(&mut self, args: &str) -> String {
+        let mut parts = args.splitn(2, ':');
+        let range = parts.next().and_then(|range| self.memory_range(range));
+        let data = parts.next().and_then(decode_hex);
+
+        let (addr, data) = match (range, data) {
+            (Some((addr, len)), Some(data)) if data.len() == len => (addr, data),
+            _ => return "E01".to_string(),
+        };
+
+        match self
+            .cpu
+            .memory_mut()
+            .write_bytes(B::Addr::from_u64(addr), &data)
+        {
+            Ok(()) => "OK".to_string(),
+            Err(_) => "E01".to_string(),
+        }
+    }
+
+    /// Executes a single instruction, and returns the stop reply if the guest stopped.
+    ///
+    /// An exception stops the guest at the faulting instruction, and the trap is taken
+    /// when the guest continues, so the next step enters the trap handler instead.
+    fn step(&mut self) -> Option<String> {
+        if let Some((err, tval)) = self.pending.take() {
+            self.cpu.take_trap(err.into(), tval);
+            return None;
+        }
+
+        match self.cpu.step() {
+            // GDB only supports 8-bit exit codes.
+            Ok(StepOutcome::Halted(HaltReason::Exit(code))) => Some(format!("W{:02x}", code as u8)),
+            Ok(StepOutcome::Halted(_)) | Ok(StepOutcome::WaitingForInterrupt) => {
+                Some(stop_reply(None))
+            }
+            Ok(_) => None,
+            Err(err) => {
+                self.pending = Some((err, self.cpu.trap_value()));
+                Some(stop_reply(Some(err)))
+            }
+        }
+    }
+
+    /// Executes instructions until the guest stops.
+    ///
+    /// The stream is not read while the guest is running, so an interrupt request
+    /// (`Ctrl-C` inside GDB) is only seen after the guest stopped on its own.
+    /// A guest that is stuck in a loop without a breakpoint can't be interrupted.
+    fn resume(&mut self) -> String {
+        loop {
+            if let Some(reply) = self.step() {
+                return reply;
+            }
+        }
+    }
+
+    /// Parses the `type,addr,kind` arguments of a breakpoint packet,
+    /// and returns the address if it's a software breakpoint.
+    fn breakpoint_addr(&self, args: &str) -> Option<u64> {
+        let mut parts = args.splitn(3, ',');
+        if parts.next()? != "0" {
+            return None;
+        }
+
+        let addr = u64::from_str_radix(parts.next()?, 16).ok()?;
+        (addr % 4 == 0 && self.cpu.memory().contains(addr, 4)).then(|| addr)
+    }
+
+    fn insert_breakpoint(&mut self, args: &str) -> String {
+        let addr = match self.breakpoint_addr(args) {
+            Some(addr) => addr,
+            None => return String::new(),
+        };
+
+        let memory = self.cpu.memory_mut();
+        let original = memory.read(B::Addr::from_u64(addr));
+        self.breakpoints.entry(addr).or_insert(original);
+        memory.write(B::Addr::from_u64(addr), EBREAK);
+        "OK".to_string()
+    }
+
+    fn remove_breakpoint(&mut self, args: &str) -> String {
+        let addr = match self.breakpoint_addr(args) {
+            Some(addr) => addr,
+            None => return String::new(),
+        };
+
+        if let Some(original) = self.breakpoints.remove(&addr) {
+            self.cpu
+                .memory_mut()
+                .write(B::Addr::from_u64(addr), original);
+        }
+        "OK".to_string()
+    }
+}
+
+/// Creates the stop reply packet, for the exception that stopped the execution.
+fn stop_reply(err: Option<Exception>) -> String {
+    let signal = match err {
+        None | Some(Exception::Breakpoint) => 5,
+        Some(Exception::IllegalInstruction) => 4,
+        Some(_) => 11,
+    };
+    format!("S{:02x}", signal)
+}
+
+/// Encodes the register value as `XLEN` bits in little endian.
+fn encode_register<B: Base>(val: u64) -> String {
+    let mut out = String::with_capacity(B::XLEN / 4);
+    for byte in &val.to_le_bytes()[..B::XLEN / 8] {
+        let _ = write!(out, "{:02x}", byte);
+    }
+    out
+}
+
+/// Decodes a little endian register value.
+fn decode_register(hex: &str) -> Option<u64> {
+    let bytes = decode_hex(hex).filter(|bytes| bytes.len() <= 8)?;
+    Some(
+        bytes
+            .iter()
+            .rev()
+            .fold(0, |val, &byte| val << 8 | u64::from(byte)),
+    )
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(hex.get(idx..idx + 2)?, 16).ok())
+        .collect()
+}
+
+fn read_byte(stream: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    match stream.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+/// Reads the next packet from the stream, and acknowledges it.
+///
+/// Returns `None` if the connection was closed.
+fn read_packet(stream: &mut (impl Read + Write)) -> io::Result<Option<String>> {
+    loop {
+        match read_byte(stream)? {
+            None => return Ok(None),
+            // An interrupt request is answered like a `?` packet.
+            Some(0x03) => return Ok(Some("?".to_string())),
+            Some(b'$') => {}
+            Some(_) => continue,
+        }
+
+        let mut data = Vec::new();
+        loop {
+            match read_byte(stream)? {
+                None => return Ok(None),
+                Some(b'#') => break,
+                Some(byte) => data.push(byte),
+            }
+        }
+
+        let mut checksum = [0; 2];
+        stream.read_exact(&mut checksum)?;
+        let expected = std::str::from_utf8(&checksum)
+            .ok()
+            .and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+
+        match String::from_utf8(data) {
+            Ok(packet) if expected == Some(checksum_of(&packet)) => {
+                stream.write_all(b"+")?;
+                return Ok(Some(packet));
+            }
+            _ => stream.write_all(b"-")?,
+        }
+    }
+}
+
+fn write_packet(stream: &mut impl Write, packet: &str) -> io::Result<()> {
+    write!(stream, "${}#{:02x}", packet, checksum_of(packet))?;
+    stream.flush()
+}
+
+fn checksum_of(packet: &str) -> u8 {
+    packet.bytes().fold(0, u8::wrapping_add)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GdbStub;
+    use crate::{
+        cpu::{csr, Cpu, XRegister},
+        device::htif::Htif,
+        memory::Memory,
+        RV32I, RV64I,
+    };
+    use std::{
+        cell::RefCell,
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        rc::Rc,
+        thread,
+    };
+
+    fn cpu<B: crate::Base>() -> Cpu<B> {
+        let mut memory = Memory::with_size(0x100);
+        memory.write(crate::Address::from_u64(0), 0x02A0_0093_u32); // addi x1, x0, 42
+        memory.write(crate::Address::from_u64(4), 0x0010_8113_u32); // addi x2, x1, 1
+        Cpu::new(memory)
+    }
+
+    #[test]
+    fn registers() {
+        let mut cpu = cpu::<RV32I>();
+        cpu.registers().write_x(XRegister::from(1), 0x1234_5678);
+        let mut stub = GdbStub::new(&mut cpu);
+
+        let regs = stub.handle("g").unwrap();
+        assert_eq!(regs.len(), 33 * 8);
+        assert_eq!(&regs[..16], "0000000078563412");
+
+        assert_eq!(stub.handle("p1").unwrap(), "78563412");
+        assert_eq!(stub.handle("P20=08000000").unwrap(), "OK");
+        assert_eq!(stub.handle("p20").unwrap(), "08000000");
+        assert_eq!(stub.handle("p21").unwrap(), "E01");
+
+        let mut regs = "0".repeat(33 * 8);
+        regs.replace_range(16..24, "ffffffff");
+        assert_eq!(stub.handle(&format!("G{}", regs)).unwrap(), "OK");
+        assert_eq!(cpu.registers().read_x(XRegister::from(2)), 0xFFFF_FFFF);
+        assert_eq!(cpu.registers().pc(), 0);
+    }
+
+    #[test]
+    fn memory_and_breakpoints() {
+        let mut cpu = cpu::<RV64I>();
+        let mut stub = GdbStub::new(&mut cpu);
+
+        assert_eq!(stub.handle("m0,4").unwrap(), "9300a002");
+        assert_eq!(stub.handle("mfe,4").unwrap(), "E01");
+        assert_eq!(stub.handle("M10,2:abcd").unwrap(), "OK");
+        assert_eq!(stub.handle("m10,2").unwrap(), "abcd");
+
+        assert_eq!(stub.handle("Z0,4,4").unwrap(), "OK");
+        assert_eq!(stub.handle("c").unwrap(), "S05");
+        assert_eq!(stub.handle("p20").unwrap(), "0400000000000000");
+        assert_eq!(stub.handle("p1").unwrap(), "2a00000000000000");
+
+        assert_eq!(stub.handle("z0,4,4").unwrap(), "OK");
+        assert_eq!(stub.handle("s").unwrap(), "S05");
+        assert_eq!(stub.handle("p2").unwrap(), "2b00000000000000");
+        assert_eq!(stub.handle("s").unwrap(), "S04");
+    }
+
+    #[test]
+    fn serve() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(b"$g#67").unwrap();
+
+            let mut response = vec![0; 1 + 1 + 33 * 16 + 3];
+            stream.read_exact(&mut response).unwrap();
+            stream.write_all(b"+$k#6b").unwrap();
+            String::from_utf8(response).unwrap()
+        });
+
+        let mut cpu = cpu::<RV64I>();
+        cpu.registers().write_x(XRegister::from(31), 0xFF);
+        let (stream, _) = listener.accept().unwrap();
+        GdbStub::new(&mut cpu).serve(stream).unwrap();
+
+        let response = client.join().unwrap();
+        assert!(response.starts_with("+$0000"));
+        assert!(response.contains("ff00000000000000"));
+        assert_eq!(response.as_bytes()[response.len() - 3], b'#');
+    }
+
+    #[test]
+    fn malformed_packets() {
+        let mut cpu = cpu::<RV64I>();
+        let mut stub = GdbStub::new(&mut cpu);
+
+        assert_eq!(stub.handle("").unwrap(), "");
+        assert_eq!(stub.handle("\u{e9}1").unwrap(), "");
+        assert_eq!(stub.handle("Z0,fffffffffffffffc,4").unwrap(), "");
+        assert_eq!(stub.handle("z0,fffffffffffffffc,4").unwrap(), "");
+    }
+
+    #[test]
+    fn exceptions() {
+        let mut cpu = cpu::<RV64I>();
+        cpu.memory_mut().write(0x8, 0x0000_0073_u32); // ecall
+        cpu.memory_mut().write(0x40, 0x0011_0193_u32); // addi x3, x2, 1
+        cpu.memory_mut().write(0x44, 0x0010_0073_u32); // ebreak
+        cpu.registers().write_csr(csr::MTVEC, 0x40);
+        let mut stub = GdbStub::new(&mut cpu);
+
+        // The guest stops at the `ecall`, and continues inside the trap handler.
+        assert_eq!(stub.handle("c").unwrap(), "S0b");
+        assert_eq!(stub.handle("p20").unwrap(), "0800000000000000");
+        assert_eq!(stub.handle("s").unwrap(), "S05");
+        assert_eq!(stub.handle("p20").unwrap(), "4000000000000000");
+        assert_eq!(stub.handle("c").unwrap(), "S05");
+        assert_eq!(stub.handle("p3").unwrap(), "2c00000000000000");
+
+        let regs = cpu.registers();
+        assert_eq!(regs.read_csr(csr::MEPC), 8);
+        assert_eq!(regs.read_csr(csr::MCAUSE), 11);
+    }
+
+    #[test]
+    fn exit() {
+        let mut cpu = cpu::<RV32I>();
+        cpu.memory_mut().write(0x8, 0x0011_2023_u32); // sw x1, 0(x2)
+        cpu.memory_mut()
+            .map_device(0x1000, Rc::new(RefCell::new(Htif::new())));
+        let mut stub = GdbStub::new(&mut cpu);
+
+        assert_eq!(stub.handle("P20=08000000").unwrap(), "OK");
+        assert_eq!(stub.handle("P2=00100000").unwrap(), "OK");
+        assert_eq!(stub.handle("P1=07000000").unwrap(), "OK");
+        assert_eq!(stub.handle("s").unwrap(), "W03");
+    }
+}
@@ -8,7 +8,9 @@
 //!
 //! [`spec`]: https://riscv.org/specifications/isa-spec-pdf/
 
+mod asm;
 mod parse;
+pub use asm::*;
 pub use parse::*;
 
 use crate::cpu::XRegister;
@@ -19,7 +21,7 @@ use std::fmt;
 ///
 /// [`Variant`]: ./enum.Variant.html
 /// [`Kind`]: ./enum.Kind.html
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct Instruction {
     /// The operands of this `Instruction`
     pub variant: Variant,
@@ -29,10 +31,133 @@ pub struct Instruction {
     pub raw: u32,
 }
 
+/// Returns the width in bytes of the instruction, whose
+/// lowest 16 bits are `low`.
+///
+/// Returns `None` for the reserved encodings of instructions,
+/// that are at least 192 bits wide.
+///
+/// See chapter 1.5 in the [`spec`].
+///
+/// [`spec`]: https://riscv.org/specifications/isa-spec-pdf/
+#[allow(clippy::module_name_repetitions)]
+pub fn instruction_width(low: u16) -> Option<usize> {
+    if low & 0b11 != 0b11 {
+        Some(2)
+    } else if low & 0b1_1100 != 0b1_1100 {
+        Some(4)
+    } else if low & 0b11_1111 == 0b01_1111 {
+        Some(6)
+    } else if low & 0b111_1111 == 0b011_1111 {
+        Some(8)
+    } else if low & 0b111_0000_0111_1111 == 0b111_0000_0111_1111 {
+        None
+    } else {
+        Some(10 + 2 * ((low >> 12) & 0b111) as usize)
+    }
+}
+
+impl Instruction {
+    /// Returns the size of this instruction in bytes, which is `2` for
+    /// compressed instructions, and `4` otherwise.
+    ///
+    /// See [`instruction_width`].
+    pub fn size(&self) -> usize {
+        match instruction_width(self.raw as u16) {
+            Some(2) => 2,
+            _ => 4,
+        }
+    }
+
+    /// Returns the opcode, which is stored in the lowest 7 bits of [`raw`](Self::raw).
+    ///
+    /// This and the other bitfield accessors extract the field directly from the raw
+    /// instruction, even if the encoding variant of this instruction doesn't use it.
+    pub fn opcode(&self) -> u8 {
+        (self.raw & 0x7F) as u8
+    }
+
+    /// Returns the destination register field, stored in bits 7 to 11.
+    pub fn rd(&self) -> XRegister {
+        XRegister::from((self.raw >> 7 & 0x1F) as u8)
+    }
+
+    /// Returns the `funct3` field, stored in bits 12 to 14.
+    pub fn funct3(&self) -> u8 {
+        (self.raw >> 12 & 0x7) as u8
+    }
+
+    /// Returns the first source register field, stored in bits 15 to 19.
+    pub fn rs1(&self) -> XRegister {
+        XRegister::from((self.raw >> 15 & 0x1F) as u8)
+    }
+
+    /// Returns the second source register field, stored in bits 20 to 24.
+    pub fn rs2(&self) -> XRegister {
+        XRegister::from((self.raw >> 20 & 0x1F) as u8)
+    }
+
+    /// Returns the `funct7` field, stored in bits 25 to 31.
+    pub fn funct7(&self) -> u8 {
+        (self.raw >> 25) as u8
+    }
+
+    /// Checks if this instruction is a call, which is a `jal` or `jalr`
+    /// that stores the return address in `ra` or `t0`.
+    pub fn is_call(&self) -> bool {
+        let rd = match (self.kind, &self.variant) {
+            (Kind::JAL, Variant::J { rd, .. }) | (Kind::JALR, Variant::I { rd, .. }) => *rd,
+            _ => return false,
+        };
+
+        rd == XRegister::from(1) || rd == XRegister::from(5)
+    }
+
+    /// Formats this instruction like it's [`Display`](fmt::Display) implementation,
+    /// but replaces the relative offset of branches and `jal` with the absolute
+    /// target address, given that the instruction is located at `pc`.
+    ///
+    /// The target of a `jalr` depends on a register, so it's formatted as usual.
+    pub fn display_at(&self, pc: u64) -> String {
+        let target = |offset: i32| pc.wrapping_add(offset as u64);
+
+        match self.variant {
+            Variant::B { val, rs1, rs2 } => {
+                format!("{} 0x{:x} r{} r{}", self.kind, target(val), rs1, rs2)
+            }
+            Variant::J { val, rd } => format!("{} r{} 0x{:x}", self.kind, rd, target(val)),
+            _ => self.to_string(),
+        }
+    }
+
+    /// Checks if this instruction is a return, which is a `jalr`
+    /// that jumps to `ra` without storing the return address.
+    pub fn is_return(&self) -> bool {
+        match (self.kind, &self.variant) {
+            (Kind::JALR, Variant::I { rd, rs1, .. }) => {
+                *rd == XRegister::from(0) && *rs1 == XRegister::from(1)
+            }
+            _ => false,
+        }
+    }
+}
+
 impl fmt::Display for Instruction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if let Kind::ECALL | Kind::EBREAK = self.kind {
+        if let Kind::ECALL
+        | Kind::EBREAK
+        | Kind::MRET
+        | Kind::SRET
+        | Kind::WFI
+        | Kind::PAUSE
+        | Kind::NTL_P1
+        | Kind::NTL_PALL
+        | Kind::NTL_S1
+        | Kind::NTL_ALL = self.kind
+        {
             write!(f, "{}", self.kind)
+        } else if f.alternate() {
+            write!(f, "{} {:#}", self.kind, self.variant)
         } else {
             write!(f, "{} {}", self.kind, self.variant)
         }
@@ -44,7 +169,7 @@ impl fmt::Display for Instruction {
 /// Details can be found in chapter 2.3 in the [`spec`].
 ///
 /// [`spec`]: https://riscv.org/specifications/isa-spec-pdf/
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum Variant {
     /// The R(egister) variant is used to process data from two
     /// source registers, and store the result in a destination register.
@@ -112,8 +237,37 @@ pub enum Variant {
     },
 }
 
+/// Formats the operands.
+///
+/// The alternate form (`{:#}`) prints them in the order of the assembler syntax,
+/// separated by commas and using the ABI names of registers, like `objdump` does.
+/// Immediates are printed in decimal, except for the upper 20 bits of a
+/// [`U`](Variant::U) immediate, which are printed in hexadecimal.
 impl fmt::Display for Variant {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            return match self {
+                Variant::R { rd, rs1, rs2 } => write!(
+                    f,
+                    "{}, {}, {}",
+                    rd.abi_name(),
+                    rs1.abi_name(),
+                    rs2.abi_name()
+                ),
+                Variant::I { val, rd, rs1 } => {
+                    write!(f, "{}, {}, {}", rd.abi_name(), rs1.abi_name(), val)
+                }
+                Variant::S { val, rs1, rs2 } => {
+                    write!(f, "{}, {}({})", rs2.abi_name(), val, rs1.abi_name())
+                }
+                Variant::B { val, rs1, rs2 } => {
+                    write!(f, "{}, {}, {}", rs1.abi_name(), rs2.abi_name(), val)
+                }
+                Variant::U { val, rd } => write!(f, "{}, 0x{:x}", rd.abi_name(), *val as u32 >> 12),
+                Variant::J { val, rd } => write!(f, "{}, {}", rd.abi_name(), val),
+            };
+        }
+
         match self {
             Variant::R { rd, rs1, rs2 } => write!(f, "r{} r{} r{}", rd, rs1, rs2),
             Variant::I { val, rd, rs1 } => write!(f, "r{} r{} 0x{:x}", rd, rs1, val),
@@ -131,13 +285,24 @@ macro_rules! kind_enum {
         use derive_more::Display;
         /// A `Kind` represents any instruction kind (e.g `ld`, `addi`, etc).
         #[allow(non_camel_case_types)]
-        #[derive(Display, Debug, Clone, Copy)]
+        #[derive(Display, Debug, Clone, Copy, PartialEq, Eq, Hash)]
         pub enum Kind {
             $(
                 #[display(fmt = $str)]
                 $entry,
             )*
         }
+
+        impl Kind {
+            /// Returns the `Kind` whose mnemonic is `name`,
+            /// which is the same string that is used by it's `Display` implementation.
+            pub fn from_mnemonic(name: &str) -> Option<Self> {
+                match name {
+                    $($str => Some(Kind::$entry),)*
+                    _ => None,
+                }
+            }
+        }
     };
 }
 
@@ -191,6 +356,43 @@ kind_enum! {
 
     ECALL -> "ecall",
     EBREAK -> "ebreak",
+    MRET -> "mret",
+    SRET -> "sret",
+    WFI -> "wfi",
+    SFENCE_VMA -> "sfence.vma",
+
+    CSRRW -> "csrrw",
+    CSRRS -> "csrrs",
+    CSRRC -> "csrrc",
+    CSRRWI -> "csrrwi",
+    CSRRSI -> "csrrsi",
+    CSRRCI -> "csrrci",
+
+    LR_W -> "lr.w",
+    SC_W -> "sc.w",
+    LR_D -> "lr.d",
+    SC_D -> "sc.d",
+
+    AMOSWAP_W -> "amoswap.w",
+    AMOADD_W -> "amoadd.w",
+    AMOXOR_W -> "amoxor.w",
+    AMOAND_W -> "amoand.w",
+    AMOOR_W -> "amoor.w",
+    AMOMIN_W -> "amomin.w",
+    AMOMAX_W -> "amomax.w",
+    AMOMINU_W -> "amominu.w",
+    AMOMAXU_W -> "amomaxu.w",
+
+    AMOSWAP_D -> "amoswap.d",
+    AMOADD_D -> "amoadd.d",
+    AMOXOR_D -> "amoxor.d",
+    AMOAND_D -> "amoand.d",
+    AMOOR_D -> "amoor.d",
+    AMOMIN_D -> "amomin.d",
+    AMOMAX_D -> "amomax.d",
+    AMOMINU_D -> "amominu.d",
+    AMOMAXU_D -> "amomaxu.d",
+
     LWU -> "lwu",
     LD -> "ld",
     SD -> "sd",
@@ -205,4 +407,606 @@ kind_enum! {
     SLLW -> "sllw",
     SRLW -> "srlw",
     SRAW -> "sraw",
+
+    MUL -> "mul",
+    MULH -> "mulh",
+    MULHSU -> "mulhsu",
+    MULHU -> "mulhu",
+    DIV -> "div",
+    DIVU -> "divu",
+    REM -> "rem",
+    REMU -> "remu",
+
+    MULW -> "mulw",
+    DIVW -> "divw",
+    DIVUW -> "divuw",
+    REMW -> "remw",
+    REMUW -> "remuw",
+
+    PAUSE -> "pause",
+    NTL_P1 -> "ntl.p1",
+    NTL_PALL -> "ntl.pall",
+    NTL_S1 -> "ntl.s1",
+    NTL_ALL -> "ntl.all",
+}
+
+/// A coarse classification of instructions, which can be used
+/// to group instructions without matching every [`Kind`].
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InstructionCategory {
+    /// Integer computations, including `lui` and `auipc`.
+    Arithmetic,
+    /// Loads from memory.
+    Load,
+    /// Stores to memory.
+    Store,
+    /// Conditional branches.
+    Branch,
+    /// Unconditional jumps.
+    Jump,
+    /// Environment calls and breakpoints, trap returns, and `wfi`.
+    System,
+    /// Memory and address-translation fences.
+    Fence,
+    /// Reads and writes of CSRs.
+    CsrAccess,
+    /// Load-reserved, store-conditional and atomic memory operations.
+    Atomic,
+    /// Integer multiplications and divisions.
+    MultiplyDivide,
+    /// Hints, which don't change the architectural state.
+    Hint,
+}
+
+impl Kind {
+    /// Returns the [`InstructionCategory`] of this instruction.
+    pub fn category(self) -> InstructionCategory {
+        use InstructionCategory::{
+            Arithmetic, Atomic, Branch, CsrAccess, Fence, Hint, Jump, Load, MultiplyDivide, Store,
+            System,
+        };
+
+        match self {
+            Kind::ADDI
+            | Kind::SLTI
+            | Kind::SLTIU
+            | Kind::ANDI
+            | Kind::ORI
+            | Kind::XORI
+            | Kind::SLLI
+            | Kind::SRLI
+            | Kind::SRAI
+            | Kind::ADD
+            | Kind::SLT
+            | Kind::SLTU
+            | Kind::AND
+            | Kind::OR
+            | Kind::XOR
+            | Kind::SLL
+            | Kind::SLR
+            | Kind::SUB
+            | Kind::SRL
+            | Kind::SRA
+            | Kind::LUI
+            | Kind::AUIPC
+            | Kind::ADDIW
+            | Kind::SLLIW
+            | Kind::SRLIW
+            | Kind::SRAIW
+            | Kind::ADDW
+            | Kind::SUBW
+            | Kind::SLLW
+            | Kind::SRLW
+            | Kind::SRAW => Arithmetic,
+
+            Kind::LB | Kind::LH | Kind::LW | Kind::LD | Kind::LBU | Kind::LHU | Kind::LWU => Load,
+            Kind::SB | Kind::SH | Kind::SW | Kind::SD => Store,
+
+            Kind::BEQ | Kind::BNE | Kind::BLT | Kind::BGE | Kind::BLTU | Kind::BGEU => Branch,
+            Kind::JAL | Kind::JALR => Jump,
+
+            Kind::ECALL | Kind::EBREAK | Kind::MRET | Kind::SRET | Kind::WFI => System,
+            Kind::FENCE | Kind::FENCE_I | Kind::SFENCE_VMA => Fence,
+
+            Kind::CSRRW
+            | Kind::CSRRS
+            | Kind::CSRRC
+            | Kind::CSRRWI
+            | Kind::CSRRSI
+            | Kind::CSRRCI => CsrAccess,
+
+            Kind::LR_W
+            | Kind::SC_W
+            | Kind::LR_D
+            | Kind::SC_D
+            | Kind::AMOSWAP_W
+            | Kind::AMOADD_W
+            | Kind::AMOXOR_W
+            | Kind::AMOAND_W
+            | Kind::AMOOR_W
+            | Kind::AMOMIN_W
+            | Kind::AMOMAX_W
+            | Kind::AMOMINU_W
+            | Kind::AMOMAXU_W
+            | Kind::AMOSWAP_D
+            | Kind::AMOADD_D
+            | Kind::AMOXOR_D
+            | Kind::AMOAND_D
+            | Kind::AMOOR_D
+            | Kind::AMOMIN_D
+            | Kind::AMOMAX_D
+            | Kind::AMOMINU_D
+            | Kind::AMOMAXU_D => Atomic,
+
+            Kind::MUL
+            | Kind::MULH
+            | Kind::MULHSU
+            | Kind::MULHU
+            | Kind::DIV
+            | Kind::DIVU
+            | Kind::REM
+            | Kind::REMU
+            | Kind::MULW
+            | Kind::DIVW
+            | Kind::DIVUW
+            | Kind::REMW
+            | Kind::REMUW => MultiplyDivide,
+
+            Kind::PAUSE | Kind::NTL_P1 | Kind::NTL_PALL | Kind::NTL_S1 | Kind::NTL_ALL => Hint,
+        }
+    }
+
+    /// Returns the default number of cycles, that are modeled for this instruction.
+    ///
+    /// This is a rough estimate, where simple arithmetic instructions take
+    /// one cycle, and memory accesses, jumps, and system instructions take longer.
+    pub fn default_cost(self) -> u32 {
+        match self.category() {
+            InstructionCategory::Arithmetic | InstructionCategory::Hint => 1,
+            InstructionCategory::Branch
+            | InstructionCategory::Jump
+            | InstructionCategory::Store => 2,
+            InstructionCategory::Load
+            | InstructionCategory::CsrAccess
+            | InstructionCategory::MultiplyDivide => 3,
+            InstructionCategory::Atomic | InstructionCategory::Fence => 5,
+            InstructionCategory::System => 10,
+        }
+    }
+
+    /// Returns the letter of the extension, that this instruction is part of,
+    /// or `None` if it's part of the base ISA, or of an extension without a letter.
+    pub fn extension(self) -> Option<char> {
+        match self.category() {
+            InstructionCategory::Atomic => Some('A'),
+            InstructionCategory::MultiplyDivide => Some('M'),
+            _ => None,
+        }
+    }
+
+    /// Checks if this instruction is only available in the `RV64I` base ISA,
+    /// and thus is illegal on an `RV32I` machine.
+    pub fn requires_rv64(self) -> bool {
+        matches!(
+            self,
+            Kind::LWU
+                | Kind::LD
+                | Kind::SD
+                | Kind::ADDIW
+                | Kind::SLLIW
+                | Kind::SRLIW
+                | Kind::SRAIW
+                | Kind::ADDW
+                | Kind::SUBW
+                | Kind::SLLW
+                | Kind::SRLW
+                | Kind::SRAW
+                | Kind::MULW
+                | Kind::DIVW
+                | Kind::DIVUW
+                | Kind::REMW
+                | Kind::REMUW
+                | Kind::LR_D
+                | Kind::SC_D
+                | Kind::AMOSWAP_D
+                | Kind::AMOADD_D
+                | Kind::AMOXOR_D
+                | Kind::AMOAND_D
+                | Kind::AMOOR_D
+                | Kind::AMOMIN_D
+                | Kind::AMOMAX_D
+                | Kind::AMOMINU_D
+                | Kind::AMOMAXU_D
+        )
+    }
+
+    /// Returns the major opcode, which is stored in the lowest 7 bits of the instruction.
+    pub fn opcode(self) -> u8 {
+        match self {
+            Kind::ADDI
+            | Kind::SLTI
+            | Kind::SLTIU
+            | Kind::ANDI
+            | Kind::ORI
+            | Kind::XORI
+            | Kind::SLLI
+            | Kind::SRLI
+            | Kind::SRAI => 0b001_0011,
+            Kind::ADDIW | Kind::SLLIW | Kind::SRLIW | Kind::SRAIW => 0b001_1011,
+            Kind::ADD
+            | Kind::SLT
+            | Kind::SLTU
+            | Kind::AND
+            | Kind::OR
+            | Kind::XOR
+            | Kind::SLL
+            | Kind::SLR
+            | Kind::SUB
+            | Kind::SRL
+            | Kind::SRA
+            | Kind::MUL
+            | Kind::MULH
+            | Kind::MULHSU
+            | Kind::MULHU
+            | Kind::DIV
+            | Kind::DIVU
+            | Kind::REM
+            | Kind::REMU
+            | Kind::NTL_P1
+            | Kind::NTL_PALL
+            | Kind::NTL_S1
+            | Kind::NTL_ALL => 0b011_0011,
+            Kind::ADDW
+            | Kind::SUBW
+            | Kind::SLLW
+            | Kind::SRLW
+            | Kind::SRAW
+            | Kind::MULW
+            | Kind::DIVW
+            | Kind::DIVUW
+            | Kind::REMW
+            | Kind::REMUW => 0b011_1011,
+            Kind::LUI => 0b011_0111,
+            Kind::AUIPC => 0b001_0111,
+            Kind::JAL => 0b110_1111,
+            Kind::JALR => 0b110_0111,
+            Kind::FENCE | Kind::FENCE_I | Kind::PAUSE => 0b000_1111,
+            Kind::SFENCE_VMA => 0b111_0011,
+            _ => match self.category() {
+                InstructionCategory::Load => 0b000_0011,
+                InstructionCategory::Store => 0b010_0011,
+                InstructionCategory::Branch => 0b110_0011,
+                InstructionCategory::Atomic => 0b010_1111,
+                _ => 0b111_0011,
+            },
+        }
+    }
+
+    /// Returns the `funct3` field, which is stored in bits 12 to 14 of the instruction,
+    /// or `None` if this instruction doesn't have one.
+    #[allow(clippy::match_same_arms)]
+    pub fn funct3(self) -> Option<u8> {
+        Some(match self {
+            Kind::ADDI | Kind::ADDIW | Kind::ADD | Kind::SUB | Kind::ADDW | Kind::SUBW => 0b000,
+            Kind::SLLI | Kind::SLLIW | Kind::SLL | Kind::SLLW => 0b001,
+            Kind::SLTI | Kind::SLT => 0b010,
+            Kind::SLTIU | Kind::SLTU => 0b011,
+            Kind::XORI | Kind::XOR => 0b100,
+            Kind::SRLI | Kind::SRAI | Kind::SRLIW | Kind::SRAIW => 0b101,
+            Kind::SRL | Kind::SRA | Kind::SRLW | Kind::SRAW => 0b101,
+            Kind::ORI | Kind::OR => 0b110,
+            Kind::ANDI | Kind::AND => 0b111,
+
+            Kind::MUL | Kind::MULW => 0b000,
+            Kind::MULH => 0b001,
+            Kind::MULHSU => 0b010,
+            Kind::MULHU => 0b011,
+            Kind::DIV | Kind::DIVW => 0b100,
+            Kind::DIVU | Kind::DIVUW => 0b101,
+            Kind::REM | Kind::REMW => 0b110,
+            Kind::REMU | Kind::REMUW => 0b111,
+
+            Kind::JALR => 0b000,
+
+            Kind::BEQ => 0b000,
+            Kind::BNE => 0b001,
+            Kind::BLT => 0b100,
+            Kind::BGE => 0b101,
+            Kind::BLTU => 0b110,
+            Kind::BGEU => 0b111,
+
+            Kind::LB | Kind::SB => 0b000,
+            Kind::LH | Kind::SH => 0b001,
+            Kind::LW | Kind::SW => 0b010,
+            Kind::LD | Kind::SD => 0b011,
+            Kind::LBU => 0b100,
+            Kind::LHU => 0b101,
+            Kind::LWU => 0b110,
+
+            Kind::FENCE | Kind::PAUSE => 0b000,
+            Kind::FENCE_I => 0b001,
+
+            Kind::ECALL | Kind::EBREAK | Kind::MRET | Kind::SRET | Kind::WFI => 0b000,
+            Kind::SFENCE_VMA => 0b000,
+            Kind::CSRRW => 0b001,
+            Kind::CSRRS => 0b010,
+            Kind::CSRRC => 0b011,
+            Kind::CSRRWI => 0b101,
+            Kind::CSRRSI => 0b110,
+            Kind::CSRRCI => 0b111,
+
+            Kind::NTL_P1 | Kind::NTL_PALL | Kind::NTL_S1 | Kind::NTL_ALL => 0b000,
+
+            Kind::LR_W
+            | Kind::SC_W
+            | Kind::AMOSWAP_W
+            | Kind::AMOADD_W
+            | Kind::AMOXOR_W
+            | Kind::AMOAND_W
+            | Kind::AMOOR_W
+            | Kind::AMOMIN_W
+            | Kind::AMOMAX_W
+            | Kind::AMOMINU_W
+            | Kind::AMOMAXU_W => 0b010,
+            Kind::LR_D
+            | Kind::SC_D
+            | Kind::AMOSWAP_D
+            | Kind::AMOADD_D
+            | Kind::AMOXOR_D
+            | Kind::AMOAND_D
+            | Kind::AMOOR_D
+            | Kind::AMOMIN_D
+            | Kind::AMOMAX_D
+            | Kind::AMOMINU_D
+            | Kind::AMOMAXU_D => 0b011,
+
+            // `slr` is not a real instruction, so it has no encoding.
+            Kind::LUI | Kind::AUIPC | Kind::JAL | Kind::SLR => return None,
+        })
+    }
+
+    /// Returns the `funct7` field, which is stored in the upper 7 bits of the instruction,
+    /// or `None` if this instruction doesn't have one.
+    ///
+    /// Shifts by an immediate store the shift amount below it, so on `RV64I`,
+    /// the lowest bit of `funct7` is part of the shift amount. Atomics use the
+    /// lowest two bits for the `aq` and `rl` bits, which are `0` here.
+    pub fn funct7(self) -> Option<u8> {
+        let amo = |funct5: u8| Some(funct5 << 2);
+
+        match self {
+            Kind::ADD
+            | Kind::SLT
+            | Kind::SLTU
+            | Kind::AND
+            | Kind::OR
+            | Kind::XOR
+            | Kind::SLL
+            | Kind::SRL
+            | Kind::ADDW
+            | Kind::SLLW
+            | Kind::SRLW
+            | Kind::SLLI
+            | Kind::SRLI
+            | Kind::SLLIW
+            | Kind::SRLIW
+            | Kind::NTL_P1
+            | Kind::NTL_PALL
+            | Kind::NTL_S1
+            | Kind::NTL_ALL => Some(0),
+            Kind::SUB | Kind::SRA | Kind::SUBW | Kind::SRAW | Kind::SRAI | Kind::SRAIW => {
+                Some(0b010_0000)
+            }
+            Kind::SFENCE_VMA => Some(0b000_1001),
+            Kind::MUL
+            | Kind::MULH
+            | Kind::MULHSU
+            | Kind::MULHU
+            | Kind::DIV
+            | Kind::DIVU
+            | Kind::REM
+            | Kind::REMU
+            | Kind::MULW
+            | Kind::DIVW
+            | Kind::DIVUW
+            | Kind::REMW
+            | Kind::REMUW => Some(0b000_0001),
+
+            Kind::AMOADD_W | Kind::AMOADD_D => amo(0b00000),
+            Kind::AMOSWAP_W | Kind::AMOSWAP_D => amo(0b00001),
+            Kind::LR_W | Kind::LR_D => amo(0b00010),
+            Kind::SC_W | Kind::SC_D => amo(0b00011),
+            Kind::AMOXOR_W | Kind::AMOXOR_D => amo(0b00100),
+            Kind::AMOOR_W | Kind::AMOOR_D => amo(0b01000),
+            Kind::AMOAND_W | Kind::AMOAND_D => amo(0b01100),
+            Kind::AMOMIN_W | Kind::AMOMIN_D => amo(0b10000),
+            Kind::AMOMAX_W | Kind::AMOMAX_D => amo(0b10100),
+            Kind::AMOMINU_W | Kind::AMOMINU_D => amo(0b11000),
+            Kind::AMOMAXU_W | Kind::AMOMAXU_D => amo(0b11100),
+
+            _ => None,
+        }
+    }
+
+    /// Checks if this instruction is a conditional branch.
+    pub fn is_branch(self) -> bool {
+        self.category() == InstructionCategory::Branch
+    }
+
+    /// Checks if this instruction is a load.
+    ///
+    /// Load-reserved instructions are [atomic](InstructionCategory::Atomic),
+    /// and not considered loads.
+    pub fn is_load(self) -> bool {
+        self.category() == InstructionCategory::Load
+    }
+
+    /// Checks if this instruction is a store.
+    ///
+    /// Store-conditional instructions are [atomic](InstructionCategory::Atomic),
+    /// and not considered stores.
+    pub fn is_store(self) -> bool {
+        self.category() == InstructionCategory::Store
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, instruction_width, Instruction, InstructionCategory, Kind, Variant};
+    use crate::cpu::XRegister;
+    use crate::RV64I;
+
+    #[test]
+    fn calls_and_returns() {
+        let call = |raw| decode::<RV64I>(raw).unwrap().is_call();
+        let ret = |raw| decode::<RV64I>(raw).unwrap().is_return();
+
+        assert!(call(0x0100_00EF)); // jal ra, 16
+        assert!(call(0x0005_02E7)); // jalr t0, 0(a0)
+        assert!(call(0x0002_80E7)); // jalr ra, 0(t0)
+        assert!(!call(0x0080_006F)); // jal x0, 8
+        assert!(!call(0x0000_8067)); // jalr x0, 0(ra)
+
+        assert!(ret(0x0000_8067)); // jalr x0, 0(ra)
+        assert!(!ret(0x0002_80E7)); // jalr ra, 0(t0)
+        assert!(!ret(0x0100_00EF)); // jal ra, 16
+    }
+
+    #[test]
+    fn widths() {
+        assert_eq!(instruction_width(0x0001), Some(2)); // c.nop
+        assert_eq!(instruction_width(0x0093), Some(4)); // addi
+        assert_eq!(instruction_width(0x001F), Some(6));
+        assert_eq!(instruction_width(0x003F), Some(8));
+        assert_eq!(instruction_width(0x107F), Some(12));
+        assert_eq!(instruction_width(0x707F), None);
+    }
+
+    #[test]
+    fn categories() {
+        let category = |raw| decode::<RV64I>(raw).unwrap().kind.category();
+
+        assert_eq!(category(0x0020_80B3), InstructionCategory::Arithmetic); // add x1, x1, x2
+        assert_eq!(category(0x0000_0537), InstructionCategory::Arithmetic); // lui a0, 0
+        assert_eq!(category(0x0005_3503), InstructionCategory::Load); // ld a0, 0(a0)
+        assert_eq!(category(0x00A5_2023), InstructionCategory::Store); // sw a0, 0(a0)
+        assert_eq!(category(0xFE01_1CE3), InstructionCategory::Branch); // bne x2, x0, -8
+        assert_eq!(category(0x0100_00EF), InstructionCategory::Jump); // jal ra, 16
+        assert_eq!(category(0x0000_0073), InstructionCategory::System); // ecall
+        assert_eq!(category(0x1050_0073), InstructionCategory::System); // wfi
+        assert_eq!(category(0x0000_100F), InstructionCategory::Fence); // fence.i
+        assert_eq!(category(0x1200_0073), InstructionCategory::Fence); // sfence.vma
+        assert_eq!(category(0x3000_2573), InstructionCategory::CsrAccess); // csrrs a0, mstatus, x0
+        assert_eq!(category(0x1005_252F), InstructionCategory::Atomic); // lr.w a0, (a0)
+        assert_eq!(category(0x00B5_352F), InstructionCategory::Atomic); // amoadd.d a0, a1, (a0)
+        assert_eq!(category(0x0100_000F), InstructionCategory::Hint); // pause
+        assert_eq!(category(0x02C5_C533), InstructionCategory::MultiplyDivide); // div a0, a1, a2
+
+        let kind = |raw| decode::<RV64I>(raw).unwrap().kind;
+        assert!(kind(0xFE01_1CE3).is_branch());
+        assert!(!kind(0x0100_00EF).is_branch());
+        assert!(kind(0x0005_3503).is_load());
+        assert!(!kind(0x1005_252F).is_load());
+        assert!(kind(0x00A5_2023).is_store());
+        assert!(!kind(0x0005_3503).is_store());
+    }
+
+    #[test]
+    fn requires_rv64() {
+        assert!(Kind::LD.requires_rv64());
+        assert!(Kind::ADDW.requires_rv64());
+        assert!(Kind::AMOMAXU_D.requires_rv64());
+        assert!(Kind::REMUW.requires_rv64());
+        assert!(!Kind::DIV.requires_rv64());
+        assert!(!Kind::ADDI.requires_rv64());
+        assert!(!Kind::LW.requires_rv64());
+        assert!(!Kind::LR_W.requires_rv64());
+    }
+
+    #[test]
+    fn size() {
+        let addi = decode::<RV64I>(0x0010_0093).unwrap(); // addi x1, x0, 1
+        assert_eq!(addi.size(), 4);
+
+        // The C extension is not supported yet, so only the raw bits are compressed.
+        let compressed = Instruction {
+            raw: 0x4085, // c.li x1, 1
+            ..addi
+        };
+        assert_eq!(compressed.size(), 2);
+    }
+
+    #[test]
+    fn display_at() {
+        let display_at = |raw, pc| decode::<RV64I>(raw).unwrap().display_at(pc);
+
+        assert_eq!(display_at(0x00C0_00EF, 0x1000), "jal r1 0x100c"); // jal ra, 12
+        assert_eq!(display_at(0xFE01_1CE3, 0x1010), "bne 0x1008 r2 r0"); // bne x2, x0, -8
+        assert_eq!(display_at(0x0000_8067, 0x1000), "jalr r0 r1 0x0"); // jalr x0, 0(ra)
+        assert_eq!(display_at(0x0010_0093, 0x1000), "addi r1 r0 0x1"); // addi x1, x0, 1
+    }
+
+    #[test]
+    fn alternate_display() {
+        let display = |raw| format!("{:#}", decode::<RV64I>(raw).unwrap());
+
+        assert_eq!(display(0x00E6_86B3), "add a3, a3, a4"); // add x13, x13, x14
+        assert_eq!(display(0xFF01_0113), "addi sp, sp, -16");
+        assert_eq!(display(0x0011_3423), "sd ra, 8(sp)");
+        assert_eq!(display(0xFEB5_1CE3), "bne a0, a1, -8");
+        assert_eq!(display(0x0001_1537), "lui a0, 0x11");
+        assert_eq!(display(0xFFFF_F297), "auipc t0, 0xfffff");
+        assert_eq!(display(0xFFDF_F06F), "jal zero, -4");
+        assert_eq!(display(0x0000_0073), "ecall");
+
+        // The default form is unchanged.
+        let addi = decode::<RV64I>(0xFF01_0113).unwrap();
+        assert_eq!(addi.to_string(), "addi r2 r2 0xfffffff0");
+    }
+
+    #[test]
+    fn encoding_fields() {
+        assert_eq!(Kind::ADD.funct7(), Some(0));
+        assert_eq!(Kind::SUB.funct7(), Some(0x20));
+        assert_eq!(Kind::SRAW.funct7(), Some(0x20));
+        assert_eq!(Kind::ADDI.funct7(), None);
+
+        assert_eq!(Kind::SUB.opcode(), 0b011_0011);
+        assert_eq!(Kind::LWU.opcode(), 0b000_0011);
+        assert_eq!(Kind::AMOMAXU_D.opcode(), 0b010_1111);
+        assert_eq!(Kind::CSRRCI.opcode(), 0b111_0011);
+
+        assert_eq!(Kind::LWU.funct3(), Some(0b110));
+        assert_eq!(Kind::AMOMAXU_D.funct3(), Some(0b011));
+        assert_eq!(Kind::AMOMAXU_D.funct7(), Some(0b111_0000));
+        assert_eq!(Kind::LUI.funct3(), None);
+
+        // The fields are the inverse of the decode tables.
+        let sub = decode::<RV64I>(0x40F7_0733).unwrap(); // sub a4, a4, a5
+        assert_eq!(sub.raw & 0x7F, u32::from(Kind::SUB.opcode()));
+        assert_eq!(sub.raw >> 12 & 0x7, u32::from(Kind::SUB.funct3().unwrap()));
+        assert_eq!(sub.raw >> 25, u32::from(Kind::SUB.funct7().unwrap()));
+    }
+
+    #[test]
+    fn raw_fields() {
+        let sub = decode::<RV64I>(0x40F7_0733).unwrap(); // sub a4, a4, a5
+        let (rd, rs1, rs2) = match sub.variant {
+            Variant::R { rd, rs1, rs2 } => (rd, rs1, rs2),
+            _ => panic!("sub is not R-type"),
+        };
+
+        assert_eq!(sub.opcode(), Kind::SUB.opcode());
+        assert_eq!(sub.rd(), rd);
+        assert_eq!(sub.rs1(), rs1);
+        assert_eq!(sub.rs2(), rs2);
+        assert_eq!(sub.funct3(), 0b000);
+        assert_eq!(sub.funct7(), 0b010_0000);
+
+        let sd = decode::<RV64I>(0x0011_3423).unwrap(); // sd ra, 8(sp)
+        assert_eq!(sd.rd(), XRegister::from(8)); // the low bits of the immediate
+        assert_eq!(sd.rs1(), XRegister::from(2));
+        assert_eq!(sd.rs2(), XRegister::from(1));
+    }
 }
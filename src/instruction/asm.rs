@@ -0,0 +1,561 @@
+//! Encoding of instructions, and assembling them from their textual form.
+
+use super::{decode, DecodeError, Instruction, Kind, Variant};
+use crate::{cpu::XRegister, Base};
+use derive_more::Display;
+
+/// The error that is returned if a line couldn't be assembled.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Display, Clone, PartialEq, Eq)]
+pub enum AsmError {
+    /// The mnemonic doesn't belong to any supported instruction.
+    #[display(fmt = "unknown mnemonic `{}`", _0)]
+    UnknownMnemonic(String),
+    /// An operand is not a register, immediate or memory operand
+    /// of the expected form.
+    #[display(fmt = "invalid operand `{}`", _0)]
+    InvalidOperand(String),
+    /// The instruction was given the wrong number of operands.
+    #[display(fmt = "expected {} operands, found {}", expected, found)]
+    OperandCount {
+        /// The number of operands the instruction takes.
+        expected: usize,
+        /// The number of operands that were given.
+        found: usize,
+    },
+    /// An immediate doesn't fit into the instruction.
+    #[display(fmt = "immediate {} is out of range", _0)]
+    ImmediateOutOfRange(i64),
+    /// The assembled instruction is not valid for the base ISA.
+    #[display(fmt = "{}", _0)]
+    Decode(DecodeError),
+}
+
+impl std::error::Error for AsmError {}
+
+/// Encodes an instruction into it's raw 32bit form.
+///
+/// The immediates of `variant` are interpreted the same way [`decode`] produces them,
+/// so `decode(encode(kind, variant))` yields the same instruction again.
+///
+/// Returns `None` if `variant` is not the encoding variant of `kind`.
+pub fn encode(kind: Kind, variant: Variant) -> Option<u32> {
+    // Instructions without operands are encoded using fixed operand fields.
+    match kind {
+        Kind::ECALL => return Some(0x0000_0073),
+        Kind::EBREAK => return Some(0x0010_0073),
+        Kind::SRET => return Some(0x1020_0073),
+        Kind::MRET => return Some(0x3020_0073),
+        Kind::WFI => return Some(0x1050_0073),
+        Kind::PAUSE => return Some(0x0100_000F),
+        Kind::NTL_P1 => return Some(0x0020_0033),
+        Kind::NTL_PALL => return Some(0x0030_0033),
+        Kind::NTL_S1 => return Some(0x0040_0033),
+        Kind::NTL_ALL => return Some(0x0050_0033),
+        _ => {}
+    }
+
+    let opcode = u32::from(kind.opcode());
+    let funct3 = u32::from(kind.funct3().unwrap_or(0));
+    let reg = |reg: XRegister| u32::from(u8::from(reg));
+    let base = opcode | funct3 << 12;
+
+    let raw = match (opcode, variant) {
+        (_, Variant::R { rd, rs1, rs2 })
+            if matches!(opcode, 0b011_0011 | 0b011_1011 | 0b010_1111 | 0b111_0011) =>
+        {
+            let funct7 = u32::from(kind.funct7()?);
+            base | reg(rd) << 7 | reg(rs1) << 15 | reg(rs2) << 20 | funct7 << 25
+        }
+        (_, Variant::I { val, rd, rs1 })
+            if matches!(
+                opcode,
+                0b001_0011 | 0b001_1011 | 0b000_0011 | 0b110_0111 | 0b000_1111 | 0b111_0011
+            ) =>
+        {
+            let funct7 = u32::from(kind.funct7().unwrap_or(0));
+            let imm = (val as u32 & 0xFFF) | funct7 << 5;
+            base | reg(rd) << 7 | reg(rs1) << 15 | imm << 20
+        }
+        (0b010_0011, Variant::S { val, rs1, rs2 }) => {
+            let imm = val as u32;
+            base | (imm & 0x1F) << 7 | reg(rs1) << 15 | reg(rs2) << 20 | (imm >> 5 & 0x7F) << 25
+        }
+        (0b110_0011, Variant::B { val, rs1, rs2 }) => {
+            let imm = val as u32;
+            base | (imm >> 11 & 0x1) << 7
+                | (imm >> 1 & 0xF) << 8
+                | reg(rs1) << 15
+                | reg(rs2) << 20
+                | (imm >> 5 & 0x3F) << 25
+                | (imm >> 12 & 0x1) << 31
+        }
+        (_, Variant::U { val, rd }) if matches!(opcode, 0b011_0111 | 0b001_0111) => {
+            opcode | reg(rd) << 7 | val as u32 & 0xFFFF_F000
+        }
+        (0b110_1111, Variant::J { val, rd }) => {
+            let imm = val as u32;
+            opcode
+                | reg(rd) << 7
+                | (imm >> 12 & 0xFF) << 12
+                | (imm >> 11 & 0x1) << 20
+                | (imm >> 1 & 0x3FF) << 21
+                | (imm >> 20 & 0x1) << 31
+        }
+        _ => return None,
+    };
+
+    Some(raw)
+}
+
+/// Assembles a single line of assembly, like `addi x8, x8, 0xb0`.
+///
+/// Registers can be written as `xN` or using their ABI name, and immediates
+/// are either decimal or hexadecimal with a `0x` prefix. Loads, stores and `jalr`
+/// take their address as `imm(rs1)`, and atomics as `(rs1)`. Branch and jump targets
+/// are the offset relative to the instruction, and `lui` and `auipc` take the upper 20 bits.
+///
+/// ## Errors
+///
+/// Returns an [`AsmError`] if the line is malformed, or if the instruction
+/// is not valid for the base ISA `B`.
+#[allow(clippy::module_name_repetitions)]
+pub fn parse_asm<B: Base>(line: &str) -> Result<Instruction, AsmError> {
+    let line = line.trim();
+    let (mnemonic, operands) = match line.find(char::is_whitespace) {
+        Some(idx) => (&line[..idx], line[idx..].trim()),
+        None => (line, ""),
+    };
+    let operands = if operands.is_empty() {
+        vec![]
+    } else {
+        operands.split(',').map(str::trim).collect::<Vec<_>>()
+    };
+
+    let unknown = || AsmError::UnknownMnemonic(mnemonic.to_string());
+    let kind = match mnemonic {
+        "fence.i" => Kind::FENCE_I,
+        _ => Kind::from_mnemonic(mnemonic).ok_or_else(unknown)?,
+    };
+
+    let variant = parse_operands(kind, &operands)?;
+    let raw = encode(kind, variant).ok_or_else(unknown)?;
+    decode::<B>(raw).map_err(AsmError::Decode)
+}
+
+fn parse_operands(kind: Kind, operands: &[&str]) -> Result<Variant, AsmError> {
+    let count = |expected: usize| {
+        if operands.len() == expected {
+            Ok(())
+        } else {
+            Err(AsmError::OperandCount {
+                expected,
+                found: operands.len(),
+            })
+        }
+    };
+    let zero = XRegister::from(0);
+
+    Ok(match kind {
+        Kind::ECALL
+        | Kind::EBREAK
+        | Kind::SRET
+        | Kind::MRET
+        | Kind::WFI
+        | Kind::FENCE_I
+        | Kind::PAUSE
+        | Kind::NTL_P1
+        | Kind::NTL_PALL
+        | Kind::NTL_S1
+        | Kind::NTL_ALL => {
+            count(0)?;
+            Variant::I {
+                val: 0,
+                rd: zero,
+                rs1: zero,
+            }
+        }
+
+        Kind::FENCE => {
+            let val = if operands.is_empty() {
+                0xFF
+            } else {
+                count(2)?;
+                fence_set(operands[0])? << 4 | fence_set(operands[1])?
+            };
+            Variant::I {
+                val,
+                rd: zero,
+                rs1: zero,
+            }
+        }
+
+        Kind::SFENCE_VMA => {
+            let (rs1, rs2) = if operands.is_empty() {
+                (zero, zero)
+            } else {
+                count(2)?;
+                (register(operands[0])?, register(operands[1])?)
+            };
+            Variant::R { rd: zero, rs1, rs2 }
+        }
+
+        Kind::ADDI
+        | Kind::SLTI
+        | Kind::SLTIU
+        | Kind::XORI
+        | Kind::ORI
+        | Kind::ANDI
+        | Kind::ADDIW => {
+            count(3)?;
+            Variant::I {
+                rd: register(operands[0])?,
+                rs1: register(operands[1])?,
+                val: immediate(operands[2], -0x800, 0x7FF)?,
+            }
+        }
+
+        Kind::SLLI | Kind::SRLI | Kind::SRAI | Kind::SLLIW | Kind::SRLIW | Kind::SRAIW => {
+            count(3)?;
+            let max = if let Kind::SLLIW | Kind::SRLIW | Kind::SRAIW = kind {
+                31
+            } else {
+                63
+            };
+            Variant::I {
+                rd: register(operands[0])?,
+                rs1: register(operands[1])?,
+                val: immediate(operands[2], 0, max)?,
+            }
+        }
+
+        Kind::LB
+        | Kind::LH
+        | Kind::LW
+        | Kind::LD
+        | Kind::LBU
+        | Kind::LHU
+        | Kind::LWU
+        | Kind::JALR => {
+            count(2)?;
+            let (val, rs1) = memory_operand(operands[1])?;
+            Variant::I {
+                rd: register(operands[0])?,
+                rs1,
+                val,
+            }
+        }
+
+        Kind::SB | Kind::SH | Kind::SW | Kind::SD => {
+            count(2)?;
+            let (val, rs1) = memory_operand(operands[1])?;
+            Variant::S {
+                rs2: register(operands[0])?,
+                rs1,
+                val,
+            }
+        }
+
+        Kind::BEQ | Kind::BNE | Kind::BLT | Kind::BGE | Kind::BLTU | Kind::BGEU => {
+            count(3)?;
+            Variant::B {
+                rs1: register(operands[0])?,
+                rs2: register(operands[1])?,
+                val: offset(operands[2], 13)?,
+            }
+        }
+
+        Kind::JAL => {
+            count(2)?;
+            Variant::J {
+                rd: register(operands[0])?,
+                val: offset(operands[1], 21)?,
+            }
+        }
+
+        Kind::LUI | Kind::AUIPC => {
+            count(2)?;
+            Variant::U {
+                rd: register(operands[0])?,
+                val: immediate(operands[1], 0, 0xF_FFFF)? << 12,
+            }
+        }
+
+        Kind::CSRRW | Kind::CSRRS | Kind::CSRRC => {
+            count(3)?;
+            Variant::I {
+                rd: register(operands[0])?,
+                val: immediate(operands[1], 0, 0xFFF)?,
+                rs1: register(operands[2])?,
+            }
+        }
+
+        Kind::CSRRWI | Kind::CSRRSI | Kind::CSRRCI => {
+            count(3)?;
+            Variant::I {
+                rd: register(operands[0])?,
+                val: immediate(operands[1], 0, 0xFFF)?,
+                rs1: XRegister::from(immediate(operands[2], 0, 31)? as u8),
+            }
+        }
+
+        Kind::LR_W | Kind::LR_D => {
+            count(2)?;
+            Variant::R {
+                rd: register(operands[0])?,
+                rs1: address(operands[1])?,
+                rs2: zero,
+            }
+        }
+
+        Kind::SC_W
+        | Kind::SC_D
+        | Kind::AMOSWAP_W
+        | Kind::AMOADD_W
+        | Kind::AMOXOR_W
+        | Kind::AMOAND_W
+        | Kind::AMOOR_W
+        | Kind::AMOMIN_W
+        | Kind::AMOMAX_W
+        | Kind::AMOMINU_W
+        | Kind::AMOMAXU_W
+        | Kind::AMOSWAP_D
+        | Kind::AMOADD_D
+        | Kind::AMOXOR_D
+        | Kind::AMOAND_D
+        | Kind::AMOOR_D
+        | Kind::AMOMIN_D
+        | Kind::AMOMAX_D
+        | Kind::AMOMINU_D
+        | Kind::AMOMAXU_D => {
+            count(3)?;
+            Variant::R {
+                rd: register(operands[0])?,
+                rs2: register(operands[1])?,
+                rs1: address(operands[2])?,
+            }
+        }
+
+        Kind::ADD
+        | Kind::SUB
+        | Kind::SLL
+        | Kind::SLR
+        | Kind::SLT
+        | Kind::SLTU
+        | Kind::XOR
+        | Kind::SRL
+        | Kind::SRA
+        | Kind::OR
+        | Kind::AND
+        | Kind::ADDW
+        | Kind::SUBW
+        | Kind::SLLW
+        | Kind::SRLW
+        | Kind::SRAW
+        | Kind::MUL
+        | Kind::MULH
+        | Kind::MULHSU
+        | Kind::MULHU
+        | Kind::DIV
+        | Kind::DIVU
+        | Kind::REM
+        | Kind::REMU
+        | Kind::MULW
+        | Kind::DIVW
+        | Kind::DIVUW
+        | Kind::REMW
+        | Kind::REMUW => {
+            count(3)?;
+            Variant::R {
+                rd: register(operands[0])?,
+                rs1: register(operands[1])?,
+                rs2: register(operands[2])?,
+            }
+        }
+    })
+}
+
+fn invalid(operand: &str) -> AsmError {
+    AsmError::InvalidOperand(operand.to_string())
+}
+
+fn register(operand: &str) -> Result<XRegister, AsmError> {
+    XRegister::from_name(operand).ok_or_else(|| invalid(operand))
+}
+
+/// Parses a decimal or hexadecimal immediate, that must be in the range `min..=max`.
+fn immediate(operand: &str, min: i64, max: i64) -> Result<i32, AsmError> {
+    let (negative, digits) = match operand.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, operand),
+    };
+    let val = match digits.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => digits.parse::<i64>(),
+    }
+    .map_err(|_| invalid(operand))?;
+    let val = if negative { -val } else { val };
+
+    if val < min || val > max {
+        return Err(AsmError::ImmediateOutOfRange(val));
+    }
+    Ok(val as i32)
+}
+
+/// Parses the offset of a branch or jump, which must be even and
+/// fit into a signed immediate of `bits` bits.
+fn offset(operand: &str, bits: u32) -> Result<i32, AsmError> {
+    let max = (1 << (bits - 1)) - 1;
+    let val = immediate(operand, -max - 1, max)?;
+    if val % 2 == 0 {
+        Ok(val)
+    } else {
+        Err(AsmError::ImmediateOutOfRange(i64::from(val)))
+    }
+}
+
+/// Parses a memory operand of the form `imm(rs1)`, where `imm` may be omitted.
+fn memory_operand(operand: &str) -> Result<(i32, XRegister), AsmError> {
+    let idx = operand.find('(').ok_or_else(|| invalid(operand))?;
+    let val = match operand[..idx].trim() {
+        "" => 0,
+        imm => immediate(imm, -0x800, 0x7FF)?,
+    };
+    Ok((val, address(&operand[idx..])?))
+}
+
+/// Parses the address of an atomic instruction, which is of the form `(rs1)`.
+fn address(operand: &str) -> Result<XRegister, AsmError> {
+    operand
+        .strip_prefix('(')
+        .and_then(|rest| rest.strip_suffix(')'))
+        .ok_or_else(|| invalid(operand))
+        .and_then(|reg| register(reg.trim()))
+}
+
+/// Parses the predecessor or successor set of a `fence`, like `iorw`.
+fn fence_set(operand: &str) -> Result<i32, AsmError> {
+    operand.chars().try_fold(0, |set, c| {
+        let bit = match c {
+            'i' => 0b1000,
+            'o' => 0b0100,
+            'r' => 0b0010,
+            'w' => 0b0001,
+            _ => return Err(invalid(operand)),
+        };
+        Ok(set | bit)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode, parse_asm, AsmError};
+    use crate::{
+        instruction::{decode, DecodeError, Kind},
+        RV32I, RV64I,
+    };
+
+    #[test]
+    fn round_trip() {
+        let cases: &[(&str, u32, Kind)] = &[
+            // R
+            ("add x13, x13, x14", 0x00E6_86B3, Kind::ADD),
+            ("sub a4, a4, a5", 0x40F7_0733, Kind::SUB),
+            ("sraw a0, a1, a2", 0x40C5_D53B, Kind::SRAW),
+            ("remu a0, a1, a2", 0x02C5_F533, Kind::REMU),
+            ("mulhsu a0, a1, a2", 0x02C5_A533, Kind::MULHSU),
+            ("divuw a0, a1, a2", 0x02C5_D53B, Kind::DIVUW),
+            ("amoadd.w ra, sp, (gp)", 0x0021_A0AF, Kind::AMOADD_W),
+            ("lr.d a0, (a1)", 0x1005_B52F, Kind::LR_D),
+            ("sc.w a0, a2, (a1)", 0x18C5_A52F, Kind::SC_W),
+            ("sfence.vma a0, a1", 0x12B5_0073, Kind::SFENCE_VMA),
+            // I
+            ("addi x8, x8, 0xb0", 0x0B04_0413, Kind::ADDI),
+            ("addi sp, sp, -16", 0xFF01_0113, Kind::ADDI),
+            ("addiw a0, a0, -1", 0xFFF5_051B, Kind::ADDIW),
+            ("slli a3, a3, 2", 0x0026_9693, Kind::SLLI),
+            ("srai a0, a0, 63", 0x43F5_5513, Kind::SRAI),
+            ("sraiw a4, ra, 3", 0x4030_D71B, Kind::SRAIW),
+            ("ld s0, 16(s2)", 0x0109_3403, Kind::LD),
+            ("lw ra, -4(sp)", 0xFFC1_2083, Kind::LW),
+            ("jalr ra, 0(t0)", 0x0002_80E7, Kind::JALR),
+            ("csrrw ra, 0x340, sp", 0x3401_10F3, Kind::CSRRW),
+            ("csrrsi t2, 0x340, 2", 0x3401_63F3, Kind::CSRRSI),
+            ("fence", 0x0FF0_000F, Kind::FENCE),
+            ("fence rw, w", 0x0310_000F, Kind::FENCE),
+            ("fence.i", 0x0000_100F, Kind::FENCE_I),
+            ("ecall", 0x0000_0073, Kind::ECALL),
+            ("mret", 0x3020_0073, Kind::MRET),
+            ("pause", 0x0100_000F, Kind::PAUSE),
+            // S
+            ("sd ra, 8(sp)", 0x0011_3423, Kind::SD),
+            ("sb a1, 14(a4)", 0x00B7_0723, Kind::SB),
+            // B
+            ("beq s7, zero, 72", 0x040B_8463, Kind::BEQ),
+            ("bne a0, a1, -8", 0xFEB5_1CE3, Kind::BNE),
+            // U
+            ("lui a0, 0x11", 0x0001_1537, Kind::LUI),
+            ("auipc t0, 0xfffff", 0xFFFF_F297, Kind::AUIPC),
+            // J
+            ("jal ra, 2048", 0x0010_00EF, Kind::JAL),
+            ("jal x0, -4", 0xFFDF_F06F, Kind::JAL),
+        ];
+
+        for (line, raw, kind) in cases {
+            let inst = parse_asm::<RV64I>(line).unwrap();
+            assert_eq!(inst.raw, *raw, "{}", line);
+            assert_eq!(inst.kind, *kind, "{}", line);
+            assert_eq!(encode(inst.kind, inst.variant), Some(*raw), "{}", line);
+
+            let decoded = decode::<RV64I>(*raw).unwrap();
+            assert_eq!(
+                encode(decoded.kind, decoded.variant),
+                Some(*raw),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            parse_asm::<RV64I>("fadd.s fa0, fa1, fa2").unwrap_err(),
+            AsmError::UnknownMnemonic("fadd.s".into())
+        );
+        assert_eq!(
+            parse_asm::<RV64I>("addi a0, a1").unwrap_err(),
+            AsmError::OperandCount {
+                expected: 3,
+                found: 2
+            }
+        );
+        assert_eq!(
+            parse_asm::<RV64I>("addi a0, x32, 1").unwrap_err(),
+            AsmError::InvalidOperand("x32".into())
+        );
+        assert_eq!(
+            parse_asm::<RV64I>("addi a0, a0, 2048").unwrap_err(),
+            AsmError::ImmediateOutOfRange(2048)
+        );
+        assert_eq!(
+            parse_asm::<RV64I>("beq a0, a0, 3").unwrap_err(),
+            AsmError::ImmediateOutOfRange(3)
+        );
+        assert_eq!(
+            parse_asm::<RV32I>("ld a0, 0(a1)").unwrap_err(),
+            AsmError::Decode(DecodeError::InvalidInstruction(0x0005_B503))
+        );
+        assert_eq!(
+            parse_asm::<RV32I>("slli a0, a0, 32").unwrap_err(),
+            AsmError::Decode(DecodeError::InvalidInstruction(0x0205_1513))
+        );
+    }
+
+    #[test]
+    fn register_names() {
+        let fp = parse_asm::<RV64I>("addi fp, s0, 0").unwrap();
+        let x8 = parse_asm::<RV64I>("addi x8, x8, 0").unwrap();
+        assert_eq!(fp.raw, x8.raw);
+    }
+}
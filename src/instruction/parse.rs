@@ -1,31 +1,33 @@
 //! Parsing of RISC-V instructions.
 
-use super::{Instruction, Kind, Variant};
-use crate::{cpu::XRegister, Base};
-use std::collections::HashMap;
-use std::lazy::SyncLazy;
-
-macro_rules! kind_table {
-    ($($key:expr => $kind:ident),*$(,)?) => {
-        ::std::lazy::SyncLazy::new(|| {
-            let mut map = ::std::collections::HashMap::new();
-
-            $(
-                map.insert($key, $crate::instruction::Kind::$kind);
-            )*
-
-            map
-        });
-    }
+use super::{instruction_width, Instruction, Kind, Variant};
+use crate::{cpu::XRegister, Base, Extensions};
+use derive_more::Display;
+
+/// The error that is returned if an instruction couldn't be decoded.
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The opcode of the instruction is unknown.
+    #[display(fmt = "unknown opcode 0b{:07b}", _0)]
+    UnknownOpcode(u8),
+    /// The opcode is known, but the rest of the instruction
+    /// doesn't encode a valid instruction.
+    #[display(fmt = "invalid instruction 0x{:08x}", _0)]
+    InvalidInstruction(u32),
+    /// The instruction is part of an extension, that is disabled.
+    #[display(fmt = "the {} extension is disabled", _0)]
+    DisabledExtension(char),
 }
 
+impl std::error::Error for DecodeError {}
+
 fn instruction_type(opcode: u8) -> Option<Type> {
     match opcode {
         0b000_0011 | 0b000_1111 | 0b001_0011 | 0b001_1011 | 0b110_0111 | 0b111_0011 => {
             Some(Type::I)
         }
         0b001_0111 | 0b011_0111 => Some(Type::U),
-        0b011_0011 | 0b011_1011 => Some(Type::R),
+        0b011_0011 | 0b011_1011 | 0b010_1111 => Some(Type::R),
         0b010_0011 => Some(Type::S),
         0b110_0011 => Some(Type::B),
         0b110_1111 => Some(Type::J),
@@ -34,99 +36,176 @@ fn instruction_type(opcode: u8) -> Option<Type> {
 }
 
 fn i_kind_get<B: Base>(opcode: u8, funct3: u8) -> Option<Kind> {
-    static RV32_TABLE: SyncLazy<HashMap<(u8, u8), Kind>> = kind_table! {
-        (0b001_0011, 0b000) => ADDI,
-        (0b001_0011, 0b010) => SLTI,
-        (0b001_0011, 0b011) => SLTIU,
-        (0b001_0011, 0b100) => XORI,
-        (0b001_0011, 0b110) => ORI,
-        (0b001_0011, 0b111) => ANDI,
-
-        (0b000_0011, 0b000) => LB,
-        (0b000_0011, 0b001) => LH,
-        (0b000_0011, 0b010) => LW,
-        (0b000_0011, 0b100) => LBU,
-        (0b000_0011, 0b101) => LHU,
-
-        (0b110_0111, 0b00) => JALR,
-
-        (0b000_1111, 0b000) => FENCE,
-    };
-
-    static RV64_TABLE: SyncLazy<HashMap<(u8, u8), Kind>> = kind_table! {
-        (0b000_0011, 0b110) => LWU,
-        (0b000_0011, 0b011) => LD,
-        (0b000_1111, 0b001) => FENCE_I,
+    let kind = match (opcode, funct3) {
+        (0b001_0011, 0b000) => Kind::ADDI,
+        (0b001_0011, 0b010) => Kind::SLTI,
+        (0b001_0011, 0b011) => Kind::SLTIU,
+        (0b001_0011, 0b100) => Kind::XORI,
+        (0b001_0011, 0b110) => Kind::ORI,
+        (0b001_0011, 0b111) => Kind::ANDI,
+
+        (0b000_0011, 0b000) => Kind::LB,
+        (0b000_0011, 0b001) => Kind::LH,
+        (0b000_0011, 0b010) => Kind::LW,
+        (0b000_0011, 0b100) => Kind::LBU,
+        (0b000_0011, 0b101) => Kind::LHU,
+
+        (0b110_0111, 0b00) => Kind::JALR,
+
+        (0b000_1111, 0b000) => Kind::FENCE,
+        (0b000_1111, 0b001) => Kind::FENCE_I,
+
+        _ if !B::supports_rv64() => return None,
+        (0b000_0011, 0b110) => Kind::LWU,
+        (0b000_0011, 0b011) => Kind::LD,
+        _ => return None,
     };
-
-    RV32_TABLE.get(&(opcode, funct3)).cloned().or_else(|| {
-        B::supports_rv64()
-            .then(|| RV64_TABLE.get(&(opcode, funct3)).cloned())
-            .flatten()
-    })
+    Some(kind)
 }
 
 fn s_kind_get<B: Base>(opcode: u8, funct3: u8) -> Option<Kind> {
-    static RV32_TABLE: SyncLazy<HashMap<(u8, u8), Kind>> = kind_table! {
-        (0b010_0011, 0b000) => SB,
-        (0b010_0011, 0b001) => SH,
-        (0b010_0011, 0b010) => SW,
+    let kind = match (opcode, funct3) {
+        (0b010_0011, 0b000) => Kind::SB,
+        (0b010_0011, 0b001) => Kind::SH,
+        (0b010_0011, 0b010) => Kind::SW,
+
+        _ if !B::supports_rv64() => return None,
+        (0b010_0011, 0b011) => Kind::SD,
+        _ => return None,
     };
-
-    static RV64_TABLE: SyncLazy<HashMap<(u8, u8), Kind>> = kind_table! {
-        (0b010_0011, 0b011) => SD,
-    };
-
-    RV32_TABLE.get(&(opcode, funct3)).cloned().or_else(|| {
-        B::supports_rv64()
-            .then(|| RV64_TABLE.get(&(opcode, funct3)).cloned())
-            .flatten()
-    })
+    Some(kind)
 }
 
 fn r_kind_get<B: Base>(opcode: u8, funct3: u8, funct7: u8) -> Option<Kind> {
-    static RV32_TABLE: SyncLazy<HashMap<(u8, u8, u8), Kind>> = kind_table! {
-        (0b011_0011, 0b000, 0b000_0000) => ADD,
-        (0b011_0011, 0b000, 0b010_0000) => SUB,
-        (0b011_0011, 0b001, 0b000_0000) => SLL,
-        (0b011_0011, 0b010, 0b000_0000) => SLT,
-        (0b011_0011, 0b011, 0b000_0000) => SLTU,
-        (0b011_0011, 0b100, 0b000_0000) => XOR,
-        (0b011_0011, 0b101, 0b000_0000) => SRL,
-        (0b011_0011, 0b101, 0b010_0000) => SRA,
-        (0b011_0011, 0b110, 0b000_0000) => OR,
-        (0b011_0011, 0b111, 0b000_0000) => AND,
+    let kind = match (opcode, funct3, funct7) {
+        (0b011_0011, 0b000, 0b000_0000) => Kind::ADD,
+        (0b011_0011, 0b000, 0b010_0000) => Kind::SUB,
+        (0b011_0011, 0b001, 0b000_0000) => Kind::SLL,
+        (0b011_0011, 0b010, 0b000_0000) => Kind::SLT,
+        (0b011_0011, 0b011, 0b000_0000) => Kind::SLTU,
+        (0b011_0011, 0b100, 0b000_0000) => Kind::XOR,
+        (0b011_0011, 0b101, 0b000_0000) => Kind::SRL,
+        (0b011_0011, 0b101, 0b010_0000) => Kind::SRA,
+        (0b011_0011, 0b110, 0b000_0000) => Kind::OR,
+        (0b011_0011, 0b111, 0b000_0000) => Kind::AND,
+
+        (0b011_0011, 0b000, 0b000_0001) => Kind::MUL,
+        (0b011_0011, 0b001, 0b000_0001) => Kind::MULH,
+        (0b011_0011, 0b010, 0b000_0001) => Kind::MULHSU,
+        (0b011_0011, 0b011, 0b000_0001) => Kind::MULHU,
+        (0b011_0011, 0b100, 0b000_0001) => Kind::DIV,
+        (0b011_0011, 0b101, 0b000_0001) => Kind::DIVU,
+        (0b011_0011, 0b110, 0b000_0001) => Kind::REM,
+        (0b011_0011, 0b111, 0b000_0001) => Kind::REMU,
+
+        _ if !B::supports_rv64() => return None,
+        (0b011_1011, 0b000, 0b000_0000) => Kind::ADDW,
+        (0b011_1011, 0b000, 0b010_0000) => Kind::SUBW,
+        (0b011_1011, 0b001, 0b000_0000) => Kind::SLLW,
+        (0b011_1011, 0b101, 0b000_0000) => Kind::SRLW,
+        (0b011_1011, 0b101, 0b010_0000) => Kind::SRAW,
+
+        (0b011_1011, 0b000, 0b000_0001) => Kind::MULW,
+        (0b011_1011, 0b100, 0b000_0001) => Kind::DIVW,
+        (0b011_1011, 0b101, 0b000_0001) => Kind::DIVUW,
+        (0b011_1011, 0b110, 0b000_0001) => Kind::REMW,
+        (0b011_1011, 0b111, 0b000_0001) => Kind::REMUW,
+        _ => return None,
     };
+    Some(kind)
+}
 
-    static RV64_TABLE: SyncLazy<HashMap<(u8, u8, u8), Kind>> = kind_table! {
-        (0b011_1011, 0b000, 0b000_0000) => ADDW,
-        (0b011_1011, 0b000, 0b010_0000) => SUBW,
-        (0b011_1011, 0b001, 0b000_0000) => SLLW,
-        (0b011_1011, 0b101, 0b000_0000) => SRLW,
-        (0b011_1011, 0b101, 0b100_0000) => SRAW,
+/// Returns the kind of an atomic instruction, which is identified
+/// by the width in `funct3`, and the upper five bits of `funct7`.
+fn atomic_kind_get<B: Base>(funct3: u8, funct5: u8) -> Option<Kind> {
+    let kind = match (funct3, funct5) {
+        (0b010, 0b00010) => Kind::LR_W,
+        (0b010, 0b00011) => Kind::SC_W,
+        (0b010, 0b00001) => Kind::AMOSWAP_W,
+        (0b010, 0b00000) => Kind::AMOADD_W,
+        (0b010, 0b00100) => Kind::AMOXOR_W,
+        (0b010, 0b01100) => Kind::AMOAND_W,
+        (0b010, 0b01000) => Kind::AMOOR_W,
+        (0b010, 0b10000) => Kind::AMOMIN_W,
+        (0b010, 0b10100) => Kind::AMOMAX_W,
+        (0b010, 0b11000) => Kind::AMOMINU_W,
+        (0b010, 0b11100) => Kind::AMOMAXU_W,
+
+        _ if !B::supports_rv64() => return None,
+        (0b011, 0b00010) => Kind::LR_D,
+        (0b011, 0b00011) => Kind::SC_D,
+        (0b011, 0b00001) => Kind::AMOSWAP_D,
+        (0b011, 0b00000) => Kind::AMOADD_D,
+        (0b011, 0b00100) => Kind::AMOXOR_D,
+        (0b011, 0b01100) => Kind::AMOAND_D,
+        (0b011, 0b01000) => Kind::AMOOR_D,
+        (0b011, 0b10000) => Kind::AMOMIN_D,
+        (0b011, 0b10100) => Kind::AMOMAX_D,
+        (0b011, 0b11000) => Kind::AMOMINU_D,
+        (0b011, 0b11100) => Kind::AMOMAXU_D,
+        _ => return None,
     };
-
-    RV32_TABLE
-        .get(&(opcode, funct3, funct7))
-        .cloned()
-        .or_else(|| {
-            B::supports_rv64()
-                .then(|| RV64_TABLE.get(&(opcode, funct3, funct7)).cloned())
-                .flatten()
-        })
+    Some(kind)
 }
 
 fn b_kind_get<B: Base>(opcode: u8, funct3: u8) -> Option<Kind> {
-    static RV32_TABLE: SyncLazy<HashMap<(u8, u8), Kind>> = kind_table! {
-        (0b110_0011, 0b000) => BEQ,
-        (0b110_0011, 0b001) => BNE,
-        (0b110_0011, 0b100) => BLT,
-        (0b110_0011, 0b101) => BGE,
-        (0b110_0011, 0b110) => BLTU,
-        (0b110_0011, 0b111) => BGEU,
+    let kind = match (opcode, funct3) {
+        (0b110_0011, 0b000) => Kind::BEQ,
+        (0b110_0011, 0b001) => Kind::BNE,
+        (0b110_0011, 0b100) => Kind::BLT,
+        (0b110_0011, 0b101) => Kind::BGE,
+        (0b110_0011, 0b110) => Kind::BLTU,
+        (0b110_0011, 0b111) => Kind::BGEU,
+        _ => return None,
     };
+    Some(kind)
+}
+
+/// Returns the kind of an instruction with the `SYSTEM` opcode.
+///
+/// The CSR instructions are identified by `funct3`. All other instructions
+/// have a `funct3` of zero, and are identified by `funct12`, except for
+/// `SFENCE.VMA`, which is identified by the upper seven bits of it.
+fn system_kind_get(funct3: u8, funct12: u32, rs1: u32, rd: u32) -> Option<Kind> {
+    let kind = match (funct3, funct12 >> 5, funct12) {
+        (0b001, _, _) => Kind::CSRRW,
+        (0b010, _, _) => Kind::CSRRS,
+        (0b011, _, _) => Kind::CSRRC,
+        (0b101, _, _) => Kind::CSRRWI,
+        (0b110, _, _) => Kind::CSRRSI,
+        (0b111, _, _) => Kind::CSRRCI,
+
+        (0b000, 0b000_1001, _) if rd == 0 => Kind::SFENCE_VMA,
+
+        _ if funct3 != 0 || rs1 != 0 || rd != 0 => return None,
+        (_, _, 0x000) => Kind::ECALL,
+        (_, _, 0x001) => Kind::EBREAK,
+        (_, _, 0x102) => Kind::SRET,
+        (_, _, 0x302) => Kind::MRET,
+        (_, _, 0x105) => Kind::WFI,
+        _ => return None,
+    };
+    Some(kind)
+}
+
+/// Hint instructions are encoded using regular instructions,
+/// that don't have any architectural effect.
+///
+/// This function returns the kind of hint, that is encoded by
+/// the given raw instruction, if any.
+fn hint_kind(inst: u32) -> Option<Kind> {
+    match inst {
+        // fence w, 0
+        0x0100_000F => Some(Kind::PAUSE),
+
+        // add x0, x0, x2..x5
+        0x0020_0033 => Some(Kind::NTL_P1),
+        0x0030_0033 => Some(Kind::NTL_PALL),
+        0x0040_0033 => Some(Kind::NTL_S1),
+        0x0050_0033 => Some(Kind::NTL_ALL),
 
-    RV32_TABLE.get(&(opcode, funct3)).cloned()
+        _ => None,
+    }
 }
 
 enum Type {
@@ -151,7 +230,19 @@ impl Type {
                 let funct3 = ((inst >> 12) & 0x7) as u8;
                 let funct7 = ((inst >> 25) & 0x7F) as u8;
 
-                let kind = r_kind_get::<B>(opcode, funct3, funct7)?;
+                let kind = if opcode == 0b010_1111 {
+                    // The lower two bits of `funct7` are the `aq` and `rl` bits,
+                    // which don't matter for a single hart.
+                    let kind = atomic_kind_get::<B>(funct3, funct7 >> 2)?;
+                    if let Kind::LR_W | Kind::LR_D = kind {
+                        if rs2 != 0 {
+                            return None;
+                        }
+                    }
+                    kind
+                } else {
+                    r_kind_get::<B>(opcode, funct3, funct7)?
+                };
                 Some(Instruction {
                     variant: Variant::R {
                         rd: XRegister::from(rd as u8),
@@ -169,36 +260,55 @@ impl Type {
                 let funct3 = ((inst >> 12) & 0x7) as u8;
                 let rd = (inst >> 7) & 0x1F;
 
-                // ECALL and EBREAK instructions
                 if opcode == 0b111_0011 {
-                    let kind = match imm {
-                        0 => Kind::ECALL,
-                        _ => Kind::EBREAK,
-                    };
-                    return Some(Instruction {
-                        variant: Variant::I {
-                            val: 0,
+                    let kind = system_kind_get(funct3, imm, rs1, rd)?;
+                    let variant = match kind {
+                        // SFENCE.VMA uses the R-type encoding
+                        Kind::SFENCE_VMA => Variant::R {
                             rd: 0.into(),
-                            rs1: 0.into(),
+                            rs1: XRegister::from(rs1 as u8),
+                            rs2: XRegister::from((imm & 0x1F) as u8),
+                        },
+                        // ECALL, EBREAK, WFI and the trap return instructions don't have operands
+                        Kind::ECALL | Kind::EBREAK | Kind::SRET | Kind::MRET | Kind::WFI => {
+                            Variant::I {
+                                val: 0,
+                                rd: 0.into(),
+                                rs1: 0.into(),
+                            }
+                        }
+                        // CSR instructions, which store the CSR number in the immediate,
+                        // and the immediate operand in `rs1`.
+                        _ => Variant::I {
+                            val: imm as i32,
+                            rd: XRegister::from(rd as u8),
+                            rs1: XRegister::from(rs1 as u8),
                         },
+                    };
+
+                    return Some(Instruction {
+                        variant,
                         kind,
                         raw: inst,
                     });
                 } else if B::supports_rv64() && opcode == 0b001_1011 {
-                    let shifttop = (imm >> 6) & 0x7F;
+                    // The shift amount of the word shifts is only 5 bits wide,
+                    // and the upper bits select the kind of the shift.
+                    let funct7 = imm >> 5;
                     let shamt = imm & 0x1F;
 
-                    let kind = match funct3 {
-                        0b000 => Kind::ADDIW,
-                        0b001 => Kind::SLLIW,
-                        0b101 if shifttop == 0 => Kind::SRLIW,
-                        0b101 if shifttop != 0 => Kind::SRAIW,
+                    let (kind, val) = match funct3 {
+                        // Sign extend the immediate
+                        0b000 => (Kind::ADDIW, ((imm as i32) << 20) >> 20),
+                        0b001 if funct7 == 0 => (Kind::SLLIW, shamt as i32),
+                        0b101 if funct7 == 0 => (Kind::SRLIW, shamt as i32),
+                        0b101 if funct7 == 0b010_0000 => (Kind::SRAIW, shamt as i32),
                         _ => return None,
                     };
 
                     return Some(Instruction {
                         variant: Variant::I {
-                            val: shamt as i32,
+                            val,
                             rd: XRegister::from(rd as u8),
                             rs1: XRegister::from(rs1 as u8),
                         },
@@ -208,14 +318,17 @@ impl Type {
                 }
 
                 let (kind, imm) = match funct3 {
-                    0b001 | 0b101 => {
+                    0b001 | 0b101 if opcode == 0b001_0011 => {
                         let shifttop = (imm >> 6) & 0x3F;
                         // In the case this is a shift operations,
                         // the `imm` value represents the shift amount.
+                        // On RV32, shift amounts of 32 and above are reserved.
                         let imm = if B::supports_rv64() {
                             imm & 0x3F
-                        } else {
+                        } else if imm & 0x20 == 0 {
                             imm & 0x1F
+                        } else {
+                            return None;
                         };
                         let kind = match funct3 {
                             0b001 => Kind::SLLI,
@@ -349,19 +462,79 @@ impl Type {
 /// See [`spec`] for more information on how to decode instructions.
 ///
 /// [`spec`]: https://riscv.org/specifications/isa-spec-pdf/
-pub fn decode<B: Base>(raw_inst: u32) -> Option<Instruction> {
-    let opcode = raw_inst & 0x7F;
+///
+/// ## Errors
+///
+/// Returns a [`DecodeError`] if the given instruction is not valid
+/// for the base ISA `B`.
+pub fn decode<B: Base>(raw_inst: u32) -> Result<Instruction, DecodeError> {
+    let opcode = (raw_inst & 0x7F) as u8;
+
+    let mut inst = instruction_type(opcode)
+        .ok_or(DecodeError::UnknownOpcode(opcode))?
+        .decode::<B>(raw_inst)
+        .ok_or(DecodeError::InvalidInstruction(raw_inst))?;
+
+    if let Some(kind) = hint_kind(raw_inst) {
+        inst.kind = kind;
+    }
 
-    instruction_type(opcode as u8).and_then(|variant| variant.decode::<B>(raw_inst))
+    Ok(inst)
+}
+
+/// Decodes a raw 32bit instruction, like [`decode`], but rejects instructions
+/// of extensions that are not part of `extensions`.
+///
+/// ## Errors
+///
+/// Returns a [`DecodeError`] if the given instruction is not valid for the base
+/// ISA `B`, or if it's part of a disabled extension.
+pub fn decode_with_extensions<B: Base>(
+    raw_inst: u32,
+    extensions: Extensions,
+) -> Result<Instruction, DecodeError> {
+    let inst = decode::<B>(raw_inst)?;
+    match inst.kind.extension() {
+        Some(ext) if !extensions.contains(ext) => Err(DecodeError::DisabledExtension(ext)),
+        _ => Ok(inst),
+    }
+}
+
+/// Lazily decodes the little endian instructions inside `bytes`.
+///
+/// An instruction that can't be decoded yields an error, and decoding continues
+/// after it, using the width that is encoded in it's lowest bits. If the width is
+/// reserved, decoding continues after the first halfword. A truncated instruction
+/// at the end of `bytes` is ignored.
+pub fn decode_stream<B: Base, I: Iterator<Item = u8>>(
+    mut bytes: I,
+) -> impl Iterator<Item = Result<Instruction, DecodeError>> {
+    std::iter::from_fn(move || {
+        let mut half = || Some(u16::from_le_bytes([bytes.next()?, bytes.next()?]));
+
+        let low = half()?;
+        let raw = match instruction_width(low) {
+            Some(2) => u32::from(low),
+            Some(4) => u32::from(half()?) << 16 | u32::from(low),
+            width => {
+                for _ in 2..width.unwrap_or(2) {
+                    bytes.next()?;
+                }
+                return Some(Err(DecodeError::InvalidInstruction(u32::from(low))));
+            }
+        };
+        Some(decode::<B>(raw))
+    })
 }
 
 #[cfg(test)]
+#[allow(clippy::unreadable_literal)]
 mod tests {
     use super::*;
 
     fn assert(inst: u32, s: &str) {
         let decoded = decode::<crate::RV64I>(inst);
-        assert!(decoded.is_some());
+        assert!(decoded.is_ok());
         assert_eq!(&decoded.unwrap().to_string(), s);
     }
 
@@ -373,11 +546,13 @@ mod tests {
         assert(0x4807F713, "andi r14 r15 0x480");
         assert(0x01093403, "ld r8 r18 0x10");
         assert(0x00000073, "ecall");
+        assert(0x1050_0073, "wfi");
         assert(0x00269693, "slli r13 r13 0x2");
         assert(0x000FD013, "srli r0 r31 0x0");
         assert(0x400FD013, "srai r0 r31 0x0");
         assert(0x4000D71B, "sraiw r14 r1 0x0");
         assert(0x0010D71B, "srliw r14 r1 0x1");
+        assert(0xFFF1_071B, "addiw r14 r2 0xffffffff");
     }
 
     #[test]
@@ -405,8 +580,213 @@ mod tests {
         assert(0x3EB51A63, "bne 0x3f4 r10 r11");
     }
 
+    #[test]
+    fn test_hints() {
+        assert(0x0100_000F, "pause");
+        assert(0x0020_0033, "ntl.p1");
+        assert(0x0030_0033, "ntl.pall");
+        assert(0x0040_0033, "ntl.s1");
+        assert(0x0050_0033, "ntl.all");
+
+        assert(0x0060_0033, "add r0 r0 r6");
+        assert(0x0020_00B3, "add r1 r0 r2");
+        assert(0x0210_000F, "fence r0 r0 0x21");
+    }
+
+    #[test]
+    fn test_system() {
+        assert(0x0000_0073, "ecall");
+        assert(0x1050_0073, "wfi");
+        assert(0x0010_0073, "ebreak");
+        assert(0x1020_0073, "sret");
+        assert(0x3020_0073, "mret");
+
+        assert!(decode::<crate::RV64I>(0x3020_0873).is_err());
+        assert!(decode::<crate::RV64I>(0x7020_0073).is_err());
+        assert!(decode::<crate::RV64I>(0x3402_41F3).is_err());
+
+        assert(0x3401_10F3, "csrrw r1 r2 0x340");
+        assert(0x3400_21F3, "csrrs r3 r0 0x340");
+        assert(0x3402_3073, "csrrc r0 r4 0x340");
+        assert(0x3402_D373, "csrrwi r6 r5 0x340");
+        assert(0x3401_63F3, "csrrsi r7 r2 0x340");
+        assert(0xF140_7473, "csrrci r8 r0 0xf14");
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert_eq!(
+            decode::<crate::RV64I>(0x0000_0000).unwrap_err(),
+            DecodeError::UnknownOpcode(0)
+        );
+        // `ld` is not available on RV32
+        assert_eq!(
+            decode::<crate::RV32I>(0x0109_3403).unwrap_err(),
+            DecodeError::InvalidInstruction(0x0109_3403)
+        );
+    }
+
     #[test]
     fn test_j_type() {
         assert(0x00C000EF, "jal r1 0xc");
     }
+
+    #[test]
+    fn test_atomic() {
+        assert(0x1005_20AF, "lr.w r1 r10 r0");
+        assert(0x1835_212F, "sc.w r2 r10 r3");
+        assert(0x1605_30AF, "lr.d r1 r10 r0");
+        assert(0x1835_312F, "sc.d r2 r10 r3");
+
+        assert!(decode::<crate::RV64I>(0x1035_20AF).is_err());
+        assert!(decode::<crate::RV32I>(0x1005_30AF).is_err());
+    }
+
+    #[test]
+    fn test_multiply_divide() {
+        assert(0x02C5_8533, "mul r10 r11 r12");
+        assert(0x02C5_A533, "mulhsu r10 r11 r12");
+        assert(0x02C5_C533, "div r10 r11 r12");
+        assert(0x02C5_F53B, "remuw r10 r11 r12");
+
+        // The word instructions are not available on RV32
+        assert!(decode::<crate::RV32I>(0x02C5_F53B).is_err());
+    }
+
+    #[test]
+    fn test_sfence_vma() {
+        assert(0x1200_0073, "sfence.vma r0 r0 r0");
+        assert(0x12B5_0073, "sfence.vma r0 r10 r11");
+
+        // `rd` must be zero
+        assert!(decode::<crate::RV64I>(0x12B5_00F3).is_err());
+    }
+
+    #[test]
+    fn test_amo() {
+        assert(0x0035_22AF, "amoadd.w r5 r10 r3");
+        assert(0xC035_232F, "amominu.w r6 r10 r3");
+        assert(0x0035_32AF, "amoadd.d r5 r10 r3");
+        assert(0xA035_332F, "amomax.d r6 r10 r3");
+        assert(0x0E35_232F, "amoswap.w r6 r10 r3");
+
+        assert!(decode::<crate::RV32I>(0x0035_32AF).is_err());
+    }
+
+    #[test]
+    fn test_loads() {
+        assert(0x0041_1083, "lh r1 r2 0x4");
+        assert(0x0041_5083, "lhu r1 r2 0x4");
+        assert(0x0000_100F, "fence_i r0 r0 0x0");
+    }
+
+    #[test]
+    fn test_rv64_only() {
+        for &inst in &[0x0109_3403, 0x0020_873B, 0x4000_D71B, 0x0000_3023] {
+            assert!(decode::<crate::RV64I>(inst).unwrap().kind.requires_rv64());
+            assert!(decode::<crate::RV32I>(inst).is_err());
+        }
+    }
+
+    #[test]
+    fn test_decode_stream() {
+        let bytes = vec![
+            0x93, 0x00, 0x10, 0x00, // addi x1, x0, 1
+            0x01, 0x00, // c.nop
+            0x3F, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // 64-bit instruction
+            0xEF, 0x00, 0x80, 0x00, // jal x1, 8
+            0x13, 0x00, // truncated
+        ];
+
+        let kinds = decode_stream::<crate::RV64I, _>(bytes.into_iter())
+            .map(|inst| inst.map(|inst| inst.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                Ok(Kind::ADDI),
+                Err(DecodeError::UnknownOpcode(0x01)),
+                Err(DecodeError::InvalidInstruction(0x3F)),
+                Ok(Kind::JAL),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rv32_shift_amount() {
+        // slli x1, x1, 32
+        let inst = 0x0200_9093;
+        assert_eq!(
+            decode::<crate::RV32I>(inst).map(|inst| inst.kind),
+            Err(DecodeError::InvalidInstruction(inst))
+        );
+        assert_eq!(
+            decode::<crate::RV64I>(inst).map(|inst| inst.to_string()),
+            Ok("slli r1 r1 0x20".to_string())
+        );
+
+        // srai x1, x1, 31
+        assert_eq!(
+            decode::<crate::RV32I>(0x41F0_D093).map(|inst| inst.kind),
+            Ok(Kind::SRAI)
+        );
+    }
+
+    #[test]
+    fn test_word_shift_amount() {
+        // slliw x1, x1, 32
+        assert_eq!(
+            decode::<crate::RV64I>(0x0200_909B).map(|inst| inst.kind),
+            Err(DecodeError::InvalidInstruction(0x0200_909B))
+        );
+        // sraiw x1, x1, 32
+        assert_eq!(
+            decode::<crate::RV64I>(0x4200_D09B).map(|inst| inst.kind),
+            Err(DecodeError::InvalidInstruction(0x4200_D09B))
+        );
+        // srliw with an invalid funct7
+        assert_eq!(
+            decode::<crate::RV64I>(0x1000_D09B).map(|inst| inst.kind),
+            Err(DecodeError::InvalidInstruction(0x1000_D09B))
+        );
+        // slliw x1, x1, 31
+        assert_eq!(
+            decode::<crate::RV64I>(0x01F0_909B).map(|inst| inst.kind),
+            Ok(Kind::SLLIW)
+        );
+    }
+
+    #[test]
+    fn test_disabled_extension() {
+        // amoadd.w x1, x2, (x3)
+        let inst = 0x0021_A0AF;
+        let extensions = Extensions::from_letters(&['A', 'I']).unwrap();
+        assert_eq!(
+            decode_with_extensions::<crate::RV64I>(inst, extensions).map(|inst| inst.kind),
+            Ok(Kind::AMOADD_W)
+        );
+
+        let extensions = Extensions::from_letters(&['I']).unwrap();
+        assert_eq!(
+            decode_with_extensions::<crate::RV64I>(inst, extensions).map(|inst| inst.kind),
+            Err(DecodeError::DisabledExtension('A'))
+        );
+        assert_eq!(
+            decode_with_extensions::<crate::RV64I>(0x0020_80B3, extensions).map(|inst| inst.kind),
+            Ok(Kind::ADD)
+        );
+    }
+
+    #[test]
+    fn test_system_kind_get() {
+        assert_eq!(system_kind_get(0b000, 0x000, 0, 0), Some(Kind::ECALL));
+        assert_eq!(system_kind_get(0b000, 0x001, 0, 0), Some(Kind::EBREAK));
+        assert_eq!(system_kind_get(0b000, 0x302, 0, 0), Some(Kind::MRET));
+        assert_eq!(system_kind_get(0b000, 0x302, 1, 0), None);
+        assert_eq!(system_kind_get(0b000, 0x7FF, 0, 0), None);
+        assert_eq!(system_kind_get(0b100, 0x000, 0, 0), None);
+        assert_eq!(system_kind_get(0b010, 0x300, 0, 5), Some(Kind::CSRRS));
+        assert_eq!(system_kind_get(0b000, 0x120, 1, 0), Some(Kind::SFENCE_VMA));
+        assert_eq!(system_kind_get(0b000, 0x120, 1, 1), None);
+    }
 }
@@ -1,5 +1,4 @@
 //! todo
-#![feature(once_cell)]
 #![deny(
     rust_2018_idioms,
     clippy::pedantic,
@@ -10,22 +9,31 @@
     clippy::must_use_candidate,
     clippy::cast_possible_truncation,
     clippy::too_many_lines,
-    clippy::cast_possible_wrap
+    clippy::cast_possible_wrap,
+    clippy::cast_sign_loss
 )]
 
-mod config;
-pub use config::*;
+mod error;
+pub use error::*;
+
+mod extensions;
+pub use extensions::*;
 
 pub mod cpu;
+pub mod device;
+pub mod dtb;
+pub mod gdb;
 pub mod instruction;
 pub mod memory;
+pub mod system;
 pub mod trap;
 
 use num_traits::{FromPrimitive, Num, ToPrimitive};
+use std::{fmt::Debug, mem};
 
 /// This trait represents every type that can be used as an
 /// address of the CPU/Memory.
-pub trait Address: Num + Clone + Copy {
+pub trait Address: Num + Debug + Clone + Copy {
     /// Convert `self` address to a `u64`.
     fn to_u64(&self) -> u64;
 
@@ -34,9 +42,22 @@ pub trait Address: Num + Clone + Copy {
     /// A value passed to this function should always
     /// fit in the inner storage type (e.g. `u32` for `RV32I`)
     fn from_u64(num: u64) -> Self;
+
+    /// Adds `rhs` to `self`, and wraps around at the end of the address space.
+    fn wrapping_add(self, rhs: Self) -> Self;
+
+    /// Subtracts `rhs` from `self`, and wraps around at the start of the address space.
+    fn wrapping_sub(self, rhs: Self) -> Self;
+
+    /// Sign extends the lower `bits` bits of `self` to the full width of the address.
+    ///
+    /// ## Panics
+    ///
+    /// If `bits` is zero, or larger than the width of the address.
+    fn sign_extend(self, bits: usize) -> Self;
 }
 
-impl<T: Num + ToPrimitive + FromPrimitive + Clone + Copy> Address for T {
+impl<T: Num + ToPrimitive + FromPrimitive + Debug + Clone + Copy> Address for T {
     fn to_u64(&self) -> u64 {
         self.to_u64().expect("address conversion to u64 failed")
     }
@@ -44,6 +65,36 @@ impl<T: Num + ToPrimitive + FromPrimitive + Clone + Copy> Address for T {
     fn from_u64(num: u64) -> Self {
         Self::from_u64(num).expect("address conversion from u64 failed")
     }
+
+    fn wrapping_add(self, rhs: Self) -> Self {
+        let sum = Address::to_u64(&self).wrapping_add(Address::to_u64(&rhs));
+        Address::from_u64(truncate::<T>(sum))
+    }
+
+    fn wrapping_sub(self, rhs: Self) -> Self {
+        let diff = Address::to_u64(&self).wrapping_sub(Address::to_u64(&rhs));
+        Address::from_u64(truncate::<T>(diff))
+    }
+
+    fn sign_extend(self, bits: usize) -> Self {
+        assert!(
+            bits > 0 && bits <= mem::size_of::<T>() * 8,
+            "can't sign extend from bit {}",
+            bits
+        );
+
+        let shift = 64 - bits;
+        let val = ((Address::to_u64(&self) << shift) as i64 >> shift) as u64;
+        Address::from_u64(truncate::<T>(val))
+    }
+}
+
+/// Truncates `val` to the width of the type `T`.
+fn truncate<T>(val: u64) -> u64 {
+    match mem::size_of::<T>() * 8 {
+        64 => val,
+        bits => val & ((1 << bits) - 1),
+    }
 }
 
 /// A [`Base`] represents the different RISC-V
@@ -65,6 +116,7 @@ pub trait Base: sealed::Sealed {
 }
 
 /// The RV32I base integer instruction set.
+#[derive(Debug, Clone, Copy)]
 pub struct RV32I;
 
 impl Base for RV32I {
@@ -77,6 +129,7 @@ impl Base for RV32I {
 }
 
 /// The RV64I base integer instruction set.
+#[derive(Debug, Clone, Copy)]
 pub struct RV64I;
 
 impl Base for RV64I {
@@ -94,3 +147,27 @@ mod sealed {
     impl Sealed for super::RV64I {}
     impl Sealed for super::RV32I {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Address;
+
+    #[test]
+    fn wrapping_arithmetic() {
+        assert_eq!(Address::wrapping_add(0xFFFF_FFFF_u32, 1), 0);
+        assert_eq!(Address::wrapping_add(0xFFFF_FFFF_u64, 1), 0x1_0000_0000);
+        assert_eq!(Address::wrapping_add(u64::MAX, 2), 1);
+
+        assert_eq!(Address::wrapping_sub(0_u32, 4), 0xFFFF_FFFC);
+        assert_eq!(Address::wrapping_sub(0_u64, 4), 0xFFFF_FFFF_FFFF_FFFC);
+    }
+
+    #[test]
+    fn sign_extend() {
+        assert_eq!(0x800_u32.sign_extend(12), 0xFFFF_F800);
+        assert_eq!(0x800_u64.sign_extend(12), 0xFFFF_FFFF_FFFF_F800);
+        assert_eq!(0x7FF_u64.sign_extend(12), 0x7FF);
+        assert_eq!(0x8000_0000_u64.sign_extend(32), 0xFFFF_FFFF_8000_0000);
+        assert_eq!(0x8000_0000_u32.sign_extend(32), 0x8000_0000);
+    }
+}
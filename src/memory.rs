@@ -13,6 +13,8 @@
 //!
 //! The address space of the RISC-V ISA is circular.
 //! Accordingly, memory addresses are computed using `addr % (1 << XLEN)`.
+//! The raw [`Memory::read`] and [`Memory::write`] accessors also wrap around
+//! at the end of the memory, and thus use `(addr - base) % size`.
 //!
 //! See chapter 1.4 in the [`RISC-V Spec`].
 //!
@@ -21,23 +23,64 @@
 mod mmu;
 pub use mmu::*;
 
-use crate::{Address, Base};
+use crate::{device::SharedDevice, trap::Exception, Address, Base};
 use bytemuck::Pod;
-use std::{convert::TryInto, marker::PhantomData, mem};
+use std::{fmt::Write, marker::PhantomData, ops::Range};
 
 /// The default `MEMORY_SIZE` is 128MiB.
 pub const MEMORY_SIZE: usize = 0x100_0000;
 
+/// The size of the pages, that are used to serialize the memory.
+#[cfg(feature = "serde")]
+const PAGE_SIZE: usize = 0x1000;
+
 /// The memory that is responsible for reading and writing
 /// different types into the raw memory of the CPU.
 ///
+/// The physical address space consists of the RAM, which starts at the
+/// [base address](Memory::base) (`0` by default), and the regions of the
+/// [devices](crate::device::MmioDevice) that are mapped into it. A device may be
+/// mapped over a part of the RAM, which it shadows for integer accesses.
+/// Integer accesses to any other address raise an access fault.
+///
+/// Cloning the memory copies the RAM, but the devices are shared between
+/// both copies. Devices are never serialized.
+///
 /// Note that `Memory` does not include the Memory Manage Unit.
 /// To use the MMU use `Mmu` instead.
 pub struct Memory<B: Base> {
+    base: u64,
     memory: Box<[u8]>,
+    devices: Vec<MappedDevice>,
     _data: PhantomData<B>,
 }
 
+impl<B: Base> Clone for Memory<B> {
+    fn clone(&self) -> Self {
+        Self {
+            base: self.base,
+            memory: self.memory.clone(),
+            devices: self.devices.clone(),
+            _data: PhantomData,
+        }
+    }
+}
+
+/// A device, together with the region it's mapped to.
+#[derive(Clone)]
+struct MappedDevice {
+    region: Range<u64>,
+    device: SharedDevice,
+}
+
+/// The target of an integer access, after checking the physical memory attributes.
+enum Target {
+    /// The range of bytes inside the RAM.
+    Ram(Range<usize>),
+    /// The index of the device, and the offset inside it's region.
+    Device(usize, u64),
+}
+
 impl<B: Base> Default for Memory<B> {
     /// Creates a new [`Memory`] with the [default memory size](MEMORY_SIZE).
     fn default() -> Self {
@@ -47,56 +90,510 @@ impl<B: Base> Default for Memory<B> {
 
 impl<B: Base> Memory<B> {
     /// Creates a new [`Memory`] with the given size in bytes.
+    ///
+    /// ## Panics
+    ///
+    /// If the size exceeds the address space of `1 << XLEN` bytes.
+    /// See [`Memory::is_valid_size`].
     pub fn with_size(size: usize) -> Self {
+        Self::with_base(0, size)
+    }
+
+    /// Creates a new [`Memory`] with the given size in bytes, whose RAM starts
+    /// at the physical address `base`.
+    ///
+    /// ## Panics
+    ///
+    /// If the RAM doesn't end inside the address space of `1 << XLEN` bytes.
+    /// See [`Memory::is_valid_region`].
+    pub fn with_base(base: u64, size: usize) -> Self {
+        assert!(
+            Self::is_valid_region(base, size),
+            "memory size {:#x} at {:#x} exceeds the {}-bit address space",
+            size,
+            base,
+            B::XLEN
+        );
+
         Self {
+            base,
             memory: vec![0_u8; size].into_boxed_slice(),
+            devices: Vec::new(),
             _data: PhantomData,
         }
     }
 
+    /// Replaces the RAM with the RAM of `other`, but keeps the devices,
+    /// that are mapped into this memory.
+    pub(crate) fn replace_ram(&mut self, other: Memory<B>) {
+        self.base = other.base;
+        self.memory = other.memory;
+    }
+
+    /// Checks if a memory of `size` bytes fits into the address space
+    /// of `1 << XLEN` bytes.
+    pub fn is_valid_size(size: usize) -> bool {
+        Self::is_valid_region(0, size)
+    }
+
+    /// Checks if a memory of `size` bytes, that starts at `base`, ends inside
+    /// the address space of `1 << XLEN` bytes.
+    pub fn is_valid_region(base: u64, size: usize) -> bool {
+        base.checked_add(size as u64)
+            .map_or(false, |end| B::XLEN >= 64 || end <= 1 << B::XLEN)
+    }
+
+    /// Returns the physical address, where the RAM starts.
+    pub fn base(&self) -> u64 {
+        self.base
+    }
+
+    /// Returns the size of this memory in bytes.
+    pub fn size(&self) -> usize {
+        self.memory.len()
+    }
+
+    /// Maps the `device` into the physical address space, starting at `base`.
+    ///
+    /// ## Panics
+    ///
+    /// If the region of the device overlaps any other device.
+    pub fn map_device(&mut self, base: u64, device: SharedDevice) {
+        let size = device.borrow().size();
+        let region = base..base.checked_add(size).expect("device region overflows");
+
+        let overlaps = |other: &Range<u64>| region.start < other.end && other.start < region.end;
+        assert!(
+            !self.devices.iter().any(|dev| overlaps(&dev.region)),
+            "device region {:#x}..{:#x} overlaps another region",
+            region.start,
+            region.end
+        );
+
+        self.devices.push(MappedDevice { region, device });
+    }
+
     /// Writes a [`Pod`] into the memory at the given address.
     ///
+    /// The address wraps around at the end of the memory, so a value
+    /// that crosses the end is continued at the start of the RAM.
+    ///
     /// ## Panics
     ///
-    /// - if address is out of bounds
-    /// - if address is unaligned to `T`
-    /// - if transmute to `T` failed
-    /// - if address can not be converted into a `usize`
+    /// If the memory is empty.
     pub fn write<T: Pod>(&mut self, addr: B::Addr, value: T) {
-        let addr = Self::addr_to_usize(addr);
-        let bytes = bytemuck::bytes_of(&value);
-        let target = &mut self.memory[addr..addr + bytes.len()];
-        target.copy_from_slice(bytes);
+        self.write_phys(PhysAddr::new(addr.to_u64()), value);
+    }
+
+    /// Writes a [`Pod`] into the memory at the given physical address,
+    /// which may be wider than `XLEN` bits. See [`Memory::write`].
+    ///
+    /// ## Panics
+    ///
+    /// If the memory is empty.
+    pub fn write_phys<T: Pod>(&mut self, addr: PhysAddr, value: T) {
+        let addr = addr.as_u64();
+        for (offset, byte) in bytemuck::bytes_of(&value).iter().enumerate() {
+            let idx = self.wrap(addr.wrapping_add(offset as u64));
+            self.memory[idx] = *byte;
+        }
     }
 
     /// Reads a [`Pod`] from the memory at the given address.
     ///
+    /// The address wraps around at the end of the memory, so a value
+    /// that crosses the end is continued at the start of the RAM.
+    ///
     /// ## Panics
     ///
-    /// - if address is out of bounds
-    /// - if address is unaligned to `T`
-    /// - if transmute to `T` failed
-    /// - if address can not be converted into a `usize`
+    /// If the memory is empty.
     pub fn read<T: Pod>(&self, addr: B::Addr) -> T {
-        let addr = Self::addr_to_usize(addr);
-        let bytes = &self.memory[addr..addr + mem::size_of::<T>()];
-        *bytemuck::from_bytes::<T>(bytes)
+        self.read_phys(PhysAddr::new(addr.to_u64()))
+    }
+
+    /// Reads a [`Pod`] from the memory at the given physical address,
+    /// which may be wider than `XLEN` bits. See [`Memory::read`].
+    ///
+    /// ## Panics
+    ///
+    /// If the memory is empty.
+    pub fn read_phys<T: Pod>(&self, addr: PhysAddr) -> T {
+        let addr = addr.as_u64();
+        let mut value = T::zeroed();
+        for (offset, byte) in bytemuck::bytes_of_mut(&mut value).iter_mut().enumerate() {
+            *byte = self.memory[self.wrap(addr.wrapping_add(offset as u64))];
+        }
+        value
+    }
+
+    /// Copies `data` into the RAM, starting at `addr`.
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`Exception::StoreAccessFault`] if the bytes are not completely
+    /// inside the RAM. Nothing is written in this case.
+    pub fn write_bytes(&mut self, addr: B::Addr, data: &[u8]) -> Result<(), Exception> {
+        let range = self
+            .ram_range(addr.to_u64(), data.len())
+            .ok_or(Exception::StoreAccessFault)?;
+        self.memory[range].copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Sets `len` bytes of the RAM, starting at `addr`, to `byte`.
+    ///
+    /// This can be used to zero the `.bss` section, without allocating a buffer.
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`Exception::StoreAccessFault`] if the bytes are not completely
+    /// inside the RAM. Nothing is written in this case.
+    pub fn fill(&mut self, addr: B::Addr, len: usize, byte: u8) -> Result<(), Exception> {
+        let range = self
+            .ram_range(addr.to_u64(), len)
+            .ok_or(Exception::StoreAccessFault)?;
+        self.memory[range].iter_mut().for_each(|b| *b = byte);
+        Ok(())
+    }
+
+    /// Copies `len` bytes out of the RAM, starting at `addr`.
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`Exception::LoadAccessFault`] if the bytes are not completely
+    /// inside the RAM.
+    pub fn read_bytes(&self, addr: B::Addr, len: usize) -> Result<Vec<u8>, Exception> {
+        let range = self
+            .ram_range(addr.to_u64(), len)
+            .ok_or(Exception::LoadAccessFault)?;
+        Ok(self.memory[range].to_vec())
+    }
+
+    /// Fills `buf` with the bytes of the RAM, starting at `addr`.
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`Exception::LoadAccessFault`] if the bytes are not completely
+    /// inside the RAM. `buf` is left untouched in this case.
+    pub fn read_into(&self, addr: B::Addr, buf: &mut [u8]) -> Result<(), Exception> {
+        let range = self
+            .ram_range(addr.to_u64(), buf.len())
+            .ok_or(Exception::LoadAccessFault)?;
+        buf.copy_from_slice(&self.memory[range]);
+        Ok(())
+    }
+
+    /// Renders `len` bytes of the RAM, starting at `addr`, in the classic
+    /// hexdump format, with 16 bytes per line.
+    ///
+    /// Every line contains the address of the first byte, the bytes in hex,
+    /// and their printable ASCII characters. The dump stops at the end of the RAM.
+    pub fn hexdump(&self, addr: B::Addr, len: usize) -> String {
+        let offset = addr.to_u64().saturating_sub(self.base);
+        let start = offset.min(self.size() as u64) as usize;
+        let end = start.saturating_add(len).min(self.size());
+
+        let mut out = String::new();
+        for (idx, line) in self.memory[start..end].chunks(16).enumerate() {
+            let addr = self.base + (start + idx * 16) as u64;
+            let _ = write!(out, "{:0width$x} ", addr, width = B::XLEN / 4);
+
+            for col in 0..16 {
+                if col % 8 == 0 {
+                    out.push(' ');
+                }
+                match line.get(col) {
+                    Some(byte) => {
+                        let _ = write!(out, "{:02x} ", byte);
+                    }
+                    None => out.push_str("   "),
+                }
+            }
+
+            let ascii = line
+                .iter()
+                .map(|&b| {
+                    if b.is_ascii_graphic() || b == b' ' {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect::<String>();
+            let _ = writeln!(out, " |{}|", ascii);
+        }
+        out
+    }
+
+    /// Checks if the `len` bytes at `addr` are completely inside the RAM.
+    pub fn contains(&self, addr: u64, len: usize) -> bool {
+        self.ram_range(addr, len).is_some()
+    }
+
+    /// Returns the range of `len` bytes at `addr` inside the RAM,
+    /// if they are completely inside it.
+    fn ram_range(&self, addr: u64, len: usize) -> Option<Range<usize>> {
+        let start = addr.checked_sub(self.base)?;
+        let end = start.checked_add(len as u64)?;
+        (end <= self.size() as u64).then(|| start as usize..end as usize)
+    }
+
+    /// Returns the index of the byte at `addr`, which is `(addr - base) % size`.
+    fn wrap(&self, addr: u64) -> usize {
+        assert!(!self.memory.is_empty(), "memory is empty");
+        (addr.wrapping_sub(self.base) % self.size() as u64) as usize
+    }
+
+    /// Returns the base address and the device of every mapped device.
+    pub(crate) fn devices(&self) -> impl Iterator<Item = (u64, &SharedDevice)> {
+        self.devices
+            .iter()
+            .map(|dev| (dev.region.start, &dev.device))
+    }
+
+    /// Returns the interrupt lines of the given hart, that are asserted by any of the devices.
+    pub(crate) fn device_interrupts(&self, hart: u64) -> u64 {
+        self.devices
+            .iter()
+            .fold(0, |lines, dev| lines | dev.device.borrow().interrupts(hart))
+    }
+
+    /// Returns the exit code of the first device, that received an exit request
+    /// since the last call.
+    pub(crate) fn take_exit_request(&self) -> Option<u64> {
+        self.devices
+            .iter()
+            .find_map(|dev| dev.device.borrow_mut().take_exit())
+    }
+
+    /// Reads a little endian integer of `width` bytes from the given address,
+    /// and zero or sign extends it to `XLEN` bits.
+    ///
+    /// If the address is inside the region of a device, the read is forwarded to it.
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`Exception::LoadAddressMisaligned`] if the address is not aligned
+    /// to `width`, and [`Exception::LoadAccessFault`] if it's neither inside the RAM,
+    /// nor inside the region of a device that supports the access.
+    ///
+    /// ## Panics
+    ///
+    /// If `width` is not `1`, `2`, `4` or `8` bytes, or is larger than `XLEN`.
+    pub fn read_int(
+        &self,
+        addr: B::Addr,
+        width: usize,
+        signed: bool,
+    ) -> Result<B::Addr, Exception> {
+        let val = self.read_int_phys(PhysAddr::new(addr.to_u64()), width)?;
+
+        let shift = 64 - width * 8;
+        let val = if signed {
+            ((val << shift) as i64 >> shift) as u64
+        } else {
+            (val << shift) >> shift
+        };
+        Ok(B::Addr::from_u64(val & (u64::MAX >> (64 - B::XLEN))))
+    }
+
+    /// Reads a little endian integer of `width` bytes from the given physical address,
+    /// which may be wider than `XLEN` bits. See [`Memory::read_int`].
+    ///
+    /// The integer is always zero extended.
+    ///
+    /// ## Errors
+    ///
+    /// The same as for [`Memory::read_int`].
+    ///
+    /// ## Panics
+    ///
+    /// The same as for [`Memory::read_int`].
+    pub fn read_int_phys(&self, addr: PhysAddr, width: usize) -> Result<u64, Exception> {
+        let target = self.target(
+            addr.as_u64(),
+            width,
+            Exception::LoadAddressMisaligned,
+            Exception::LoadAccessFault,
+        )?;
+
+        match target {
+            Target::Ram(bytes) => {
+                let mut buf = [0_u8; 8];
+                buf[..width].copy_from_slice(&self.memory[bytes]);
+                Ok(u64::from_le_bytes(buf))
+            }
+            Target::Device(idx, offset) => self.devices[idx]
+                .device
+                .borrow_mut()
+                .read(offset, width)
+                .ok_or(Exception::LoadAccessFault),
+        }
+    }
+
+    /// Writes the lower `width` bytes of `val` as a little endian integer
+    /// into the given address.
+    ///
+    /// If the address is inside the region of a device, the write is forwarded to it.
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`Exception::StoreAddressMisaligned`] if the address is not aligned
+    /// to `width`, and [`Exception::StoreAccessFault`] if it's neither inside the RAM,
+    /// nor inside the region of a device that supports the access.
+    ///
+    /// ## Panics
+    ///
+    /// If `width` is not `1`, `2`, `4` or `8` bytes, or is larger than `XLEN`.
+    pub fn write_int(
+        &mut self,
+        addr: B::Addr,
+        width: usize,
+        val: B::Addr,
+    ) -> Result<(), Exception> {
+        self.write_int_phys(PhysAddr::new(addr.to_u64()), width, val.to_u64())
+    }
+
+    /// Writes the lower `width` bytes of `val` as a little endian integer
+    /// into the given physical address, which may be wider than `XLEN` bits.
+    /// See [`Memory::write_int`].
+    ///
+    /// ## Errors
+    ///
+    /// The same as for [`Memory::write_int`].
+    ///
+    /// ## Panics
+    ///
+    /// The same as for [`Memory::write_int`].
+    pub fn write_int_phys(
+        &mut self,
+        addr: PhysAddr,
+        width: usize,
+        val: u64,
+    ) -> Result<(), Exception> {
+        let target = self.target(
+            addr.as_u64(),
+            width,
+            Exception::StoreAddressMisaligned,
+            Exception::StoreAccessFault,
+        )?;
+
+        match target {
+            Target::Ram(bytes) => {
+                self.memory[bytes].copy_from_slice(&val.to_le_bytes()[..width]);
+                Ok(())
+            }
+            Target::Device(idx, offset) => {
+                let shift = 64 - width * 8;
+                self.devices[idx]
+                    .device
+                    .borrow_mut()
+                    .write(offset, width, (val << shift) >> shift)
+                    .ok_or(Exception::StoreAccessFault)
+            }
+        }
+    }
+
+    /// Checks the physical memory attributes of an integer access of `width` bytes
+    /// at `addr`, and returns where the access goes to.
+    ///
+    /// Devices take precedence over the RAM they shadow. Returns the given exception
+    /// if the address is misaligned, or if it's not completely inside the region
+    /// of a single device or the RAM.
+    fn target(
+        &self,
+        addr: u64,
+        width: usize,
+        misaligned: Exception,
+        fault: Exception,
+    ) -> Result<Target, Exception> {
+        assert!(
+            matches!(width, 1 | 2 | 4 | 8) && width * 8 <= B::XLEN,
+            "invalid integer width {} for XLEN {}",
+            width,
+            B::XLEN
+        );
+
+        if addr % width as u64 != 0 {
+            return Err(misaligned);
+        }
+
+        let end = addr.checked_add(width as u64).ok_or(fault)?;
+        let device = self
+            .devices
+            .iter()
+            .position(|dev| dev.region.start <= addr && end <= dev.region.end);
+        if let Some(idx) = device {
+            return Ok(Target::Device(idx, addr - self.devices[idx].region.start));
+        }
+
+        self.ram_range(addr, width).map(Target::Ram).ok_or(fault)
+    }
+}
+
+/// The serialized form of the [`Memory`], which only contains the pages
+/// that are not completely zero.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct MemoryState {
+    #[serde(default)]
+    base: u64,
+    size: usize,
+    pages: Vec<(usize, Vec<u8>)>,
+}
+
+#[cfg(feature = "serde")]
+impl<B: Base> serde::Serialize for Memory<B> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let pages = self
+            .memory
+            .chunks(PAGE_SIZE)
+            .enumerate()
+            .filter_map(|(idx, page)| {
+                let used = page.iter().any(|&b| b != 0);
+                used.then(|| (idx * PAGE_SIZE, page.to_vec()))
+            })
+            .collect();
+
+        MemoryState {
+            base: self.base,
+            size: self.size(),
+            pages,
+        }
+        .serialize(serializer)
     }
+}
 
-    #[allow(clippy::match_wild_err_arm)]
-    fn addr_to_usize(addr: B::Addr) -> usize {
-        addr.to_u64()
-            .try_into()
-            .expect("address conversion to usize failed")
+#[cfg(feature = "serde")]
+impl<'de, B: Base> serde::Deserialize<'de> for Memory<B> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let state = MemoryState::deserialize(deserializer)?;
+        if !Self::is_valid_region(state.base, state.size) {
+            return Err(serde::de::Error::custom(
+                "memory size exceeds the address space",
+            ));
+        }
+
+        let mut memory = Self::with_base(state.base, state.size);
+        for (start, page) in state.pages {
+            let target = start
+                .checked_add(page.len())
+                .and_then(|end| memory.memory.get_mut(start..end))
+                .ok_or_else(|| serde::de::Error::custom("page is out of bounds"))?;
+            target.copy_from_slice(&page);
+        }
+        Ok(memory)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Memory;
-    use crate::RV64I;
+    use super::{Memory, PhysAddr};
+    use crate::{device::MmioDevice, trap::Exception, RV32I, RV64I};
+    use std::{cell::RefCell, rc::Rc};
 
     #[test]
+    #[allow(clippy::unseparated_literal_suffix)]
     fn read_write() {
         let mut memory = Memory::<RV64I>::with_size(1024);
 
@@ -110,4 +607,264 @@ mod tests {
         memory.write(0x08, num.to_be());
         assert_eq!(u32::from_be_bytes(memory.read::<[u8; 4]>(0x08)), num);
     }
+
+    #[test]
+    fn read_write_int() {
+        let mut memory = Memory::<RV64I>::with_size(1024);
+
+        memory.write_int(0x10, 1, 0x1234).unwrap();
+        assert_eq!(memory.read_int(0x10, 1, false), Ok(0x34));
+        assert_eq!(memory.read::<u8>(0x11), 0);
+
+        memory.write_int(0x20, 2, 0xFFFF_8001).unwrap();
+        assert_eq!(memory.read::<[u8; 2]>(0x20), [0x01, 0x80]);
+        assert_eq!(memory.read_int(0x20, 2, false), Ok(0x8001));
+        assert_eq!(memory.read_int(0x20, 2, true), Ok(0xFFFF_FFFF_FFFF_8001));
+
+        memory.write_int(0x30, 4, 0xDEAD_BEEF).unwrap();
+        assert_eq!(memory.read_int(0x30, 4, false), Ok(0xDEAD_BEEF));
+        assert_eq!(memory.read_int(0x30, 4, true), Ok(0xFFFF_FFFF_DEAD_BEEF));
+
+        memory.write_int(0x40, 8, 0x0123_4567_89AB_CDEF).unwrap();
+        assert_eq!(memory.read::<[u8; 2]>(0x40), [0xEF, 0xCD]);
+        assert_eq!(memory.read_int(0x40, 8, true), Ok(0x0123_4567_89AB_CDEF));
+
+        assert_eq!(
+            memory.read_int(0x22, 4, false),
+            Err(Exception::LoadAddressMisaligned)
+        );
+        assert_eq!(
+            memory.write_int(0x400, 2, 0),
+            Err(Exception::StoreAccessFault)
+        );
+        assert_eq!(
+            memory.read_int(0x3FC, 8, false),
+            Err(Exception::LoadAddressMisaligned)
+        );
+        assert_eq!(memory.read_int(0x3F8, 8, false), Ok(0));
+    }
+
+    #[test]
+    fn read_int_rv32() {
+        let mut memory = Memory::<RV32I>::with_size(16);
+
+        memory.write_int(0x4, 2, 0x8000).unwrap();
+        assert_eq!(memory.read_int(0x4, 2, true), Ok(0xFFFF_8000));
+        assert_eq!(memory.read_int(0x4, 4, true), Ok(0x8000));
+        assert_eq!(
+            memory.write_int(0x10, 1, 0),
+            Err(Exception::StoreAccessFault)
+        );
+    }
+
+    /// A device with a single register.
+    struct Register(u64);
+
+    impl MmioDevice for Register {
+        fn size(&self) -> u64 {
+            8
+        }
+
+        fn read(&mut self, offset: u64, _width: usize) -> Option<u64> {
+            (offset == 0).then(|| self.0)
+        }
+
+        fn write(&mut self, offset: u64, _width: usize, val: u64) -> Option<()> {
+            (offset == 0).then(|| self.0 = val)
+        }
+    }
+
+    #[test]
+    fn devices() {
+        let mut memory = Memory::<RV64I>::with_size(0x1000);
+        let device = Rc::new(RefCell::new(Register(0)));
+        memory.map_device(0x2000, device.clone());
+
+        memory.write_int(0x2000, 4, 0xFFFF_FFFF_8000_0001).unwrap();
+        assert_eq!(device.borrow().0, 0x8000_0001);
+        assert_eq!(memory.read_int(0x2000, 4, true), Ok(0xFFFF_FFFF_8000_0001));
+        assert_eq!(memory.read_int(0x2000, 2, false), Ok(0x0001));
+
+        // Accesses the device doesn't support.
+        assert_eq!(
+            memory.read_int(0x2004, 4, false),
+            Err(Exception::LoadAccessFault)
+        );
+        assert_eq!(
+            memory.write_int(0x2008, 4, 0),
+            Err(Exception::StoreAccessFault)
+        );
+
+        // The hole between the RAM and the device.
+        assert_eq!(
+            memory.read_int(0x1800, 4, false),
+            Err(Exception::LoadAccessFault)
+        );
+        assert_eq!(
+            memory.write_int(0x1000, 1, 0),
+            Err(Exception::StoreAccessFault)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "overlaps another region")]
+    fn overlapping_devices() {
+        let mut memory = Memory::<RV64I>::with_size(0x1000);
+        memory.map_device(0x2000, Rc::new(RefCell::new(Register(0))));
+        memory.map_device(0x2004, Rc::new(RefCell::new(Register(0))));
+    }
+
+    #[test]
+    fn wraparound() {
+        let mut memory = Memory::<RV32I>::with_size(16);
+        memory.write(20_u32, 0xAB_u8);
+        assert_eq!(memory.read::<u8>(4), 0xAB);
+
+        // A value that crosses the end is continued at the start.
+        memory.write(14_u32, 0x1122_3344_u32);
+        assert_eq!(memory.read::<u16>(14), 0x3344);
+        assert_eq!(memory.read::<u16>(0), 0x1122);
+        assert_eq!(memory.read::<u32>(30), 0x1122_3344);
+        assert_eq!(memory.read::<u32>(u32::MAX - 1), 0x1122_3344);
+    }
+
+    #[test]
+    fn max_size() {
+        assert!(Memory::<RV32I>::is_valid_size(1 << 32));
+        assert!(!Memory::<RV32I>::is_valid_size((1 << 32) + 1));
+        assert!(Memory::<RV64I>::is_valid_size(usize::MAX));
+    }
+
+    #[test]
+    #[should_panic(expected = "exceeds the 32-bit address space")]
+    fn size_exceeds_address_space() {
+        Memory::<RV32I>::with_size((1 << 32) + 1);
+    }
+
+    #[test]
+    fn bulk_copy() {
+        let mut memory = Memory::<RV32I>::with_size(16);
+        memory.write_bytes(4, b"riscv").unwrap();
+        assert_eq!(memory.read_bytes(4, 5).unwrap(), b"riscv");
+        assert_eq!(memory.read::<u8>(9), 0);
+
+        let mut buf = [0; 4];
+        memory.read_into(12, &mut buf).unwrap();
+        assert_eq!(buf, [0; 4]);
+        memory.read_into(3, &mut buf).unwrap();
+        assert_eq!(&buf, b"\0ris");
+
+        assert_eq!(
+            memory.write_bytes(14, b"abc"),
+            Err(Exception::StoreAccessFault)
+        );
+        assert_eq!(memory.read::<u16>(14), 0);
+        assert_eq!(memory.read_bytes(16, 1), Err(Exception::LoadAccessFault));
+        assert_eq!(
+            memory.read_bytes(u32::MAX, 2),
+            Err(Exception::LoadAccessFault)
+        );
+        assert_eq!(memory.read_bytes(16, 0), Ok(vec![]));
+    }
+
+    #[test]
+    fn fill() {
+        let mut memory = Memory::<RV64I>::with_size(0x1000);
+        memory.fill(0x10, 0x100, 0xAA).unwrap();
+        assert_eq!(memory.read_bytes(0x10, 0x100), Ok(vec![0xAA; 0x100]));
+        assert_eq!(memory.read::<u8>(0xF), 0);
+        assert_eq!(memory.read::<u8>(0x110), 0);
+
+        memory.fill(0x20, 0x10, 0).unwrap();
+        assert_eq!(memory.read::<u64>(0x20), 0);
+        assert_eq!(memory.read::<u8>(0x30), 0xAA);
+
+        assert_eq!(
+            memory.fill(0xFF0, 0x11, 0xAA),
+            Err(Exception::StoreAccessFault)
+        );
+        assert_eq!(memory.read::<u8>(0xFF0), 0);
+        assert_eq!(
+            memory.fill(u64::MAX, 2, 0xAA),
+            Err(Exception::StoreAccessFault)
+        );
+    }
+
+    #[test]
+    fn hexdump() {
+        let mut memory = Memory::<RV32I>::with_size(0x40);
+        memory.write_bytes(0x12, b"Hello, RISC-V!\n").unwrap();
+
+        assert_eq!(
+            memory.hexdump(0x10, 0x14),
+            concat!(
+                "00000010  00 00 48 65 6c 6c 6f 2c  20 52 49 53 43 2d 56 21  |..Hello, RISC-V!|\n",
+                "00000020  0a 00 00 00                                       |....|\n",
+            )
+        );
+
+        // The dump stops at the end of the memory.
+        assert_eq!(
+            memory.hexdump(0x3C, 0x100),
+            "0000003c  00 00 00 00                                       |....|\n"
+        );
+        assert_eq!(memory.hexdump(0x40, 0x10), "");
+        assert_eq!(
+            Memory::<RV64I>::with_size(1).hexdump(0, usize::MAX),
+            "0000000000000000  00                                                |.|\n"
+        );
+    }
+
+    #[test]
+    fn physical_addresses() {
+        let mut memory = Memory::<RV32I>::with_size(0x100);
+        memory.write(0x10_u32, 0xDEAD_BEEF_u32);
+        assert_eq!(memory.read_phys::<u32>(PhysAddr::new(0x10)), 0xDEAD_BEEF);
+
+        // Physical addresses can be wider than `XLEN`.
+        memory.write_phys(PhysAddr::new(0x1_0000_0020), 0x1234_u16);
+        assert_eq!(memory.read::<u16>(0x20), 0x1234);
+    }
+
+    #[test]
+    fn base() {
+        let mut memory = Memory::<RV32I>::with_base(0x8000_0000, 0x1000);
+        assert_eq!(memory.base(), 0x8000_0000);
+
+        memory.write_int(0x8000_0FFC, 4, 0x1234_5678).unwrap();
+        assert_eq!(memory.read::<u32>(0x8000_0FFC_u32), 0x1234_5678);
+        assert_eq!(memory.read_bytes(0x8000_0FFE, 2), Ok(vec![0x34, 0x12]));
+        assert!(memory.contains(0x8000_0000, 0x1000));
+        assert!(!memory.contains(0x7FFF_FFFF, 1));
+
+        // The RAM doesn't start at `0` anymore.
+        assert_eq!(
+            memory.read_int(0, 4, false),
+            Err(Exception::LoadAccessFault)
+        );
+        assert_eq!(
+            memory.write_bytes(0x8000_1000, &[0]),
+            Err(Exception::StoreAccessFault)
+        );
+        assert!(memory.hexdump(0x8000_0FF0, 0x10).starts_with("80000ff0 "));
+
+        assert!(Memory::<RV32I>::is_valid_region(0xFFFF_F000, 0x1000));
+        assert!(!Memory::<RV32I>::is_valid_region(0xFFFF_F000, 0x1001));
+        assert!(!Memory::<RV64I>::is_valid_region(u64::MAX, 1));
+    }
+
+    #[test]
+    fn shadowed_ram() {
+        let mut memory = Memory::<RV64I>::with_size(0x1000);
+        let device = Rc::new(RefCell::new(Register(0)));
+        memory.map_device(0x800, device.clone());
+
+        memory.write_int(0x800, 8, 0x1234).unwrap();
+        assert_eq!(device.borrow().0, 0x1234);
+        assert_eq!(memory.read::<u64>(0x800), 0);
+
+        // The rest of the RAM is still accessible.
+        memory.write_int(0x808, 8, 0x5678).unwrap();
+        assert_eq!(memory.read::<u64>(0x808), 0x5678);
+    }
 }
@@ -1,13 +1,12 @@
+use super::Memory;
 use crate::{
     cpu::{csr, PrivilegeMode, Registers},
-    memory,
     trap::Exception,
     Address, Base,
 };
 use bitflags::bitflags;
-use bytemuck::Pod;
 use derive_more::Display;
-use std::{mem, rc::Rc};
+use std::marker::PhantomData;
 
 /// The result type for MMU operations.
 pub type Result<T, E = Exception> = std::result::Result<T, E>;
@@ -15,35 +14,65 @@ pub type Result<T, E = Exception> = std::result::Result<T, E>;
 /// The size of a page inside the MMU is 4KiB.
 pub const PAGE_SIZE: u64 = 1 << 12;
 
+/// The number of entries inside the TLB of the MMU.
+pub const TLB_SIZE: usize = 64;
+
 /// Defines the addressing mode that the MMU will use.
 #[derive(Debug, Clone, Copy, Display)]
 pub enum AddressingMode {
     None,
     SV32,
     SV39,
+    SV48,
 }
 
 impl AddressingMode {
-    pub(crate) fn levels(&self) -> u64 {
+    pub(crate) fn levels(self) -> u64 {
         match self {
             AddressingMode::SV32 => 2,
             AddressingMode::SV39 => 3,
+            AddressingMode::SV48 => 4,
             AddressingMode::None => unreachable!("levels shouldn't called on `None` mode"),
         }
     }
 
-    pub(crate) fn pte_size(&self) -> u64 {
+    /// Returns the number of bits of a single VPN field.
+    pub(crate) fn vpn_bits(self) -> u64 {
         match self {
-            AddressingMode::SV32 => 4,
-            AddressingMode::SV39 => 8,
+            AddressingMode::SV32 => 10,
+            AddressingMode::SV39 | AddressingMode::SV48 => 9,
             AddressingMode::None => unreachable!("levels shouldn't called on `None` mode"),
         }
     }
 
-    pub(crate) fn pte_count(&self) -> u64 {
+    /// Returns the full physical page number of the page table entry `pte`,
+    /// which is 22 bits wide in Sv32, and 44 bits wide otherwise.
+    pub(crate) fn ppn(self, pte: u64) -> u64 {
+        match self {
+            AddressingMode::SV32 => (pte >> 10) & 0x3F_FFFF,
+            _ => (pte >> 10) & ((1 << 44) - 1),
+        }
+    }
+
+    /// Checks if `va` is a canonical address, where all bits above the
+    /// virtual address are equal to it's highest bit.
+    ///
+    /// Sv32 uses the whole 32-bit address, so every address is canonical.
+    pub(crate) fn is_canonical(self, va: VirtAddr) -> bool {
+        let bits = match self {
+            AddressingMode::SV39 => 39,
+            AddressingMode::SV48 => 48,
+            _ => return true,
+        };
+
+        let shift = 64 - bits;
+        ((va.as_u64() << shift) as i64 >> shift) as u64 == va.as_u64()
+    }
+
+    pub(crate) fn pte_size(self) -> u64 {
         match self {
-            AddressingMode::SV32 => 1 << 10,
-            AddressingMode::SV39 => 1 << 9,
+            AddressingMode::SV32 => 4,
+            AddressingMode::SV39 | AddressingMode::SV48 => 8,
             AddressingMode::None => unreachable!("levels shouldn't called on `None` mode"),
         }
     }
@@ -57,6 +86,27 @@ pub enum AccessMode {
     Write,
 }
 
+impl AccessMode {
+    /// Returns the page fault, that is raised if the translation of this access fails.
+    pub fn page_fault(self) -> Exception {
+        match self {
+            AccessMode::Execute => Exception::InstructionPageFault,
+            AccessMode::Read => Exception::LoadPageFault,
+            AccessMode::Write => Exception::StorePageFault,
+        }
+    }
+
+    /// Returns the access fault, that is raised if the physical address
+    /// of this access is invalid.
+    pub fn access_fault(self) -> Exception {
+        match self {
+            AccessMode::Execute => Exception::InstructionAccessFault,
+            AccessMode::Read => Exception::LoadAccessFault,
+            AccessMode::Write => Exception::StoreAccessFault,
+        }
+    }
+}
+
 /// Represents a virtual address that has to be converted
 /// to a physical address by the MMU.
 #[repr(transparent)]
@@ -64,25 +114,41 @@ pub enum AccessMode {
 pub struct VirtAddr(u64);
 
 impl VirtAddr {
-    pub(crate) fn vpn(&self, idx: u64, mode: AddressingMode) -> u16 {
-        use AddressingMode::*;
+    /// Creates a new virtual address from it's raw `u64` representation.
+    pub const fn new(addr: u64) -> Self {
+        Self(addr)
+    }
+
+    /// Returns the raw `u64` representation of this address.
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    pub(crate) fn vpn(self, idx: u64, mode: AddressingMode) -> u16 {
+        use AddressingMode::{SV32, SV39, SV48};
 
         match (mode, idx) {
             (SV32, 0) => ((self.0 >> 12) & 0x3FF) as u16,
             (SV32, 1) => ((self.0 >> 22) & 0x3FF) as u16,
 
-            (SV39, 0) => ((self.0 >> 12) & 0x1FF) as u16,
-            (SV39, 1) => ((self.0 >> 21) & 0x1FF) as u16,
-            (SV39, 2) => ((self.0 >> 30) & 0x1FF) as u16,
-
-            (SV48, 0) => ((self.0 >> 12) & 0x1FF) as u16,
-            (SV48, 1) => ((self.0 >> 21) & 0x1FF) as u16,
-            (SV48, 2) => ((self.0 >> 30) & 0x1FF) as u16,
+            (SV39, 0) | (SV48, 0) => ((self.0 >> 12) & 0x1FF) as u16,
+            (SV39, 1) | (SV48, 1) => ((self.0 >> 21) & 0x1FF) as u16,
+            (SV39, 2) | (SV48, 2) => ((self.0 >> 30) & 0x1FF) as u16,
             (SV48, 3) => ((self.0 >> 39) & 0x1FF) as u16,
 
             (mode, idx) => panic!("VPN[{}] is not available in {} mode", idx, mode),
         }
     }
+
+    /// Returns the full virtual page number of this address.
+    pub fn page_number(self) -> u64 {
+        self.0 / PAGE_SIZE
+    }
+
+    /// Returns the offset of this address inside it's page.
+    pub fn page_offset(self) -> u64 {
+        self.0 % PAGE_SIZE
+    }
 }
 
 impl<A: Address> From<A> for VirtAddr {
@@ -97,13 +163,31 @@ impl<A: Address> From<A> for VirtAddr {
 #[derive(Debug, Display, Clone, Copy)]
 pub struct PhysAddr(u64);
 
+impl PhysAddr {
+    /// Creates a new physical address from it's raw `u64` representation.
+    pub const fn new(addr: u64) -> Self {
+        Self(addr)
+    }
+
+    /// Returns the raw `u64` representation of this address.
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    /// Returns the offset of this address inside it's page.
+    pub fn page_offset(self) -> u64 {
+        self.0 % PAGE_SIZE
+    }
+}
+
 bitflags! {
     /// The low byte of a Sv32 page table entry.
     ///
     /// See section 4.3.1 in the priviliged specification.
     pub struct PteFlags: u8 {
-        // TODO: Implement behaviour for this.
+        /// Indicates whether the page was written since the bit was last cleared.
         const D = 0b1000_0000;
+        /// Indicates whether the page was accessed since the bit was last cleared.
         const A = 0b0100_0000;
         /// Designates a global mapping
         const G = 0b0010_0000;
@@ -122,6 +206,77 @@ bitflags! {
     }
 }
 
+/// A single cached translation inside the [`Tlb`].
+#[derive(Debug, Clone, Copy)]
+struct TlbEntry {
+    /// The virtual page number this entry translates.
+    vpn: u64,
+    /// The address space this translation belongs to.
+    asid: u16,
+    /// The resolved physical page number.
+    ppn: u64,
+    /// The mask of the low VPN bits that are part of the same page.
+    ///
+    /// This is `0` for regular pages, and non-zero for superpages.
+    page_mask: u64,
+    /// The flags of the leaf PTE that was used for the translation.
+    flags: PteFlags,
+}
+
+/// A direct-mapped translation lookaside buffer, which caches
+/// the results of page table walks.
+///
+/// Every entry is tagged with the ASID of the address space it belongs to,
+/// so a change of the ASID doesn't require a full flush.
+/// Entries of global mappings match every ASID.
+struct Tlb {
+    entries: [Option<TlbEntry>; TLB_SIZE],
+}
+
+impl Tlb {
+    fn new() -> Self {
+        Self {
+            entries: [None; TLB_SIZE],
+        }
+    }
+
+    fn index(vpn: u64) -> usize {
+        (vpn % TLB_SIZE as u64) as usize
+    }
+
+    fn lookup(&self, vpn: u64, asid: u16) -> Option<TlbEntry> {
+        self.entries[Self::index(vpn)].filter(|entry| {
+            entry.vpn == vpn && (entry.asid == asid || entry.flags.contains(PteFlags::G))
+        })
+    }
+
+    fn insert(&mut self, entry: TlbEntry) {
+        self.entries[Self::index(entry.vpn)] = Some(entry);
+    }
+
+    /// Removes all entries that match the given virtual page number and ASID.
+    ///
+    /// A `None` value matches every page / address space.
+    /// Global mappings are only removed if no ASID is given.
+    fn flush(&mut self, vpn: Option<u64>, asid: Option<u16>) {
+        for slot in self.entries.iter_mut() {
+            let matches = slot.map_or(false, |entry| {
+                let vpn_matches = vpn.map_or(true, |vpn| {
+                    entry.vpn & !entry.page_mask == vpn & !entry.page_mask
+                });
+                let asid_matches = asid.map_or(true, |asid| {
+                    entry.asid == asid && !entry.flags.contains(PteFlags::G)
+                });
+                vpn_matches && asid_matches
+            });
+
+            if matches {
+                *slot = None;
+            }
+        }
+    }
+}
+
 /// Memory management unit.
 ///
 /// The MMU is responsible for the paging process of the CPU.
@@ -132,44 +287,197 @@ bitflags! {
 ///
 /// [`spec`]: https://riscv.org/specifications/privileged-isa/
 pub struct Mmu<B: Base> {
-    registers: Rc<Registers<B>>,
-    mode: AddressingMode,
+    tlb: Tlb,
+    _base: PhantomData<B>,
+}
+
+impl<B: Base> Default for Mmu<B> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<B: Base> Mmu<B> {
-    /// Creates a new `Mmu` that will read/write from/into the given
-    /// registers.
-    pub fn new(registers: Rc<Registers<B>>) -> Self {
-        let (mode, _, _) = decode_satp_reg::<B>(registers.read_csr(csr::SATP));
+    /// Creates a new `Mmu` with an empty TLB.
+    pub fn new() -> Self {
+        Self {
+            tlb: Tlb::new(),
+            _base: PhantomData,
+        }
+    }
+
+    /// Translates the given virtual address into a physical address,
+    /// using the page tables inside `memory`.
+    ///
+    /// The addressing mode, ASID and root page table are read from the `satp`
+    /// register inside `registers`. The permissions are checked for an access
+    /// from the given `privilege` mode, which is the effective privilege of the
+    /// access, and respect the `SUM` and `MXR` bits of `mstatus`.
+    ///
+    /// The TLB is consulted first, and the page tables are only walked
+    /// if there's no cached translation for the address. A walk sets the
+    /// `A` bit of the leaf PTE, and the `D` bit if it's a write.
+    /// Translations are only cached for writes, once the `D` bit is set.
+    ///
+    /// ## Errors
+    ///
+    /// Returns the page fault for the given `access` kind, if the address
+    /// is not canonical, not mapped, or the access is not permitted.
+    /// Returns the access fault for the `access` kind, if a page table entry
+    /// is outside of the physical memory.
+    pub fn translate_addr(
+        &mut self,
+        registers: &Registers<B>,
+        privilege: PrivilegeMode,
+        memory: &mut Memory<B>,
+        va: VirtAddr,
+        access: AccessMode,
+    ) -> Result<PhysAddr, Exception> {
+        let (mode, asid, root) = decode_satp_reg::<B>(registers.read_csr(csr::SATP));
+        let mode = addressing_mode::<B>(mode);
+        if let AddressingMode::None = mode {
+            return Ok(PhysAddr::new(va.as_u64()));
+        }
 
-        let mode = match (mode, B::XLEN) {
-            (0, 32) => AddressingMode::None,
-            (1, 32) => AddressingMode::SV32,
+        if !mode.is_canonical(va) {
+            return Err(access.page_fault());
+        }
 
-            (0, 64) => AddressingMode::None,
-            (8, 64) => AddressingMode::SV39,
-            (9, 64) => AddressingMode::SV48,
+        // Writes to a page that is not dirty yet must walk the page tables,
+        // to set the `D` bit of the PTE.
+        let cached = self.tlb.lookup(va.page_number(), asid).filter(|entry| {
+            !matches!(access, AccessMode::Write) || entry.flags.contains(PteFlags::D)
+        });
 
-            (mode, xlen) => panic!("invalid addressing mode {} for XLEN {}", mode, xlen),
+        let (mut entry, pte_addr) = if let Some(entry) = cached {
+            (entry, None)
+        } else {
+            let (entry, pte_addr) = Self::walk(memory, mode, root, va, access, asid)?;
+            (entry, Some(pte_addr))
         };
-        Self { registers, mode }
-    }
 
-    fn translate_addr(&self, va: VirtAddr) -> Result<PhysAddr, Exception> {
-        if let AddressingMode::None = self.mode {
-            return Ok(PhysAddr(va.0));
+        let status = registers.mstatus();
+        let allowed = match access {
+            AccessMode::Execute => entry.flags.contains(PteFlags::X),
+            AccessMode::Read => {
+                entry.flags.contains(PteFlags::R)
+                    || (status.mxr() && entry.flags.contains(PteFlags::X))
+            }
+            AccessMode::Write => entry.flags.contains(PteFlags::W),
+        };
+
+        // User pages are only accessible from supervisor mode, if `SUM` is set,
+        // and can never be executed in supervisor mode.
+        let privilege_allowed = match (privilege, entry.flags.contains(PteFlags::U)) {
+            (PrivilegeMode::User, user) => user,
+            (_, false) => true,
+            (_, true) => !matches!(access, AccessMode::Execute) && status.sum(),
+        };
+
+        if !allowed || !privilege_allowed {
+            return Err(access.page_fault());
+        }
+
+        if let Some(pte_addr) = pte_addr {
+            let mut flags = entry.flags | PteFlags::A;
+            if let AccessMode::Write = access {
+                flags |= PteFlags::D;
+            }
+
+            // The `A` and `D` bits are inside the low byte of the PTE.
+            if flags != entry.flags {
+                memory
+                    .write_int_phys(pte_addr, 1, u64::from(flags.bits()))
+                    .map_err(|_| access.access_fault())?;
+                entry.flags = flags;
+            }
+            self.tlb.insert(entry);
         }
 
-        assert!(matches!(self.mode, AddressingMode::SV32));
+        Ok(PhysAddr::new(entry.ppn * PAGE_SIZE + va.page_offset()))
+    }
+
+    /// Flushes the TLB, following the semantics of the `SFENCE.VMA` instruction.
+    ///
+    /// - If `vaddr` and `asid` are `None`, the whole TLB is flushed.
+    /// - If only `vaddr` is given, all translations of the page that contains `vaddr` are flushed.
+    /// - If only `asid` is given, all non-global translations of the address space are flushed.
+    /// - If both are given, only the non-global translation of the page in the address space is
+    ///   flushed.
+    pub fn flush_tlb(&mut self, vaddr: Option<VirtAddr>, asid: Option<u16>) {
+        self.tlb.flush(vaddr.map(VirtAddr::page_number), asid);
+    }
+
+    /// Walks the page tables to find the leaf PTE for the given address,
+    /// and returns the translation together with the address of the leaf PTE.
+    ///
+    /// See section 4.3.2 of the priviliged specification.
+    fn walk(
+        memory: &Memory<B>,
+        mode: AddressingMode,
+        root: u64,
+        va: VirtAddr,
+        access: AccessMode,
+        asid: u16,
+    ) -> Result<(TlbEntry, PhysAddr), Exception> {
+        let mut a = root * PAGE_SIZE;
+        let mut i = mode.levels() - 1;
+
+        loop {
+            let pte_addr = a + u64::from(va.vpn(i, mode)) * mode.pte_size();
+            let pte = memory
+                .read_int_phys(PhysAddr::new(pte_addr), mode.pte_size() as usize)
+                .map_err(|_| access.access_fault())?;
+            let flags = PteFlags::from_bits_truncate(pte as u8);
+
+            if !flags.contains(PteFlags::V)
+                || (!flags.contains(PteFlags::R) && flags.contains(PteFlags::W))
+            {
+                return Err(access.page_fault());
+            }
+
+            let ppn = mode.ppn(pte);
+
+            if flags.intersects(PteFlags::R | PteFlags::X) {
+                // A leaf PTE above the last level maps a superpage, where the low VPN
+                // bits of the virtual address are used as the low bits of the PPN.
+                let page_mask = (1 << (i * mode.vpn_bits())) - 1;
+
+                if ppn & page_mask != 0 {
+                    // Misaligned superpage
+                    return Err(access.page_fault());
+                }
 
-        let satp = self.registers.read_csr(csr::SATP);
-        let (_, _, ppn) = decode_satp_reg::<B>(satp);
+                let entry = TlbEntry {
+                    vpn: va.page_number(),
+                    asid,
+                    ppn: ppn | (va.page_number() & page_mask),
+                    page_mask,
+                    flags,
+                };
+                return Ok((entry, PhysAddr::new(pte_addr)));
+            }
 
-        let a = ppn * PAGE_SIZE;
-        let i = self.mode.levels() - 1;
-        let pte = a + va.vpn(i, self.mode) as u64 * self.mode.pte_size();
+            if i == 0 {
+                return Err(access.page_fault());
+            }
 
-        todo!()
+            i -= 1;
+            a = ppn * PAGE_SIZE;
+        }
+    }
+}
+
+/// Converts the `mode` field of the `satp` register into an [`AddressingMode`].
+///
+/// Unsupported modes can't be written into `satp`, but are treated
+/// as bare mode anyway.
+fn addressing_mode<B: Base>(mode: u8) -> AddressingMode {
+    match (mode, B::XLEN) {
+        (1, 32) => AddressingMode::SV32,
+        (8, 64) => AddressingMode::SV39,
+        (9, 64) => AddressingMode::SV48,
+        _ => AddressingMode::None,
     }
 }
 
@@ -197,25 +505,568 @@ fn decode_satp_reg<B: Base>(value: B::Addr) -> (u8, u16, u64) {
 
 #[cfg(test)]
 mod tests {
+    use super::{AccessMode, AddressingMode, Mmu, PhysAddr, PteFlags, VirtAddr, PAGE_SIZE};
+    use crate::{
+        cpu::{csr, PrivilegeMode, Registers},
+        memory::Memory,
+        trap::Exception,
+        RV32I, RV64I,
+    };
+
+    const ROOT_PPN: u64 = 0x10;
+
+    fn pte(ppn: u64, flags: PteFlags) -> u64 {
+        (ppn << 10) | u64::from(flags.bits())
+    }
+
+    /// Creates a SV39 MMU, that maps the page at `0x4000_1000` to
+    /// the physical page `0x20`.
+    fn sv39_setup(asid: u64) -> (Mmu<RV64I>, Registers<RV64I>, Memory<RV64I>) {
+        let mut memory = Memory::<RV64I>::with_size(0x10_0000);
+
+        // VPN[2] = 1, VPN[1] = 0, VPN[0] = 1
+        memory.write(ROOT_PPN * 0x1000 + 8, pte(0x11, PteFlags::V));
+        memory.write(0x11 * 0x1000, pte(0x12, PteFlags::V));
+        memory.write(0x12 * 0x1000 + 8, pte(0x20, PteFlags::V | PteFlags::R));
+
+        let registers = Registers::<RV64I>::new();
+        registers.write_csr(csr::SATP, (8 << 60) | (asid << 44) | ROOT_PPN);
+
+        (Mmu::new(), registers, memory)
+    }
+
+    fn remap(memory: &mut Memory<RV64I>, ppn: u64) {
+        memory.write(0x12 * 0x1000 + 8, pte(ppn, PteFlags::V | PteFlags::R));
+    }
+
+    #[test]
+    fn tlb_caches_translation() {
+        let (mut mmu, registers, mut memory) = sv39_setup(1);
+        let va = VirtAddr::from(0x4000_1234_u64);
+
+        let pa = mmu
+            .translate_addr(
+                &registers,
+                PrivilegeMode::Supervisor,
+                &mut memory,
+                va,
+                AccessMode::Read,
+            )
+            .unwrap();
+        assert_eq!(pa.as_u64(), 0x20234);
+
+        // The page table changes, but the translation is still cached.
+        remap(&mut memory, 0x30);
+        let pa = mmu
+            .translate_addr(
+                &registers,
+                PrivilegeMode::Supervisor,
+                &mut memory,
+                va,
+                AccessMode::Read,
+            )
+            .unwrap();
+        assert_eq!(pa.as_u64(), 0x20234);
+
+        mmu.flush_tlb(None, None);
+        let pa = mmu
+            .translate_addr(
+                &registers,
+                PrivilegeMode::Supervisor,
+                &mut memory,
+                va,
+                AccessMode::Read,
+            )
+            .unwrap();
+        assert_eq!(pa.as_u64(), 0x30234);
+    }
+
+    #[test]
+    fn tlb_flush_scopes() {
+        let (mut mmu, registers, mut memory) = sv39_setup(1);
+        let va = VirtAddr::from(0x4000_1234_u64);
+
+        mmu.translate_addr(
+            &registers,
+            PrivilegeMode::Supervisor,
+            &mut memory,
+            va,
+            AccessMode::Read,
+        )
+        .unwrap();
+        remap(&mut memory, 0x30);
+
+        // Flushing other pages or address spaces keeps the entry.
+        mmu.flush_tlb(Some(VirtAddr::from(0x4000_2000_u64)), None);
+        mmu.flush_tlb(None, Some(2));
+        mmu.flush_tlb(Some(va), Some(2));
+        let pa = mmu
+            .translate_addr(
+                &registers,
+                PrivilegeMode::Supervisor,
+                &mut memory,
+                va,
+                AccessMode::Read,
+            )
+            .unwrap();
+        assert_eq!(pa.as_u64(), 0x20234);
+
+        mmu.flush_tlb(Some(va), Some(1));
+        let pa = mmu
+            .translate_addr(
+                &registers,
+                PrivilegeMode::Supervisor,
+                &mut memory,
+                va,
+                AccessMode::Read,
+            )
+            .unwrap();
+        assert_eq!(pa.as_u64(), 0x30234);
+
+        remap(&mut memory, 0x40);
+        mmu.flush_tlb(None, Some(1));
+        let pa = mmu
+            .translate_addr(
+                &registers,
+                PrivilegeMode::Supervisor,
+                &mut memory,
+                va,
+                AccessMode::Read,
+            )
+            .unwrap();
+        assert_eq!(pa.as_u64(), 0x40234);
+
+        remap(&mut memory, 0x50);
+        mmu.flush_tlb(Some(va), None);
+        let pa = mmu
+            .translate_addr(
+                &registers,
+                PrivilegeMode::Supervisor,
+                &mut memory,
+                va,
+                AccessMode::Read,
+            )
+            .unwrap();
+        assert_eq!(pa.as_u64(), 0x50234);
+    }
+
+    /// Maps the 2MiB superpage at `0x4000_0000` to the physical page `ppn`.
+    fn sv39_superpage(ppn: u64) -> (Mmu<RV64I>, Registers<RV64I>, Memory<RV64I>) {
+        let mut memory = Memory::<RV64I>::with_size(0x10_0000);
+
+        memory.write(ROOT_PPN * 0x1000 + 8, pte(0x11, PteFlags::V));
+        memory.write(0x11 * 0x1000, pte(ppn, PteFlags::V | PteFlags::R));
+
+        let registers = Registers::<RV64I>::new();
+        registers.write_csr(csr::SATP, (8 << 60) | ROOT_PPN);
+
+        (Mmu::new(), registers, memory)
+    }
+
+    #[test]
+    fn superpage() {
+        let (mut mmu, registers, mut memory) = sv39_superpage(0x200);
+
+        let va = VirtAddr::from(0x4001_2345_u64);
+        let pa = mmu
+            .translate_addr(
+                &registers,
+                PrivilegeMode::Supervisor,
+                &mut memory,
+                va,
+                AccessMode::Read,
+            )
+            .unwrap();
+        assert_eq!(pa.as_u64(), 0x21_2345);
+
+        let va = VirtAddr::from(0x401F_F000_u64);
+        let pa = mmu
+            .translate_addr(
+                &registers,
+                PrivilegeMode::Supervisor,
+                &mut memory,
+                va,
+                AccessMode::Read,
+            )
+            .unwrap();
+        assert_eq!(pa.as_u64(), 0x3F_F000);
+
+        // Flushing any address inside the superpage flushes the whole page.
+        memory.write(0x11 * 0x1000, pte(0x400, PteFlags::V | PteFlags::R));
+        mmu.flush_tlb(Some(VirtAddr::from(0x4000_0000_u64)), None);
+        let pa = mmu
+            .translate_addr(
+                &registers,
+                PrivilegeMode::Supervisor,
+                &mut memory,
+                va,
+                AccessMode::Read,
+            )
+            .unwrap();
+        assert_eq!(pa.as_u64(), 0x5F_F000);
+    }
+
+    #[test]
+    fn misaligned_superpage() {
+        let (mut mmu, registers, mut memory) = sv39_superpage(0x201);
+
+        let va = VirtAddr::from(0x4001_2345_u64);
+        let err = mmu
+            .translate_addr(
+                &registers,
+                PrivilegeMode::Supervisor,
+                &mut memory,
+                va,
+                AccessMode::Read,
+            )
+            .unwrap_err();
+        assert_eq!(err, Exception::LoadPageFault);
+    }
+
+    #[test]
+    fn permission_fault() {
+        let (mut mmu, registers, mut memory) = sv39_setup(1);
+        let va = VirtAddr::from(0x4000_1234_u64);
+
+        let err = mmu
+            .translate_addr(
+                &registers,
+                PrivilegeMode::Supervisor,
+                &mut memory,
+                va,
+                AccessMode::Write,
+            )
+            .unwrap_err();
+        assert_eq!(err, Exception::StorePageFault);
+
+        let err = mmu
+            .translate_addr(
+                &registers,
+                PrivilegeMode::Supervisor,
+                &mut memory,
+                VirtAddr::from(0x8000_0000_u64),
+                AccessMode::Read,
+            )
+            .unwrap_err();
+        assert_eq!(err, Exception::LoadPageFault);
+    }
+
+    #[test]
+    fn supervisor_user_memory() {
+        let (mut mmu, registers, mut memory) = sv39_setup(1);
+        memory.write(
+            0x12 * 0x1000 + 8,
+            pte(0x20, PteFlags::V | PteFlags::R | PteFlags::U),
+        );
+        let va = VirtAddr::from(0x4000_1234_u64);
+
+        let err = mmu
+            .translate_addr(
+                &registers,
+                PrivilegeMode::Supervisor,
+                &mut memory,
+                va,
+                AccessMode::Read,
+            )
+            .unwrap_err();
+        assert_eq!(err, Exception::LoadPageFault);
+
+        registers.mstatus().set_sum(true);
+        let pa = mmu
+            .translate_addr(
+                &registers,
+                PrivilegeMode::Supervisor,
+                &mut memory,
+                va,
+                AccessMode::Read,
+            )
+            .unwrap();
+        assert_eq!(pa.as_u64(), 0x20234);
+        let pa = mmu
+            .translate_addr(
+                &registers,
+                PrivilegeMode::User,
+                &mut memory,
+                va,
+                AccessMode::Read,
+            )
+            .unwrap();
+        assert_eq!(pa.as_u64(), 0x20234);
+    }
+
+    #[test]
+    fn make_executable_readable() {
+        let (mut mmu, registers, mut memory) = sv39_setup(1);
+        memory.write(0x12 * 0x1000 + 8, pte(0x20, PteFlags::V | PteFlags::X));
+        let va = VirtAddr::from(0x4000_1234_u64);
+
+        let err = mmu
+            .translate_addr(
+                &registers,
+                PrivilegeMode::Supervisor,
+                &mut memory,
+                va,
+                AccessMode::Read,
+            )
+            .unwrap_err();
+        assert_eq!(err, Exception::LoadPageFault);
+
+        registers.mstatus().set_mxr(true);
+        let pa = mmu
+            .translate_addr(
+                &registers,
+                PrivilegeMode::Supervisor,
+                &mut memory,
+                va,
+                AccessMode::Read,
+            )
+            .unwrap();
+        assert_eq!(pa.as_u64(), 0x20234);
+    }
+
+    #[test]
+    fn invalid_mode() {
+        let (mut mmu, registers, mut memory) = sv39_setup(1);
+        registers.write_csr(csr::SATP, 0);
+        registers.write_csr(csr::SATP, (0xF << 60) | ROOT_PPN);
+        assert_eq!(registers.read_csr(csr::SATP), 0);
+
+        let va = VirtAddr::from(0x4000_1234_u64);
+        let pa = mmu
+            .translate_addr(
+                &registers,
+                PrivilegeMode::Supervisor,
+                &mut memory,
+                va,
+                AccessMode::Read,
+            )
+            .unwrap();
+        assert_eq!(pa.as_u64(), 0x4000_1234);
+
+        assert!(matches!(
+            super::addressing_mode::<RV64I>(0xF),
+            super::AddressingMode::None
+        ));
+    }
+
     #[test]
     fn decode_32_satp_reg() {
-        // 0b1_100110111_1101110110101101110111u32;
-        let raw = 0xCDF7_6B77u32;
+        // 0b1_100110111_1101110110101101110111_u32;
+        let raw = 0xCDF7_6B77_u32;
 
         let (mode, asid, ppn) = super::decode_satp_reg::<crate::RV32I>(raw);
         assert_eq!(mode, 0x1);
         assert_eq!(asid, 0x137);
-        assert_eq!(ppn, 0x376B77);
+        assert_eq!(ppn, 0x0037_6B77);
     }
 
     #[test]
     fn decode_64_satp_reg() {
         // 0b1000_1001110001110011_10100111010001011010010101010100101110111011
-        let raw = 0x89C7_3A74_5A55_4BBBu64;
+        let raw = 0x89C7_3A74_5A55_4BBB_u64;
 
         let (mode, asid, ppn) = super::decode_satp_reg::<crate::RV64I>(raw);
         assert_eq!(mode, 0x8);
         assert_eq!(asid, 0x9C73);
         assert_eq!(ppn, 0xA74_5A55_4BBB);
     }
+
+    #[test]
+    fn page_offsets() {
+        let va = VirtAddr::new(0x1234_5678);
+        assert_eq!(va.as_u64(), 0x1234_5678);
+        assert_eq!(va.page_offset(), 0x678);
+        assert_eq!(va.page_number(), 0x12345);
+        assert_eq!(VirtAddr::new(PAGE_SIZE - 1).page_offset(), 0xFFF);
+        assert_eq!(VirtAddr::new(PAGE_SIZE).page_offset(), 0);
+        assert_eq!(VirtAddr::new(u64::MAX).page_number(), u64::MAX >> 12);
+
+        let pa = PhysAddr::new(0x8000_1FFF);
+        assert_eq!(pa.as_u64(), 0x8000_1FFF);
+        assert_eq!(pa.page_offset(), 0xFFF);
+        assert_eq!(PhysAddr::new(0x8000_2000).page_offset(), 0);
+    }
+
+    #[test]
+    fn sv32_ppn() {
+        let mut memory = Memory::<RV32I>::with_size(0x10_0000);
+        let pte = |ppn, flags| pte(ppn, flags) as u32;
+
+        // VPN[1] = 1, VPN[0] = 1, and the leaf uses both PPN fields,
+        // which results in a 34-bit physical address.
+        memory.write(ROOT_PPN as u32 * 0x1000 + 4, pte(0x11, PteFlags::V));
+        memory.write(0x11 * 0x1000 + 4, pte(0x30_0020, PteFlags::V | PteFlags::R));
+        // VPN[1] = 2 is a megapage.
+        memory.write(
+            ROOT_PPN as u32 * 0x1000 + 8,
+            pte(0x2A_5400, PteFlags::V | PteFlags::R),
+        );
+
+        let registers = Registers::<RV32I>::new();
+        registers.write_csr(csr::SATP, (1 << 31) | ROOT_PPN as u32);
+
+        let mut mmu = Mmu::<RV32I>::new();
+        let mut translate = |va: u32| {
+            mmu.translate_addr(
+                &registers,
+                PrivilegeMode::Supervisor,
+                &mut memory,
+                VirtAddr::from(va),
+                AccessMode::Read,
+            )
+            .map(PhysAddr::as_u64)
+        };
+        assert_eq!(translate(0x0040_1234), Ok(0x3_0002_0234));
+        assert_eq!(translate(0x0081_2345), Ok(0x2_A541_2345));
+    }
+
+    #[test]
+    fn sv39_ppn() {
+        let (mut mmu, registers, mut memory) = sv39_setup(0);
+
+        // All three PPN fields are used, and the bits above the PPN are ignored.
+        let raw = pte(0xFED_CBA9_8765, PteFlags::V | PteFlags::R) | 0xFFC0_0000_0000_0000;
+        memory.write(0x12 * 0x1000 + 8, raw);
+
+        let pa = mmu
+            .translate_addr(
+                &registers,
+                PrivilegeMode::Supervisor,
+                &mut memory,
+                VirtAddr::from(0x4000_1234_u64),
+                AccessMode::Read,
+            )
+            .unwrap();
+        assert_eq!(pa.as_u64(), 0xFE_DCBA_9876_5234);
+    }
+
+    #[test]
+    fn access_faults() {
+        assert_eq!(
+            AccessMode::Execute.page_fault(),
+            Exception::InstructionPageFault
+        );
+        assert_eq!(AccessMode::Read.page_fault(), Exception::LoadPageFault);
+        assert_eq!(AccessMode::Write.page_fault(), Exception::StorePageFault);
+
+        assert_eq!(
+            AccessMode::Execute.access_fault(),
+            Exception::InstructionAccessFault
+        );
+        assert_eq!(AccessMode::Read.access_fault(), Exception::LoadAccessFault);
+        assert_eq!(
+            AccessMode::Write.access_fault(),
+            Exception::StoreAccessFault
+        );
+    }
+
+    #[test]
+    fn non_canonical_address() {
+        let (mut mmu, registers, mut memory) = sv39_setup(1);
+        let mut translate = |va: u64| {
+            mmu.translate_addr(
+                &registers,
+                PrivilegeMode::Supervisor,
+                &mut memory,
+                VirtAddr::from(va),
+                AccessMode::Read,
+            )
+            .map(PhysAddr::as_u64)
+        };
+
+        assert_eq!(translate(0x4000_1234), Ok(0x20234));
+        // The upper bits are ignored by the walk, so this would alias `0x4000_1234`.
+        assert_eq!(
+            translate(0x8000_0000_4000_1234),
+            Err(Exception::LoadPageFault)
+        );
+
+        assert!(AddressingMode::SV39.is_canonical(VirtAddr::new(0xFFFF_FFC0_0000_0000)));
+        assert!(!AddressingMode::SV39.is_canonical(VirtAddr::new(0x0000_0040_0000_0000)));
+        assert!(AddressingMode::SV48.is_canonical(VirtAddr::new(0xFFFF_8000_0000_0000)));
+        assert!(!AddressingMode::SV48.is_canonical(VirtAddr::new(0x0000_8000_0000_0000)));
+        assert!(AddressingMode::SV32.is_canonical(VirtAddr::new(0x8000_0000)));
+    }
+
+    #[test]
+    fn page_table_outside_memory() {
+        let (mut mmu, registers, mut memory) = sv39_setup(0);
+        let va = VirtAddr::new(0x4000_1000);
+
+        // The root page table is right behind the end of the memory.
+        registers.write_csr(csr::SATP, (8 << 60) | 0x100);
+        let err = mmu
+            .translate_addr(
+                &registers,
+                PrivilegeMode::Supervisor,
+                &mut memory,
+                va,
+                AccessMode::Read,
+            )
+            .unwrap_err();
+        assert_eq!(err, Exception::LoadAccessFault);
+
+        // A non-leaf entry points outside of the memory.
+        registers.write_csr(csr::SATP, (8 << 60) | ROOT_PPN);
+        memory.write(0x11 * 0x1000, pte(0x10_0000, PteFlags::V));
+        let err = mmu
+            .translate_addr(
+                &registers,
+                PrivilegeMode::Supervisor,
+                &mut memory,
+                va,
+                AccessMode::Write,
+            )
+            .unwrap_err();
+        assert_eq!(err, Exception::StoreAccessFault);
+    }
+
+    #[test]
+    fn accessed_and_dirty_bits() {
+        let (mut mmu, registers, mut memory) = sv39_setup(0);
+        let leaf = 0x12 * 0x1000 + 8;
+        let flags = |memory: &Memory<RV64I>| PteFlags::from_bits_truncate(memory.read::<u8>(leaf));
+        memory.write(leaf, pte(0x20, PteFlags::V | PteFlags::R | PteFlags::W));
+        let va = VirtAddr::new(0x4000_1000);
+
+        // Accesses that fault don't update the PTE.
+        let err = mmu
+            .translate_addr(
+                &registers,
+                PrivilegeMode::Supervisor,
+                &mut memory,
+                va,
+                AccessMode::Execute,
+            )
+            .unwrap_err();
+        assert_eq!(err, Exception::InstructionPageFault);
+        assert_eq!(flags(&memory), PteFlags::V | PteFlags::R | PteFlags::W);
+
+        mmu.translate_addr(
+            &registers,
+            PrivilegeMode::Supervisor,
+            &mut memory,
+            va,
+            AccessMode::Read,
+        )
+        .unwrap();
+        assert_eq!(
+            flags(&memory),
+            PteFlags::V | PteFlags::R | PteFlags::W | PteFlags::A
+        );
+
+        // The cached translation is not dirty, so the write has to walk the page tables again.
+        mmu.translate_addr(
+            &registers,
+            PrivilegeMode::Supervisor,
+            &mut memory,
+            va,
+            AccessMode::Write,
+        )
+        .unwrap();
+        assert_eq!(
+            flags(&memory),
+            PteFlags::V | PteFlags::R | PteFlags::W | PteFlags::A | PteFlags::D
+        );
+    }
 }
@@ -0,0 +1,208 @@
+//! Systems with multiple harts, that share the same memory and devices.
+
+use crate::{
+    cpu::{Cpu, StepOutcome},
+    dtb,
+    memory::Memory,
+    trap::Exception,
+    Base,
+};
+use std::mem;
+
+/// A single hardware thread of a [`System`].
+///
+/// Every hart is a complete [`Cpu`], with it's own registers, privilege mode,
+/// and `mhartid`.
+pub type Hart<B> = Cpu<B>;
+
+/// A multi-core system, that consists of multiple harts, which share
+/// the same memory and devices.
+///
+/// The harts are numbered starting at `0`, which is also their `mhartid`.
+/// Inter-processor interrupts are sent by writing to the `msip` register of the
+/// other hart inside a [`Clint`](crate::device::clint::Clint).
+///
+/// The memory is owned by the system, and only lent to a hart while it executes
+/// an instruction, so the [memory](Cpu::memory) of a hart is empty while it's
+/// part of a system. A store of one hart clears the reservations of all harts,
+/// that contain the written address.
+pub struct System<B: Base> {
+    harts: Vec<Hart<B>>,
+    memory: Memory<B>,
+}
+
+impl<B: Base> System<B> {
+    /// Creates a new system with the given number of harts, which all
+    /// start executing the program inside `memory` at address `0`.
+    pub fn new(memory: Memory<B>, harts: usize) -> Self {
+        let harts = (0..harts)
+            .map(|id| {
+                let hart = Hart::new(Memory::with_size(0));
+                hart.registers().set_hart_id(id as u64);
+                hart
+            })
+            .collect();
+
+        Self { harts, memory }
+    }
+
+    /// Returns all harts of this system, ordered by their `mhartid`.
+    pub fn harts(&self) -> &[Hart<B>] {
+        &self.harts
+    }
+
+    /// Returns mutable references to all harts of this system, ordered by their `mhartid`.
+    pub fn harts_mut(&mut self) -> &mut [Hart<B>] {
+        &mut self.harts
+    }
+
+    /// Returns a reference to the memory, that is shared by all harts.
+    pub fn memory(&self) -> &Memory<B> {
+        &self.memory
+    }
+
+    /// Returns a mutable reference to the memory, that is shared by all harts.
+    pub fn memory_mut(&mut self) -> &mut Memory<B> {
+        &mut self.memory
+    }
+
+    /// Writes a [device tree](crate::dtb) of this system to `addr`, and passes it
+    /// to every hart. See [`Cpu::load_device_tree`].
+    ///
+    /// The ISA of every hart is described by the extensions of the first hart.
+    ///
+    /// ## Errors
+    ///
+    /// Returns an exception if the device tree doesn't fit into the memory at `addr`.
+    pub fn load_device_tree(&mut self, addr: B::Addr) -> Result<(), Exception> {
+        let extensions = self.harts.first().map(Cpu::extensions).unwrap_or_default();
+        let fdt = dtb::generate(extensions, &self.memory, self.harts.len());
+        self.memory.write_bytes(addr, &fdt)?;
+        for hart in &self.harts {
+            hart.registers().set_boot_arguments(addr);
+        }
+        Ok(())
+    }
+
+    /// Executes a single [step](Cpu::step) on every hart, in the order of their `mhartid`.
+    ///
+    /// Returns the outcome of the step of every hart.
+    pub fn step_all(&mut self) -> Vec<Result<StepOutcome, Exception>> {
+        let mut outcomes = Vec::with_capacity(self.harts.len());
+
+        for idx in 0..self.harts.len() {
+            let hart = &mut self.harts[idx];
+            mem::swap(&mut self.memory, hart.memory_mut());
+            outcomes.push(hart.step());
+            mem::swap(&mut self.memory, hart.memory_mut());
+
+            if let Some(stored) = hart.take_stored() {
+                for other in &mut self.harts {
+                    other.invalidate_reservation(stored);
+                }
+            }
+        }
+
+        outcomes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::System;
+    use crate::{
+        cpu::{csr, StepOutcome, XRegister},
+        device::clint::Clint,
+        memory::Memory,
+        trap::Interrupt,
+        RV64I,
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn inter_processor_interrupt() {
+        let program: &[u32] = &[
+            0xF140_2573, // csrr a0, mhartid
+            0x0005_1A63, // bnez a0, 20
+            // Hart 0 sends an IPI to hart 1.
+            0x0200_02B7, // lui t0, 0x2000
+            0x0010_0313, // li t1, 1
+            0x0062_A223, // sw t1, 4(t0)
+            0x0000_006F, // j .
+            // Hart 1 enables the software interrupt.
+            0x0080_0293, // li t0, 8
+            0x3042_9073, // csrw mie, t0
+            0x3004_6073, // csrsi mstatus, 8
+            0x1050_0073, // wfi
+        ];
+
+        let mut memory = Memory::<RV64I>::with_size(0x1000);
+        for (idx, inst) in program.iter().enumerate() {
+            memory.write(idx as u64 * 4, *inst);
+        }
+        memory.map_device(0x0200_0000, Rc::new(RefCell::new(Clint::with_harts(2))));
+
+        let mut system = System::new(memory, 2);
+        assert_eq!(system.harts()[1].registers().read_csr(csr::MHARTID), 1);
+
+        for _ in 0..5 {
+            let outcomes = system.step_all();
+            assert_eq!(outcomes, vec![Ok(StepOutcome::Retired); 2]);
+        }
+
+        let outcomes = system.step_all();
+        assert_eq!(outcomes[0], Ok(StepOutcome::Retired));
+        assert_eq!(
+            outcomes[1],
+            Ok(StepOutcome::TrapTaken(Interrupt::MachineSoftware.into()))
+        );
+        assert_eq!(system.harts()[0].registers().read_csr(csr::MIP), 0);
+    }
+
+    #[test]
+    fn load_reserved() {
+        let program: &[u32] = &[
+            0x1005_22AF, // lr.w t0, (a0)
+            0x18B5_232F, // sc.w t1, a1, (a0)
+            0x0000_006F, // j .
+        ];
+
+        let mut memory = Memory::<RV64I>::with_size(0x1000);
+        for (idx, inst) in program.iter().enumerate() {
+            memory.write(idx as u64 * 4, *inst);
+        }
+
+        let mut system = System::new(memory, 2);
+        for (id, hart) in system.harts().iter().enumerate() {
+            hart.registers().write_x(XRegister::from(10), 0x100);
+            hart.registers().write_x(XRegister::from(11), id as u64 + 1);
+        }
+
+        // Both harts reserve the same word, but only the first `sc.w` succeeds,
+        // because it invalidates the reservation of the other hart.
+        for _ in 0..2 {
+            system.step_all();
+        }
+        let sc = |id: usize| system.harts()[id].registers().read_x(XRegister::from(6));
+        assert_eq!(sc(0), 0);
+        assert_eq!(sc(1), 1);
+        assert_eq!(system.memory().read::<u32>(0x100), 1);
+    }
+
+    #[test]
+    fn device_tree() {
+        let mut system = System::new(Memory::<RV64I>::with_size(0x1000), 2);
+        system.load_device_tree(0x800).unwrap();
+
+        assert_eq!(
+            system.memory().read_bytes(0x800, 4),
+            Ok(vec![0xD0, 0x0D, 0xFE, 0xED])
+        );
+        for (id, hart) in system.harts().iter().enumerate() {
+            assert_eq!(hart.registers().read_x(XRegister::from(10)), id as u64);
+            assert_eq!(hart.registers().read_x(XRegister::from(11)), 0x800);
+        }
+
+        assert!(system.load_device_tree(0xF00).is_err());
+    }
+}
@@ -2,41 +2,222 @@
 //!
 //! See section 3.1.16 in the Volume 2 (Priviliged) Specification.
 
+use crate::Base;
+use derive_more::From;
+
+/// Either an interrupt or an exception, that causes the CPU to trap.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, From)]
+pub enum Trap {
+    Interrupt(Interrupt),
+    Exception(Exception),
+}
+
 /// All different interrupt kinds.
+///
+/// The discriminant of each variant is the exception code,
+/// that is written into the `xcause` register.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Interrupt {
     // Software interrupts
-    UserSoftware,
-    SupervisorSoftware,
-    MachineSoftware,
+    UserSoftware = 0,
+    SupervisorSoftware = 1,
+    MachineSoftware = 3,
 
     // Timers
-    UserTimer,
-    SupervisorTimer,
-    MachineTimer,
+    UserTimer = 4,
+    SupervisorTimer = 5,
+    MachineTimer = 7,
 
     // External interrupts
-    UserExternal,
-    SupervisorExternal,
-    MachineExternal,
+    UserExternal = 8,
+    SupervisorExternal = 9,
+    MachineExternal = 11,
 }
 
 /// All different exception kinds.
+///
+/// The discriminant of each variant is the exception code,
+/// that is written into the `xcause` register.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Exception {
-    InstructionAddressMisaligned,
-    InstructionAccessFault,
-    IllegalInstruction,
-    Breakpoint,
-    LoadAddressMisaligned,
-    LoadAccessFault,
-    StoreAddressMisaligned,
-    StoreAccessFault,
-    UserModeEnvironmentCall,
-    SupervisorModeEnvironmentCall,
-    MachineModeEnvironmentCall,
-    InstructionPageFault,
-    LoadPageFault,
-    StorePageFault,
-    Reserved,
+    InstructionAddressMisaligned = 0,
+    InstructionAccessFault = 1,
+    IllegalInstruction = 2,
+    Breakpoint = 3,
+    LoadAddressMisaligned = 4,
+    LoadAccessFault = 5,
+    StoreAddressMisaligned = 6,
+    StoreAccessFault = 7,
+    UserModeEnvironmentCall = 8,
+    SupervisorModeEnvironmentCall = 9,
+    Reserved = 10,
+    MachineModeEnvironmentCall = 11,
+    InstructionPageFault = 12,
+    LoadPageFault = 13,
+    StorePageFault = 15,
+}
+
+impl Trap {
+    /// Returns `true` if this trap is an interrupt.
+    pub fn is_interrupt(self) -> bool {
+        matches!(self, Trap::Interrupt(_))
+    }
+
+    /// Returns the exception code of this trap, without the interrupt bit.
+    pub fn code(self) -> u64 {
+        match self {
+            Trap::Interrupt(int) => int.code(),
+            Trap::Exception(ex) => ex.code(),
+        }
+    }
+
+    /// Returns the value that is written into the `xcause` register.
+    ///
+    /// For interrupts, the most significant bit of the `XLEN`-bit
+    /// register is set, in addition to the exception code.
+    pub fn cause<B: Base>(self) -> u64 {
+        (self.is_interrupt() as u64) << (B::XLEN - 1) | self.code()
+    }
+}
+
+impl Interrupt {
+    /// Returns the exception code of this interrupt, without the interrupt bit.
+    pub fn code(self) -> u64 {
+        self as u64
+    }
+
+    /// Converts the given exception code back into an interrupt.
+    ///
+    /// Returns `None` if `is_interrupt` is `false`, or the code is not
+    /// a known interrupt.
+    pub fn from_code(is_interrupt: bool, code: u64) -> Option<Self> {
+        if !is_interrupt {
+            return None;
+        }
+
+        Some(match code {
+            0 => Interrupt::UserSoftware,
+            1 => Interrupt::SupervisorSoftware,
+            3 => Interrupt::MachineSoftware,
+            4 => Interrupt::UserTimer,
+            5 => Interrupt::SupervisorTimer,
+            7 => Interrupt::MachineTimer,
+            8 => Interrupt::UserExternal,
+            9 => Interrupt::SupervisorExternal,
+            11 => Interrupt::MachineExternal,
+            _ => return None,
+        })
+    }
+}
+
+impl Exception {
+    /// Returns the exception code of this exception.
+    pub fn code(self) -> u64 {
+        self as u64
+    }
+
+    /// Converts the given exception code back into an exception.
+    ///
+    /// Returns `None` if `is_interrupt` is `true`, or the code is not
+    /// a known exception. The reserved codes `10` and `14` are converted into
+    /// [`Exception::Reserved`].
+    pub fn from_code(is_interrupt: bool, code: u64) -> Option<Self> {
+        if is_interrupt {
+            return None;
+        }
+
+        Some(match code {
+            0 => Exception::InstructionAddressMisaligned,
+            1 => Exception::InstructionAccessFault,
+            2 => Exception::IllegalInstruction,
+            3 => Exception::Breakpoint,
+            4 => Exception::LoadAddressMisaligned,
+            5 => Exception::LoadAccessFault,
+            6 => Exception::StoreAddressMisaligned,
+            7 => Exception::StoreAccessFault,
+            8 => Exception::UserModeEnvironmentCall,
+            9 => Exception::SupervisorModeEnvironmentCall,
+            10 | 14 => Exception::Reserved,
+            11 => Exception::MachineModeEnvironmentCall,
+            12 => Exception::InstructionPageFault,
+            13 => Exception::LoadPageFault,
+            15 => Exception::StorePageFault,
+            _ => return None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Exception, Interrupt, Trap};
+    use crate::{RV32I, RV64I};
+
+    #[test]
+    fn interrupt_codes() {
+        let interrupts = [
+            (Interrupt::UserSoftware, 0),
+            (Interrupt::SupervisorSoftware, 1),
+            (Interrupt::MachineSoftware, 3),
+            (Interrupt::UserTimer, 4),
+            (Interrupt::SupervisorTimer, 5),
+            (Interrupt::MachineTimer, 7),
+            (Interrupt::UserExternal, 8),
+            (Interrupt::SupervisorExternal, 9),
+            (Interrupt::MachineExternal, 11),
+        ];
+
+        for &(int, code) in &interrupts {
+            assert_eq!(int.code(), code);
+            assert_eq!(Interrupt::from_code(true, code), Some(int));
+            assert_eq!(Interrupt::from_code(false, code), None);
+        }
+
+        assert_eq!(Interrupt::from_code(true, 2), None);
+        assert_eq!(Interrupt::from_code(true, 16), None);
+    }
+
+    #[test]
+    fn exception_codes() {
+        let exceptions = [
+            (Exception::InstructionAddressMisaligned, 0),
+            (Exception::InstructionAccessFault, 1),
+            (Exception::IllegalInstruction, 2),
+            (Exception::Breakpoint, 3),
+            (Exception::LoadAddressMisaligned, 4),
+            (Exception::LoadAccessFault, 5),
+            (Exception::StoreAddressMisaligned, 6),
+            (Exception::StoreAccessFault, 7),
+            (Exception::UserModeEnvironmentCall, 8),
+            (Exception::SupervisorModeEnvironmentCall, 9),
+            (Exception::Reserved, 10),
+            (Exception::MachineModeEnvironmentCall, 11),
+            (Exception::InstructionPageFault, 12),
+            (Exception::LoadPageFault, 13),
+            (Exception::StorePageFault, 15),
+        ];
+
+        for &(ex, code) in &exceptions {
+            assert_eq!(ex.code(), code);
+            assert_eq!(Exception::from_code(false, code), Some(ex));
+            assert_eq!(Exception::from_code(true, code), None);
+        }
+
+        assert_eq!(Exception::from_code(false, 14), Some(Exception::Reserved));
+        assert_eq!(Exception::from_code(false, 16), None);
+    }
+
+    #[test]
+    fn cause() {
+        let timer = Trap::from(Interrupt::MachineTimer);
+        assert!(timer.is_interrupt());
+        assert_eq!(timer.code(), 7);
+        assert_eq!(timer.cause::<RV32I>(), 0x8000_0007);
+        assert_eq!(timer.cause::<RV64I>(), 0x8000_0000_0000_0007);
+
+        let ecall = Trap::from(Exception::MachineModeEnvironmentCall);
+        assert!(!ecall.is_interrupt());
+        assert_eq!(ecall.code(), 11);
+        assert_eq!(ecall.cause::<RV32I>(), 11);
+        assert_eq!(ecall.cause::<RV64I>(), 11);
+    }
 }
@@ -0,0 +1,126 @@
+//! Runs the `rv32ui-p-*` and `rv64ui-p-*` tests of the official
+//! [riscv-tests](https://github.com/riscv/riscv-tests) suite.
+//!
+//! The test binaries are not part of the repository. To run them, build the
+//! suite, and copy the resulting ELF files of the `rv32ui` and `rv64ui` tests
+//! into `tests/fixtures/riscv-tests`. If there are no test binaries, the tests
+//! are skipped.
+//!
+//! A test reports it's result using the `tohost` protocol, which is implemented
+//! by the [`Htif`] device, that is mapped at the address of the `tohost` symbol.
+
+use rivenu::{
+    cpu::{BareCpu, CpuConfig, HaltReason, RunOutcome},
+    device::htif::Htif,
+    Base, RV32I, RV64I,
+};
+use std::{
+    cell::RefCell,
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/riscv-tests");
+
+/// The size of the RAM, which starts at the lowest address of the test binary.
+const MEMORY_SIZE: usize = 0x10_0000;
+
+/// The maximum number of steps, before a test is considered to be stuck.
+const MAX_STEPS: u64 = 1_000_000;
+
+/// Returns all test binaries inside the fixtures directory, whose name starts with `prefix`.
+fn binaries(prefix: &str) -> Vec<PathBuf> {
+    let mut paths = fs::read_dir(FIXTURES)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .map_or(false, |name| {
+                    name.starts_with(prefix) && !name.ends_with(".dump")
+                })
+        })
+        .collect::<Vec<_>>();
+    paths.sort();
+    paths
+}
+
+/// Looks up the address of the `tohost` symbol.
+fn tohost(path: &Path) -> Result<u64, String> {
+    let file = elf::File::open_path(path).map_err(|err| format!("{:?}", err))?;
+    let symtab = file
+        .get_section(".symtab")
+        .ok_or("binary doesn't contain a symbol table")?;
+
+    file.get_symbols(symtab)
+        .map_err(|err| format!("{:?}", err))?
+        .into_iter()
+        .find(|sym| sym.name == "tohost")
+        .map(|sym| sym.value)
+        .ok_or_else(|| "binary doesn't contain a `tohost` symbol".to_string())
+}
+
+/// Runs a single test binary, until it writes it's result into `tohost`.
+fn run<B: Base>(path: &Path) -> Result<(), String> {
+    let tohost = tohost(path)?;
+    let config = CpuConfig::<B>::builder()
+        .elf(path)
+        .map_err(|err| err.to_string())?
+        .memory_size(MEMORY_SIZE)
+        .init_sp(false)
+        .build();
+    let mut cpu = BareCpu::from_config(config).map_err(|err| err.to_string())?;
+    cpu.set_misaligned_access(true);
+    cpu.memory_mut()
+        .map_device(tohost, Rc::new(RefCell::new(Htif::new())));
+
+    // The test environment handles all traps, including
+    // the `ECALL` that is used to report the result.
+    match cpu.run_until_halt(MAX_STEPS) {
+        Some(RunOutcome::Halted(HaltReason::Exit(0))) => Ok(()),
+        Some(RunOutcome::Halted(HaltReason::Exit(test))) => {
+            Err(format!("test case {} failed", test))
+        }
+        Some(RunOutcome::WaitingForInterrupt) => Err("waiting for an interrupt".to_string()),
+        Some(outcome) => Err(format!("unexpected stop: {:?}", outcome)),
+        None => Err(format!("didn't finish after {} steps", MAX_STEPS)),
+    }
+}
+
+/// Runs all test binaries, whose name starts with `prefix`,
+/// and panics if any of them failed.
+fn run_suite<B: Base>(prefix: &str) {
+    let paths = binaries(prefix);
+    if paths.is_empty() {
+        eprintln!("skipping `{}*` tests, no binaries in {}", prefix, FIXTURES);
+        return;
+    }
+
+    let failures = paths
+        .iter()
+        .filter_map(|path| {
+            run::<B>(path)
+                .err()
+                .map(|err| format!("{}: {}", path.display(), err))
+        })
+        .collect::<Vec<_>>();
+    assert!(
+        failures.is_empty(),
+        "{} of {} tests failed:\n{}",
+        failures.len(),
+        paths.len(),
+        failures.join("\n")
+    );
+}
+
+#[test]
+fn rv32ui() {
+    run_suite::<RV32I>("rv32ui-p-");
+}
+
+#[test]
+fn rv64ui() {
+    run_suite::<RV64I>("rv64ui-p-");
+}